@@ -0,0 +1,90 @@
+//! Backing off from sending requests which failed.
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc, time::Duration};
+
+use js_sys::{Date, Function};
+use wasm_bindgen::prelude::*;
+
+use crate::{requests::RequestType, responses::RequestFailure};
+
+/// The delay before retrying a request which failed once, unless the server
+/// says otherwise. It doubles with each consecutive failure.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest delay before retrying a request, unless the server says
+/// otherwise.
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// The consecutive failures of the requests of one type.
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+
+    /// When the requests can be sent again, in milliseconds since the epoch.
+    retry_at_ms: f64,
+}
+
+/// The state of the backoff for each type of request, shared between the
+/// clones of an `OlmMachine`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestBackoff {
+    failures: Rc<RefCell<BTreeMap<RequestType, Failures>>>,
+
+    /// The callback registered with `OlmMachine.registerRequestFailedCallback`.
+    callback: Rc<RefCell<Option<Function>>>,
+}
+
+impl RequestBackoff {
+    /// Record that a request of the given type failed, and work out how long
+    /// to wait before sending requests of this type again.
+    ///
+    /// `server_delay` is the delay asked for by the server, if any, which
+    /// takes precedence over the exponential backoff.
+    pub(crate) fn record_failure(
+        &self,
+        request_type: RequestType,
+        server_delay: Option<Duration>,
+    ) -> Duration {
+        let mut failures = self.failures.borrow_mut();
+        let failures =
+            failures.entry(request_type).or_insert(Failures { count: 0, retry_at_ms: 0. });
+
+        failures.count = failures.count.saturating_add(1);
+
+        let delay = server_delay.unwrap_or_else(|| {
+            INITIAL_DELAY.saturating_mul(2u32.saturating_pow(failures.count - 1)).min(MAX_DELAY)
+        });
+        failures.retry_at_ms = Date::now() + delay.as_millis() as f64;
+
+        delay
+    }
+
+    /// Record that a request of the given type succeeded, so that the next
+    /// failure starts the backoff afresh.
+    pub(crate) fn record_success(&self, request_type: RequestType) {
+        self.failures.borrow_mut().remove(&request_type);
+    }
+
+    /// Whether requests of the given type should not be sent yet.
+    pub(crate) fn is_backing_off(&self, request_type: RequestType) -> bool {
+        self.failures
+            .borrow()
+            .get(&request_type)
+            .is_some_and(|failures| failures.retry_at_ms > Date::now())
+    }
+
+    pub(crate) fn set_callback(&self, callback: Function) {
+        *self.callback.borrow_mut() = Some(callback);
+    }
+
+    /// Call the registered callback, if any, with the given failure.
+    pub(crate) fn notify(&self, failure: &RequestFailure) {
+        // The callback is cloned out of the cell, so that it can register another
+        // callback without panicking.
+        let Some(callback) = self.callback.borrow().clone() else { return };
+
+        if let Err(e) = callback.call1(&JsValue::NULL, &failure.clone().into()) {
+            tracing::warn!("Error calling request failed callback: {:?}", e);
+        }
+    }
+}
@@ -82,7 +82,7 @@ impl BackupDecryptionKey {
 }
 
 /// Struct holding the number of room keys we have.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[wasm_bindgen]
 pub struct RoomKeyCounts {
     /// The total number of room keys.
@@ -8,7 +8,8 @@ use crate::{
     encryption::EncryptionAlgorithm,
     future::future_to_promise,
     identifiers::{self, DeviceId, UserId},
-    impl_from_to_inner, requests, types, verification, vodozemac,
+    machine::ReadOnlyError,
+    requests, types, verification, vodozemac,
 };
 
 /// A device represents a E2EE capable client of an user.
@@ -16,9 +17,17 @@ use crate::{
 #[derive(Debug)]
 pub struct Device {
     pub(crate) inner: matrix_sdk_crypto::Device,
+
+    /// Whether the device was obtained from a read-only `OlmMachine`, in
+    /// which case the methods which would modify its state throw.
+    pub(crate) read_only: bool,
 }
 
-impl_from_to_inner!(matrix_sdk_crypto::Device => Device);
+impl From<matrix_sdk_crypto::Device> for Device {
+    fn from(inner: matrix_sdk_crypto::Device) -> Self {
+        Self { inner, read_only: false }
+    }
+}
 
 #[wasm_bindgen]
 impl Device {
@@ -30,6 +39,8 @@ impl Device {
         &self,
         methods: Option<Vec<verification::VerificationMethod>>,
     ) -> Result<Array, JsError> {
+        ReadOnlyError::check(self.read_only)?;
+
         let me = self.inner.clone();
         let methods = methods.map(|methods| methods.iter().map(Into::into).collect());
 
@@ -72,6 +83,8 @@ impl Device {
         event_type: String,
         content: JsValue,
     ) -> Result<String, JsError> {
+        ReadOnlyError::check(self.read_only)?;
+
         let me = self.inner.clone();
 
         // JSON-serialize the payload
@@ -113,8 +126,10 @@ impl Device {
     #[wasm_bindgen(js_name = "setLocalTrust")]
     pub fn set_local_trust(&self, local_state: LocalTrust) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             me.set_local_trust(local_state.into()).await?;
 
             Ok(JsValue::NULL)
@@ -233,8 +248,10 @@ impl Device {
     /// Returns a signature upload request that needs to be sent out.
     pub fn verify(&self) -> Promise {
         let device = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             Ok(requests::SignatureUploadRequest::try_from(&device.verify().await?)?)
         })
     }
@@ -294,15 +311,23 @@ impl From<LocalTrust> for matrix_sdk_crypto::LocalTrust {
 #[derive(Debug)]
 pub struct UserDevices {
     pub(crate) inner: matrix_sdk_crypto::UserDevices,
+
+    /// Whether the devices were obtained from a read-only `OlmMachine`. See
+    /// [`Device::read_only`].
+    pub(crate) read_only: bool,
 }
 
-impl_from_to_inner!(matrix_sdk_crypto::UserDevices => UserDevices);
+impl From<matrix_sdk_crypto::UserDevices> for UserDevices {
+    fn from(inner: matrix_sdk_crypto::UserDevices) -> Self {
+        Self { inner, read_only: false }
+    }
+}
 
 #[wasm_bindgen]
 impl UserDevices {
     /// Get the specific device with the given device ID.
     pub fn get(&self, device_id: &DeviceId) -> Option<Device> {
-        self.inner.get(&device_id.inner).map(Into::into)
+        self.inner.get(&device_id.inner).map(|inner| Device { inner, read_only: self.read_only })
     }
 
     /// Returns true if there is at least one devices of this user
@@ -322,6 +347,10 @@ impl UserDevices {
 
     /// Iterator over all the devices of the user devices.
     pub fn devices(&self) -> Array {
-        self.inner.devices().map(Device::from).map(JsValue::from).collect()
+        self.inner
+            .devices()
+            .map(|inner| Device { inner, read_only: self.read_only })
+            .map(JsValue::from)
+            .collect()
     }
 }
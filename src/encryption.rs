@@ -75,6 +75,18 @@ impl From<&EncryptionSettings> for matrix_sdk_crypto::olm::EncryptionSettings {
     }
 }
 
+impl From<&matrix_sdk_crypto::olm::EncryptionSettings> for EncryptionSettings {
+    fn from(value: &matrix_sdk_crypto::olm::EncryptionSettings) -> Self {
+        Self {
+            algorithm: value.algorithm.clone().into(),
+            rotation_period: value.rotation_period.as_micros().try_into().unwrap_or(u64::MAX),
+            rotation_period_messages: value.rotation_period_msgs,
+            history_visibility: value.history_visibility.clone().into(),
+            sharing_strategy: value.sharing_strategy.clone().into(),
+        }
+    }
+}
+
 /// An encryption algorithm to be used to encrypt messages sent to a
 /// room.
 #[wasm_bindgen]
@@ -1,4 +1,4 @@
-//! Errors related to room event decryption.
+//! Errors related to room event decryption and signature verification.
 
 use js_sys::JsString;
 use matrix_sdk_common::deserialized_responses::VerificationLevel;
@@ -104,3 +104,18 @@ impl From<MegolmError> for MegolmDecryptionError {
         }
     }
 }
+
+/// Error thrown when an Ed25519 signature could not be verified.
+#[derive(Debug)]
+#[wasm_bindgen(getter_with_clone)]
+pub struct SignatureVerificationError {
+    /// Detailed description of why the verification failed.
+    #[wasm_bindgen(readonly)]
+    pub description: JsString,
+}
+
+impl From<vodozemac::SignatureError> for SignatureVerificationError {
+    fn from(value: vodozemac::SignatureError) -> Self {
+        Self { description: value.to_string().into() }
+    }
+}
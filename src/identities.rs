@@ -1,27 +1,33 @@
 //! User identities.
 
-use js_sys::{Array, Promise};
+use js_sys::{Array, Object, Promise};
 use wasm_bindgen::prelude::*;
 
 use crate::{
     future::future_to_promise,
-    identifiers, impl_from_to_inner, requests,
+    identifiers, json,
+    machine::ReadOnlyError,
+    requests,
     verification::{self, VerificationRequest},
 };
 
 pub(crate) struct UserIdentity {
-    inner: matrix_sdk_crypto::UserIdentity,
-}
+    pub(crate) inner: matrix_sdk_crypto::UserIdentity,
 
-impl_from_to_inner!(matrix_sdk_crypto::UserIdentity => UserIdentity);
+    /// Whether the identity was obtained from a read-only `OlmMachine`, in
+    /// which case the methods which would modify its state throw.
+    pub(crate) read_only: bool,
+}
 
 impl From<UserIdentity> for JsValue {
     fn from(user_identities: UserIdentity) -> Self {
         use matrix_sdk_crypto::UserIdentity::*;
 
+        let read_only = user_identities.read_only;
+
         match user_identities.inner {
-            Own(own) => JsValue::from(OwnUserIdentity::from(own)),
-            Other(other) => JsValue::from(OtherUserIdentity::from(other)),
+            Own(inner) => JsValue::from(OwnUserIdentity { inner, read_only }),
+            Other(inner) => JsValue::from(OtherUserIdentity { inner, read_only }),
         }
     }
 }
@@ -33,10 +39,9 @@ impl From<UserIdentity> for JsValue {
 #[derive(Debug)]
 pub struct OwnUserIdentity {
     inner: matrix_sdk_crypto::OwnUserIdentity,
+    read_only: bool,
 }
 
-impl_from_to_inner!(matrix_sdk_crypto::OwnUserIdentity => OwnUserIdentity);
-
 #[wasm_bindgen]
 impl OwnUserIdentity {
     /// Is this user identity verified?
@@ -53,8 +58,10 @@ impl OwnUserIdentity {
     /// Returns a signature upload request that needs to be sent out.
     pub fn verify(&self) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             Ok(requests::SignatureUploadRequest::try_from(&me.verify().await?)?)
         })
     }
@@ -65,6 +72,8 @@ impl OwnUserIdentity {
         &self,
         methods: Option<Vec<verification::VerificationMethod>>,
     ) -> Result<Promise, JsError> {
+        ReadOnlyError::check(self.read_only)?;
+
         let methods = methods.map(|methods| methods.iter().map(Into::into).collect());
         let me = self.inner.clone();
 
@@ -133,8 +142,10 @@ impl OwnUserIdentity {
     #[wasm_bindgen(js_name = "withdrawVerification")]
     pub fn withdraw_verification(&self) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             let _ = &me.withdraw_verification().await?;
             Ok(JsValue::undefined())
         })
@@ -166,10 +177,9 @@ impl OwnUserIdentity {
 #[derive(Debug)]
 pub struct OtherUserIdentity {
     inner: matrix_sdk_crypto::OtherUserIdentity,
+    read_only: bool,
 }
 
-impl_from_to_inner!(matrix_sdk_crypto::OtherUserIdentity => OtherUserIdentity);
-
 #[wasm_bindgen]
 impl OtherUserIdentity {
     /// Is this user identity verified?
@@ -190,8 +200,10 @@ impl OtherUserIdentity {
     /// verified.
     pub fn verify(&self) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             Ok(requests::SignatureUploadRequest::try_from(&me.verify().await?)?)
         })
     }
@@ -205,6 +217,8 @@ impl OtherUserIdentity {
         request_event_id: &identifiers::EventId,
         methods: Option<Vec<verification::VerificationMethod>>,
     ) -> Result<VerificationRequest, JsError> {
+        ReadOnlyError::check(self.read_only)?;
+
         let me = self.inner.clone();
         let room_id = room_id.inner.clone();
         let request_event_id = request_event_id.inner.clone();
@@ -231,6 +245,20 @@ impl OtherUserIdentity {
         Ok(serde_json::to_string(&me.verification_request_content(methods))?)
     }
 
+    /// Like {@link verificationRequestContent}, but returns the content as a
+    /// plain object, as accepted by {@link OlmMachine.encryptRoomEvent}.
+    #[wasm_bindgen(js_name = "verificationRequestContentObject")]
+    pub fn verification_request_content_object(
+        &self,
+        methods: Option<Vec<verification::VerificationMethod>>,
+    ) -> Result<Object, JsError> {
+        let me = self.inner.clone();
+        let methods = methods.map(|methods| methods.iter().map(Into::into).collect());
+
+        Ok(json::to_string_or_object(&me.verification_request_content(methods), true)?
+            .unchecked_into())
+    }
+
     /// Get the master key of the identity.
     #[wasm_bindgen(getter, js_name = "masterKey")]
     pub fn master_key(&self) -> Result<String, JsError> {
@@ -249,8 +277,10 @@ impl OtherUserIdentity {
     #[wasm_bindgen(js_name = "pinCurrentMasterKey")]
     pub fn pin_current_master_key(&self) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             let _ = &me.pin_current_master_key().await?;
             Ok(JsValue::undefined())
         })
@@ -289,8 +319,10 @@ impl OtherUserIdentity {
     #[wasm_bindgen(js_name = "withdrawVerification")]
     pub fn withdraw_verification(&self) -> Promise {
         let me = self.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
+            ReadOnlyError::check(read_only)?;
             let _ = &me.withdraw_verification().await?;
             Ok(JsValue::undefined())
         })
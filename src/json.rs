@@ -0,0 +1,77 @@
+//! Helpers for the methods which take and return JSON, either encoded as
+//! strings or as plain JS objects.
+//!
+//! Plain objects are converted with `serde-wasm-bindgen`, which saves the JS
+//! side from encoding them, and this side from parsing them back. Events are
+//! the exception: the crypto crate needs their raw JSON, to check their
+//! signatures or decrypt them, and `serde-wasm-bindgen` cannot produce nor
+//! read raw JSON. Events given as objects are thus encoded with the native
+//! `JSON.stringify`, and returned as objects with the native `JSON.parse`.
+//! The same goes for the responses to outgoing requests, which are parsed
+//! from bytes: passing objects saves no work for those, it is only a
+//! convenience.
+
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Deserialize a value passed from JS, which is either a JSON-encoded string
+/// or a plain JS object.
+///
+/// `T` must not hold raw JSON: see [`raw_from_string_or_object`] for those.
+pub(crate) fn from_string_or_object<T: DeserializeOwned>(value: JsValue) -> Result<T, JsError> {
+    match value.as_string() {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => Ok(serde_wasm_bindgen::from_value(value)?),
+    }
+}
+
+/// Deserialize a value passed from JS, which is either a JSON-encoded string
+/// or a plain JS object, into a type holding raw JSON, such as `Raw<T>` or
+/// the event enums of Ruma.
+pub(crate) fn raw_from_string_or_object<T: DeserializeOwned>(value: JsValue) -> Result<T, JsError> {
+    Ok(serde_json::from_str(&string_from_string_or_object(value)?)?)
+}
+
+/// The JSON encoding of a value passed from JS, which is either a
+/// JSON-encoded string or a plain JS object.
+pub(crate) fn string_from_string_or_object(value: JsValue) -> Result<String, JsError> {
+    match value.as_string() {
+        Some(json) => Ok(json),
+        None => js_sys::JSON::stringify(&value)
+            .map(String::from)
+            .map_err(|_| JsError::new("Expected a JSON-encoded string or a plain object")),
+    }
+}
+
+/// Serialize a value to be returned to JS: as a JSON-encoded string if
+/// `as_object` is `false`, or as a plain JS object otherwise.
+///
+/// `T` must not hold raw JSON: see [`raw_to_string_or_object`] for those.
+pub(crate) fn to_string_or_object<T: Serialize>(
+    value: &T,
+    as_object: bool,
+) -> Result<JsValue, serde_json::Error> {
+    if as_object {
+        value
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(serde::ser::Error::custom)
+    } else {
+        Ok(serde_json::to_string(value)?.into())
+    }
+}
+
+/// Serialize a value holding raw JSON, such as `Raw<T>`, to be returned to JS
+/// as in [`to_string_or_object`].
+pub(crate) fn raw_to_string_or_object<T: Serialize>(
+    value: &T,
+    as_object: bool,
+) -> Result<JsValue, serde_json::Error> {
+    // Encoding raw JSON only copies it.
+    let json = serde_json::to_string(value)?;
+
+    if as_object {
+        Ok(js_sys::JSON::parse(&json).expect("serde_json should produce valid JSON"))
+    } else {
+        Ok(json.into())
+    }
+}
@@ -19,6 +19,7 @@
 #![allow(clippy::drop_non_drop)]
 
 pub mod attachment;
+mod backoff;
 pub mod backup;
 pub mod dehydrated_devices;
 pub mod device;
@@ -28,6 +29,7 @@ pub mod events;
 mod future;
 pub mod identifiers;
 pub mod identities;
+mod json;
 pub mod libolm_migration;
 pub mod machine;
 mod macros;
@@ -35,10 +37,16 @@ pub mod olm;
 pub mod qr_login;
 pub mod requests;
 pub mod responses;
+pub mod room_key_sharing;
+mod room_members;
+mod room_settings;
 pub mod store;
 pub mod sync_events;
+pub mod to_device;
 mod tracing;
+pub mod transport;
 pub mod types;
+mod unwedging;
 pub mod verification;
 pub mod vodozemac;
 
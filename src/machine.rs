@@ -1,48 +1,60 @@
 //! The crypto specific Olm objects.
 
 use std::{
+    cell::RefCell,
     collections::{BTreeMap, HashSet},
-    iter,
+    fmt, iter,
     ops::Deref,
     pin::{pin, Pin},
+    rc::Rc,
+    sync::Arc,
     time::Duration,
 };
 
-use futures_util::{pin_mut, Stream, StreamExt};
+use futures_util::{
+    pin_mut,
+    stream::{self, AbortHandle},
+    Stream, StreamExt,
+};
 use js_sys::{Array, Function, JsString, Map, Promise, Set};
 use matrix_sdk_common::{
     deserialized_responses::TimelineEvent,
     ruma::{
-        self, events::secret::request::SecretName, serde::Raw, OneTimeKeyAlgorithm, OwnedDeviceId,
-        OwnedTransactionId, OwnedUserId, UInt,
+        self, api::client::keys::claim_keys::v3::Request as RumaKeysClaimRequest,
+        events::secret::request::SecretName, serde::Raw, OneTimeKeyAlgorithm, OwnedDeviceId,
+        OwnedTransactionId, OwnedUserId, TransactionId, UInt,
     },
 };
 use matrix_sdk_crypto::{
     backups::MegolmV1BackupKey,
     olm::{BackedUpRoomKey, ExportedRoomKey},
     store::{DeviceChanges, IdentityChanges},
-    types::RoomKeyBackupInfo,
+    types::{requests::OutgoingRequest, RoomKeyBackupInfo},
     CryptoStoreError, EncryptionSyncChanges, GossippedSecret,
 };
 use serde::{ser::SerializeSeq, Serialize, Serializer};
 use serde_json::json;
-use tracing::warn;
+use tracing::{debug_span, warn, Instrument};
 use wasm_bindgen::{convert::TryFromJsValue, prelude::*};
 use wasm_bindgen_futures::{spawn_local, JsFuture};
 
 use crate::{
+    backoff::RequestBackoff,
     backup::{BackupDecryptionKey, BackupKeys, RoomKeyCounts},
     dehydrated_devices::DehydratedDevices,
     device, encryption,
     error::MegolmDecryptionError,
     future::{future_to_promise, future_to_promise_with_custom_error},
-    identifiers, identities, olm, requests,
+    identifiers, identities, json, olm, requests,
     requests::{outgoing_request_to_js_value, CrossSigningBootstrapRequests, ToDeviceRequest},
-    responses::{self, response_from_string},
-    store,
+    responses::{self, response_from_bytes},
+    room_key_sharing,
+    room_members::{MembershipChanges, RoomMembers, RoomMembersLocks},
+    room_settings, store,
     store::{RoomKeyInfo, RoomKeyWithheldInfo, StoreHandle},
-    sync_events,
+    sync_events, to_device,
     types::{self, RoomKeyImportResult, RoomSettings, SignatureVerification},
+    unwedging::Unwedging,
     verification, vodozemac,
 };
 
@@ -51,7 +63,75 @@ use crate::{
 #[wasm_bindgen]
 #[derive(Debug, Clone)]
 pub struct OlmMachine {
-    inner: matrix_sdk_crypto::OlmMachine,
+    /// The underlying machine. It is replaced when the machine is reloaded
+    /// from the store, so should be accessed via [`OlmMachine::inner`].
+    inner: Rc<RefCell<matrix_sdk_crypto::OlmMachine>>,
+
+    /// The view of the store used by the underlying machine. It is retired
+    /// when the machine is reloaded, so that the old machine, and any object
+    /// still holding a clone of it, can no longer access the store.
+    inner_store: Rc<RefCell<Arc<store::FencedStore>>>,
+
+    /// The store backing this machine.
+    store_handle: StoreHandle,
+
+    /// The callbacks registered via the `register_*_callback` methods, so
+    /// that they can be registered again when the machine is reloaded.
+    callbacks: Rc<RefCell<Vec<(CallbackKind, Function)>>>,
+
+    /// The subscriptions of the registered callbacks to the streams of the
+    /// underlying machine, which are cancelled when it is reloaded.
+    subscriptions: Rc<RefCell<Vec<AbortHandle>>>,
+
+    /// Whether this machine was created with
+    /// [`OlmMachine::initialize_read_only`].
+    read_only: bool,
+
+    /// The backoff from sending requests which failed, as reported via
+    /// [`OlmMachine::mark_request_as_failed`].
+    backoff: RequestBackoff,
+
+    /// The devices being unwedged with
+    /// [`OlmMachine::force_new_olm_session`].
+    unwedging: Unwedging,
+
+    /// The locks serializing the updates of the tracked members of each room.
+    room_members_locks: RoomMembersLocks,
+}
+
+/// The error returned when calling a method which would modify the state of a
+/// read-only [`OlmMachine`], or of an object obtained from it.
+#[derive(Debug)]
+pub(crate) struct ReadOnlyError;
+
+impl ReadOnlyError {
+    /// Fail if `read_only` is set, for an operation which would modify the
+    /// state of the machine.
+    pub(crate) fn check(read_only: bool) -> Result<(), Self> {
+        if read_only {
+            Err(Self)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("This operation is not allowed on a read-only OlmMachine")
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+/// The kinds of callback which can be registered on an [`OlmMachine`].
+#[derive(Debug, Clone, Copy)]
+enum CallbackKind {
+    RoomKeyUpdated,
+    RoomKeysWithheld,
+    UserIdentityUpdated,
+    DevicesUpdated,
+    ReceiveSecret,
 }
 
 #[wasm_bindgen]
@@ -96,7 +176,7 @@ impl OlmMachine {
         let user_id = user_id.inner.clone();
         let device_id = device_id.inner.clone();
 
-        let store_handle = StoreHandle::open(store_name, store_passphrase)
+        let store_handle = StoreHandle::open(store_name, store_passphrase, None)
             .await
             .map_err(|e| JsError::from(&*e))?;
         Self::init_helper(user_id, device_id, store_handle).await
@@ -125,20 +205,113 @@ impl OlmMachine {
         Self::init_helper(user_id, device_id, store_handle.clone()).await
     }
 
+    /// Create a new read-only `OlmMachine` backed by an existing store.
+    ///
+    /// A read-only machine can decrypt room events with
+    /// {@link decryptRoomEvent} and inspect them with
+    /// {@link getRoomEventEncryptionInfo}, but never writes to the store, so it
+    /// can safely be used alongside another machine using the same store, for
+    /// example to decrypt push notifications in a service worker while the
+    /// main client is running.
+    ///
+    /// Methods which would modify the state of the machine, or generate
+    /// outgoing requests, throw (or return a rejected promise). So do those
+    /// of the {@link Device}s, {@link UserDevices}, {@link OwnUserIdentity}s
+    /// and {@link OtherUserIdentity}s obtained from it, such as
+    /// `requestVerification`, `verify` or `setLocalTrust`, so no verification
+    /// can be started; {@link dehydratedDevices} is not available. Room key
+    /// requests are disabled. Any incidental write that the machine performs
+    /// while decrypting events is discarded.
+    ///
+    /// The store must already contain an account for the given user and
+    /// device.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - represents the unique ID of the user that owns this
+    /// machine.
+    ///
+    /// * `device_id` - represents the unique ID of the device
+    /// that owns this machine.
+    ///
+    /// * `store_handle` - the connection to the crypto store to be used for
+    ///   this machine.
+    #[wasm_bindgen(js_name = "initializeReadOnly")]
+    pub async fn initialize_read_only(
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+        store_handle: &StoreHandle,
+    ) -> Result<JsValue, JsValue> {
+        let user_id = user_id.inner.clone();
+        let device_id = device_id.inner.clone();
+
+        if store_handle.store.load_account().await.map_err(JsError::from)?.is_none() {
+            return Err(JsError::new(
+                "A read-only OlmMachine can only be created from a store which already \
+                contains an account",
+            )
+            .into());
+        }
+
+        let store_handle = store_handle.read_only();
+        let inner_store = store_handle.fenced();
+        let inner = matrix_sdk_crypto::OlmMachine::with_store(
+            user_id.as_ref(),
+            device_id.as_ref(),
+            inner_store.clone(),
+            None,
+        )
+        .await
+        .map_err(JsError::from)?;
+
+        inner.set_room_key_requests_enabled(false);
+
+        Ok(OlmMachine {
+            inner: Rc::new(RefCell::new(inner)),
+            inner_store: Rc::new(RefCell::new(inner_store)),
+            store_handle,
+            callbacks: Default::default(),
+            subscriptions: Default::default(),
+            read_only: true,
+            backoff: Default::default(),
+            unwedging: Default::default(),
+            room_members_locks: Default::default(),
+        }
+        .into())
+    }
+
+    /// Whether this machine is read-only, i.e. was created with
+    /// {@link initializeReadOnly}.
+    #[wasm_bindgen(getter, js_name = "isReadOnly")]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     async fn init_helper(
         user_id: OwnedUserId,
         device_id: OwnedDeviceId,
         store_handle: StoreHandle,
     ) -> Result<JsValue, JsValue> {
+        let inner_store = store_handle.fenced();
+        let inner = matrix_sdk_crypto::OlmMachine::with_store(
+            user_id.as_ref(),
+            device_id.as_ref(),
+            inner_store.clone(),
+            None,
+        )
+        .await
+        .map_err(JsError::from)?;
+
         Ok(OlmMachine {
-            inner: matrix_sdk_crypto::OlmMachine::with_store(
-                user_id.as_ref(),
-                device_id.as_ref(),
-                store_handle,
-                None,
-            )
-            .await
-            .map_err(JsError::from)?,
+            inner: Rc::new(RefCell::new(inner)),
+            inner_store: Rc::new(RefCell::new(inner_store)),
+            store_handle,
+            callbacks: Default::default(),
+            subscriptions: Default::default(),
+            read_only: false,
+            backoff: Default::default(),
+            unwedging: Default::default(),
+            room_members_locks: Default::default(),
         }
         .into())
     }
@@ -146,13 +319,13 @@ impl OlmMachine {
     /// The unique user ID that owns this `OlmMachine` instance.
     #[wasm_bindgen(getter, js_name = "userId")]
     pub fn user_id(&self) -> identifiers::UserId {
-        identifiers::UserId::from(self.inner.user_id().to_owned())
+        identifiers::UserId::from(self.inner().user_id().to_owned())
     }
 
     /// The unique device ID that identifies this `OlmMachine`.
     #[wasm_bindgen(getter, js_name = "deviceId")]
     pub fn device_id(&self) -> identifiers::DeviceId {
-        identifiers::DeviceId::from(self.inner.device_id().to_owned())
+        identifiers::DeviceId::from(self.inner().device_id().to_owned())
     }
 
     /// The time, in milliseconds since the unix epoch, at which the `Account`
@@ -164,19 +337,19 @@ impl OlmMachine {
     /// which that happened.
     #[wasm_bindgen(getter, js_name = "deviceCreationTimeMs")]
     pub fn device_creation_time_ms(&self) -> f64 {
-        self.inner.device_creation_time().get().into()
+        self.inner().device_creation_time().get().into()
     }
 
     /// Get the public parts of our Olm identity keys.
     #[wasm_bindgen(getter, js_name = "identityKeys")]
     pub fn identity_keys(&self) -> vodozemac::IdentityKeys {
-        self.inner.identity_keys().into()
+        self.inner().identity_keys().into()
     }
 
     /// Get the display name of our own device.
     #[wasm_bindgen(getter, js_name = "displayName")]
     pub fn display_name(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move { Ok(me.display_name().await?) })
     }
@@ -188,13 +361,17 @@ impl OlmMachine {
     /// events.
     #[wasm_bindgen(getter, js_name = "roomKeyRequestsEnabled")]
     pub fn are_room_key_requests_enabled(&self) -> bool {
-        self.inner.are_room_key_requests_enabled()
+        self.inner().are_room_key_requests_enabled()
     }
 
     /// Enable or disable automatic transmission of room key requests.
+    ///
+    /// Room key requests cannot be enabled on a read-only machine.
     #[wasm_bindgen(setter, js_name = "roomKeyRequestsEnabled")]
-    pub fn set_room_key_requests_enabled(&self, enabled: bool) {
-        self.inner.set_room_key_requests_enabled(enabled)
+    pub fn set_room_key_requests_enabled(&self, enabled: bool) -> Result<(), JsError> {
+        let me = if enabled { self.writable_inner()? } else { self.inner() };
+        me.set_room_key_requests_enabled(enabled);
+        Ok(())
     }
 
     /// Whether room key forwarding is enabled.
@@ -204,13 +381,13 @@ impl OlmMachine {
     /// forwarding the requested key (if we have it).
     #[wasm_bindgen(getter, js_name = "roomKeyForwardingEnabled")]
     pub fn is_room_key_forwarding_enabled(&self) -> bool {
-        self.inner.is_room_key_forwarding_enabled()
+        self.inner().is_room_key_forwarding_enabled()
     }
 
     /// Enable or disable room key forwarding.
     #[wasm_bindgen(setter, js_name = "roomKeyForwardingEnabled")]
     pub fn set_room_key_forwarding_enabled(&self, enabled: bool) {
-        self.inner.set_room_key_forwarding_enabled(enabled)
+        self.inner().set_room_key_forwarding_enabled(enabled)
     }
 
     /// Get the list of users whose devices we are currently tracking.
@@ -222,7 +399,7 @@ impl OlmMachine {
     #[wasm_bindgen(js_name = "trackedUsers")]
     pub fn tracked_users(&self) -> Result<Promise, JsError> {
         let set = Set::new(&JsValue::UNDEFINED);
-        let me = self.inner.clone();
+        let me = self.inner();
 
         Ok(future_to_promise(async move {
             for user in me.tracked_users().await? {
@@ -256,9 +433,10 @@ impl OlmMachine {
     pub fn update_tracked_users(&self, users: Vec<identifiers::UserId>) -> Promise {
         let users = users.iter().map(|user| user.inner.clone()).collect::<Vec<_>>();
 
-        let me = self.inner.clone();
+        let me = self.writable_inner();
 
         future_to_promise(async move {
+            let me = me?;
             me.update_tracked_users(users.iter().map(AsRef::as_ref)).await?;
             Ok(JsValue::UNDEFINED)
         })
@@ -270,7 +448,7 @@ impl OlmMachine {
     /// key query. Users whose devices we are not tracking are ignored.
     #[wasm_bindgen(js_name = "markAllTrackedUsersAsDirty")]
     pub async fn mark_all_tracked_users_as_dirty(&self) -> Result<(), JsError> {
-        self.inner.mark_all_tracked_users_as_dirty().await?;
+        self.writable_inner()?.mark_all_tracked_users_as_dirty().await?;
         Ok(())
     }
 
@@ -285,8 +463,8 @@ impl OlmMachine {
     ///
     /// # Arguments
     ///
-    /// * `to_device_events`: the JSON-encoded to-device evens from the `/sync`
-    ///   response
+    /// * `to_device_events`: the to-device events from the `/sync` response,
+    ///   either as a JSON-encoded string or as an array of plain objects
     /// * `changed_devices`: the mapping of changed and left devices, from the
     ///   `/sync` response
     /// * `one_time_keys_counts`: The number of one-time keys on the server,
@@ -298,63 +476,247 @@ impl OlmMachine {
     ///
     /// # Returns
     ///
-    /// A list of JSON strings, containing the decrypted to-device events.
+    /// The list of the decrypted to-device events: a JSON-encoded string if
+    /// `to_device_events` was a string, or an array of plain objects
+    /// otherwise.
     #[wasm_bindgen(js_name = "receiveSyncChanges")]
     pub fn receive_sync_changes(
         &self,
-        to_device_events: &str,
+        to_device_events: JsValue,
         changed_devices: &sync_events::DeviceLists,
         one_time_keys_counts: &Map,
         unused_fallback_keys: Option<Set>,
     ) -> Result<Promise, JsError> {
-        let to_device_events = serde_json::from_str(to_device_events)?;
+        let as_object = !to_device_events.is_string();
+        let to_device_events = json::raw_from_string_or_object(to_device_events)?;
         let changed_devices = changed_devices.inner.clone();
-        let one_time_keys_counts: BTreeMap<OneTimeKeyAlgorithm, UInt> = one_time_keys_counts
-            .entries()
-            .into_iter()
-            .filter_map(|js_value| {
-                let pair = Array::from(&js_value.ok()?);
-                let (key, value) = (
-                    OneTimeKeyAlgorithm::from(pair.at(0).as_string()?),
-                    UInt::new(pair.at(1).as_f64()? as u64)?,
-                );
+        let (one_time_keys_counts, unused_fallback_keys) =
+            key_counts_from_js(one_time_keys_counts, unused_fallback_keys);
+
+        let me = self.writable_inner()?;
+
+        Ok(future_to_promise(
+            async move {
+                // we discard the list of updated room keys in the result; JS applications are
+                // expected to use register_room_key_updated_callback to receive updated room
+                // keys.
+                let (decrypted_to_device_events, _) = me
+                    .receive_sync_changes(EncryptionSyncChanges {
+                        to_device_events,
+                        changed_devices: &changed_devices,
+                        one_time_keys_counts: &one_time_keys_counts,
+                        unused_fallback_keys: unused_fallback_keys.as_deref(),
+
+                        // matrix-sdk-crypto does not (currently) use `next_batch_token`.
+                        next_batch_token: None,
+                    })
+                    .await?;
 
-                Some((key, value))
-            })
-            .collect();
+                Ok(json::raw_to_string_or_object(&decrypted_to_device_events, as_object)?)
+            }
+            .instrument(debug_span!("receiveSyncChanges")),
+        ))
+    }
 
-        // Convert the unused_fallback_keys JS Set to a `Vec<OneTimeKeyAlgorithm>`
-        let unused_fallback_keys: Option<Vec<OneTimeKeyAlgorithm>> =
-            unused_fallback_keys.map(|fallback_keys| {
-                fallback_keys
-                    .values()
-                    .into_iter()
-                    .filter_map(|js_value| {
-                        Some(OneTimeKeyAlgorithm::from(js_value.ok()?.as_string()?))
+    /// Handle to-device events and one-time key counts from a sync
+    /// response, like {@link OlmMachine.receiveSyncChanges}, also telling
+    /// which encrypted to-device events could not be decrypted, and why.
+    ///
+    /// # Arguments
+    ///
+    /// The same as {@link OlmMachine.receiveSyncChanges}.
+    ///
+    /// # Returns
+    ///
+    /// A {@link ProcessedSyncChanges}, with the to-device events, the
+    /// encrypted ones which could not be decrypted, and the room keys which
+    /// were received.
+    #[wasm_bindgen(js_name = "processSyncChanges")]
+    pub fn process_sync_changes(
+        &self,
+        to_device_events: JsValue,
+        changed_devices: &sync_events::DeviceLists,
+        one_time_keys_counts: &Map,
+        unused_fallback_keys: Option<Set>,
+    ) -> Result<Promise, JsError> {
+        let as_object = !to_device_events.is_string();
+        let to_device_events: Vec<Raw<_>> = json::raw_from_string_or_object(to_device_events)?;
+        let changed_devices = changed_devices.inner.clone();
+        let (one_time_keys_counts, unused_fallback_keys) =
+            key_counts_from_js(one_time_keys_counts, unused_fallback_keys);
+
+        let me = self.writable_inner()?;
+
+        Ok(future_to_promise(
+            async move {
+                let received = to_device::ReceivedToDeviceEvents::record(&to_device_events);
+                let (decrypted_to_device_events, room_key_infos) = me
+                    .receive_sync_changes(EncryptionSyncChanges {
+                        to_device_events,
+                        changed_devices: &changed_devices,
+                        one_time_keys_counts: &one_time_keys_counts,
+                        unused_fallback_keys: unused_fallback_keys.as_deref(),
+
+                        // matrix-sdk-crypto does not (currently) use `next_batch_token`.
+                        next_batch_token: None,
                     })
-                    .collect()
-            });
+                    .await?;
 
-        let me = self.inner.clone();
+                to_device::ProcessedSyncChanges::new(
+                    &me,
+                    received,
+                    decrypted_to_device_events,
+                    room_key_infos,
+                    as_object,
+                )
+                .await
+            }
+            .instrument(debug_span!("processSyncChanges")),
+        ))
+    }
 
-        Ok(future_to_promise(async move {
-            // we discard the list of updated room keys in the result; JS applications are
-            // expected to use register_room_key_updated_callback to receive updated room
-            // keys.
-            let (decrypted_to_device_events, _) = me
-                .receive_sync_changes(EncryptionSyncChanges {
-                    to_device_events,
-                    changed_devices: &changed_devices,
-                    one_time_keys_counts: &one_time_keys_counts,
-                    unused_fallback_keys: unused_fallback_keys.as_deref(),
-
-                    // matrix-sdk-crypto does not (currently) use `next_batch_token`.
-                    next_batch_token: None,
-                })
-                .await?;
+    /// Handle a whole `/sync` response, rather than the parts of it passed to
+    /// {@link OlmMachine.receiveSyncChanges}.
+    ///
+    /// This handles the to-device events, the device list changes and the
+    /// one-time and fallback key counts, including their unstable field names.
+    /// In the joined rooms, it also handles:
+    ///
+    /// * the `m.room.encryption` state events of rooms which were not known to
+    ///   be encrypted, storing their settings (see {@link
+    ///   OlmMachine.getRoomSettings}), and
+    /// * the membership changes in encrypted rooms, tracking the users who join
+    ///   or are invited.
+    ///
+    /// The `next_batch` token of the response is stored along with the
+    /// changes caused by the to-device events, and returned by {@link
+    /// OlmMachine.getNextBatchToken}.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync_response`: the body of the `/sync` response, either as a
+    ///   JSON-encoded string or as a plain object.
+    ///
+    /// # Returns
+    ///
+    /// A {@link SyncResponseSummary}.
+    #[wasm_bindgen(js_name = "receiveSyncResponse")]
+    pub fn receive_sync_response(&self, sync_response: JsValue) -> Result<Promise, JsError> {
+        let as_object = !sync_response.is_string();
+        let sync_response: sync_events::SyncResponse =
+            json::raw_from_string_or_object(sync_response)?;
+
+        let me = self.writable_inner()?;
+        let room_members_locks = self.room_members_locks.clone();
+
+        Ok(future_to_promise(
+            async move {
+                let received =
+                    to_device::ReceivedToDeviceEvents::record(&sync_response.to_device.events);
+                let (decrypted_to_device_events, room_key_infos) = me
+                    .receive_sync_changes(EncryptionSyncChanges {
+                        to_device_events: sync_response.to_device.events,
+                        changed_devices: &sync_response.e2ee.device_lists,
+                        one_time_keys_counts: &sync_response.e2ee.device_one_time_keys_count,
+                        unused_fallback_keys: sync_response
+                            .e2ee
+                            .device_unused_fallback_key_types
+                            .as_deref(),
+                        next_batch_token: sync_response.next_batch,
+                    })
+                    .await?;
 
-            Ok(serde_json::to_string(&decrypted_to_device_events)?)
-        }))
+                let room_changes =
+                    sync_events::RoomChanges::apply(&me, &room_members_locks, sync_response.rooms)
+                        .await?;
+
+                Ok(sync_events::SyncResponseSummary::new(
+                    to_device::ProcessedSyncChanges::new(
+                        &me,
+                        received,
+                        decrypted_to_device_events,
+                        room_key_infos,
+                        as_object,
+                    )
+                    .await?,
+                    room_changes,
+                ))
+            }
+            .instrument(debug_span!("receiveSyncResponse")),
+        ))
+    }
+
+    /// Handle the `to_device` and `e2ee` extensions of a sliding sync
+    /// response ([MSC3575] or [MSC4186]).
+    ///
+    /// The `next_batch` token of the `to_device` extension is stored along
+    /// with the changes caused by the to-device events, so that they are
+    /// received again if we stop before processing them. Pass the token
+    /// returned by {@link OlmMachine.getNextBatchToken} as the `since` of the
+    /// `to_device` extension of the next sliding sync request.
+    ///
+    /// [MSC3575]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+    /// [MSC4186]: https://github.com/matrix-org/matrix-spec-proposals/pull/4186
+    ///
+    /// # Arguments
+    ///
+    /// * `to_device`: the `to_device` extension of the response, if any, either
+    ///   as a JSON-encoded string or as a plain object.
+    /// * `e2ee`: the `e2ee` extension of the response, if any, either as a
+    ///   JSON-encoded string or as a plain object.
+    ///
+    /// # Returns
+    ///
+    /// The list of the decrypted to-device events: a JSON-encoded string if
+    /// `to_device` was a string, or an array of plain objects otherwise.
+    #[wasm_bindgen(js_name = "receiveSlidingSyncExtensions")]
+    pub fn receive_sliding_sync_extensions(
+        &self,
+        to_device: JsValue,
+        e2ee: JsValue,
+    ) -> Result<Promise, JsError> {
+        let as_object = !to_device.is_string();
+        let to_device: sync_events::ToDevice = if to_device.is_undefined() || to_device.is_null() {
+            Default::default()
+        } else {
+            json::raw_from_string_or_object(to_device)?
+        };
+        let e2ee: sync_events::E2ee = if e2ee.is_undefined() || e2ee.is_null() {
+            Default::default()
+        } else {
+            json::from_string_or_object(e2ee)?
+        };
+
+        let me = self.writable_inner()?;
+
+        Ok(future_to_promise(
+            async move {
+                let (decrypted_to_device_events, _) = me
+                    .receive_sync_changes(EncryptionSyncChanges {
+                        to_device_events: to_device.events,
+                        changed_devices: &e2ee.device_lists,
+                        one_time_keys_counts: &e2ee.device_one_time_keys_count,
+                        unused_fallback_keys: e2ee.device_unused_fallback_key_types.as_deref(),
+                        next_batch_token: to_device.next_batch,
+                    })
+                    .await?;
+
+                Ok(json::raw_to_string_or_object(&decrypted_to_device_events, as_object)?)
+            }
+            .instrument(debug_span!("receiveSlidingSyncExtensions")),
+        ))
+    }
+
+    /// Get the `next_batch` token of the last to-device events which were
+    /// handled by {@link OlmMachine.receiveSlidingSyncExtensions} or {@link
+    /// OlmMachine.receiveSyncResponse}.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<string|undefined>`
+    #[wasm_bindgen(js_name = "getNextBatchToken")]
+    pub async fn get_next_batch_token(&self) -> Result<Option<String>, JsError> {
+        Ok(self.inner().store().next_batch_token().await?)
     }
 
     /// Get the outgoing requests that need to be sent out.
@@ -371,13 +733,17 @@ impl OlmMachine {
     /// Those requests need to be sent out to the server and the
     /// responses need to be passed back to the state machine
     /// using {@link OlmMachine.markRequestAsSent}.
+    ///
+    /// Requests of a type which recently failed, as reported with
+    /// {@link OlmMachine.markRequestAsFailed}, are held back until it is time
+    /// to retry them.
     #[wasm_bindgen(js_name = "outgoingRequests")]
     pub fn outgoing_requests(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.clone();
 
         future_to_promise(async move {
             Ok(me
-                .outgoing_requests()
+                .pending_outgoing_requests()
                 .await?
                 .into_iter()
                 .map(outgoing_request_to_js_value)
@@ -397,25 +763,93 @@ impl OlmMachine {
     ///   request.
     /// * `response_type` represents the type of the request that was sent out.
     /// * `response` represents the response that was received from the server
-    ///   after the outgoing request was sent out.
+    ///   after the outgoing request was sent out, either as a JSON-encoded
+    ///   string or as a plain object.
     #[wasm_bindgen(js_name = "markRequestAsSent")]
     pub fn mark_request_as_sent(
         &self,
         request_id: &str,
         request_type: requests::RequestType,
-        response: &str,
+        response: JsValue,
     ) -> Result<Promise, JsError> {
         let transaction_id = OwnedTransactionId::from(request_id);
-        let response = response_from_string(response)?;
+        let response =
+            response_from_bytes(json::string_from_string_or_object(response)?.into_bytes())?;
         let incoming_response = responses::OwnedResponse::try_from((request_type, response))?;
 
-        let me = self.inner.clone();
+        // Fail early if this machine is read-only.
+        self.writable_inner()?;
+        let me = self.clone();
 
         Ok(future_to_promise(async move {
-            Ok(me.mark_request_as_sent(&transaction_id, &incoming_response).await.map(|_| true)?)
+            me.receive_response(&transaction_id, request_type, &incoming_response).await?;
+
+            Ok(true)
         }))
     }
 
+    /// Mark the request with the given request ID as failed (see
+    /// `outgoing_requests`).
+    ///
+    /// Requests of the same type are then held back by
+    /// {@link OlmMachine.outgoingRequests}, {@link
+    /// OlmMachine.getMissingSessions} and {@link OlmMachine.backupRoomKeys}
+    /// for a while: as long as the server asked for if it was rate-limiting
+    /// us, or for an exponentially increasing delay otherwise. They are
+    /// generated again once that delay has passed.
+    ///
+    /// If the server reports that the backup version has changed
+    /// (`M_WRONG_ROOM_KEYS_VERSION`), the backup is disabled.
+    ///
+    /// The callback registered with {@link
+    /// OlmMachine.registerRequestFailedCallback}, if any, is called with the
+    /// outcome.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - the unique ID of the request that failed.
+    /// * `request_type` - the type of the request that failed.
+    /// * `status` - the HTTP status of the response, or `0` if no response was
+    ///   received, for example because of a network error.
+    /// * `body` - the body of the response, if any.
+    ///
+    /// # Returns
+    ///
+    /// A {@link RequestFailure}.
+    #[wasm_bindgen(js_name = "markRequestAsFailed")]
+    pub async fn mark_request_as_failed(
+        &self,
+        request_id: String,
+        request_type: requests::RequestType,
+        status: u16,
+        body: Option<String>,
+    ) -> Result<responses::RequestFailure, JsError> {
+        let me = self.writable_inner()?;
+        let failure =
+            responses::ParsedFailure::parse(status, body.unwrap_or_default().into_bytes());
+
+        let backup_disabled =
+            failure.wrong_backup_version && request_type == requests::RequestType::KeysBackup;
+        if backup_disabled {
+            me.backup_machine().disable_backup().await?;
+        }
+
+        let delay = self.backoff.record_failure(request_type, failure.server_delay);
+        let failure = responses::RequestFailure {
+            request_id: request_id.into(),
+            request_type,
+            status,
+            errcode: failure.errcode.map(Into::into),
+            message: failure.message.map(Into::into),
+            retryable: failure.retryable,
+            retry_after_ms: delay.as_millis() as f64,
+            backup_disabled,
+        };
+        self.backoff.notify(&failure);
+
+        Ok(failure)
+    }
+
     /// Encrypt a room message for the given room.
     ///
     /// **Note**: A room key needs to be shared with the group of users that are
@@ -445,7 +879,9 @@ impl OlmMachine {
     /// `room_id` is the ID of the room for which the message should
     /// be encrypted. `event_type` is the type of the event. `content`
     /// is the plaintext content of the message that should be
-    /// encrypted.
+    /// encrypted, either as a JSON-encoded string or as a plain object.
+    ///
+    /// The encrypted content is returned in the same form as `content`.
     ///
     /// # Panics
     ///
@@ -456,15 +892,17 @@ impl OlmMachine {
         &self,
         room_id: &identifiers::RoomId,
         event_type: String,
-        content: &str,
+        content: JsValue,
     ) -> Result<Promise, JsError> {
         let room_id = room_id.inner.clone();
-        let content = serde_json::from_str(content)?;
-        let me = self.inner.clone();
+        let as_object = !content.is_string();
+        let content = json::raw_from_string_or_object(content)?;
+        let me = self.writable_inner()?;
 
         Ok(future_to_promise(async move {
-            Ok(serde_json::to_string(
+            Ok(json::raw_to_string_or_object(
                 &me.encrypt_room_event_raw(&room_id, event_type.as_ref(), &content).await?,
+                as_object,
             )?)
         }))
     }
@@ -473,7 +911,8 @@ impl OlmMachine {
     ///
     /// # Arguments
     ///
-    /// * `event`, the event that should be decrypted.
+    /// * `event`, the event that should be decrypted, either as a JSON-encoded
+    ///   string or as a plain object.
     /// * `room_id`, the ID of the room where the event was sent to.
     ///
     /// # Returns
@@ -483,27 +922,31 @@ impl OlmMachine {
     #[wasm_bindgen(js_name = "decryptRoomEvent")]
     pub fn decrypt_room_event(
         &self,
-        event: &str,
+        event: JsValue,
         room_id: &identifiers::RoomId,
         decryption_settings: &encryption::DecryptionSettings,
     ) -> Result<Promise, JsError> {
-        let event: Raw<_> = serde_json::from_str(event)?;
+        let event: Raw<_> = json::raw_from_string_or_object(event)?;
         let room_id = room_id.inner.clone();
         let decryption_settings = decryption_settings.into();
-        let me = self.inner.clone();
+        let me = self.inner();
+        let span = debug_span!("decryptRoomEvent", %room_id);
 
         Ok(future_to_promise_with_custom_error::<
             _,
             responses::DecryptedRoomEvent,
             MegolmDecryptionError,
-        >(async move {
-            let room_event: TimelineEvent = me
-                .decrypt_room_event(&event, room_id.as_ref(), &decryption_settings)
-                .await
-                .map_err(MegolmDecryptionError::from)?
-                .into();
-            Ok(responses::DecryptedRoomEvent::from(room_event))
-        }))
+        >(
+            async move {
+                let room_event: TimelineEvent = me
+                    .decrypt_room_event(&event, room_id.as_ref(), &decryption_settings)
+                    .await
+                    .map_err(MegolmDecryptionError::from)?
+                    .into();
+                Ok(responses::DecryptedRoomEvent::from(room_event))
+            }
+            .instrument(span),
+        ))
     }
 
     /// Get encryption info for a decrypted timeline event.
@@ -516,7 +959,8 @@ impl OlmMachine {
     ///
     /// # Arguments
     ///
-    /// * `event` - The event to get information for.
+    /// * `event` - The event to get information for, either as a JSON-encoded
+    ///   string or as a plain object.
     /// * `room_id` - The ID of the room where the event was sent to.
     ///
     /// # Returns
@@ -525,12 +969,12 @@ impl OlmMachine {
     #[wasm_bindgen(js_name = "getRoomEventEncryptionInfo")]
     pub fn get_room_event_encryption_info(
         &self,
-        event: &str,
+        event: JsValue,
         room_id: &identifiers::RoomId,
     ) -> Result<Promise, JsError> {
-        let event: Raw<_> = serde_json::from_str(event)?;
+        let event: Raw<_> = json::raw_from_string_or_object(event)?;
         let room_id = room_id.inner.clone();
-        let me = self.inner.clone();
+        let me = self.inner();
 
         Ok(future_to_promise(async move {
             let encryption_info =
@@ -545,7 +989,7 @@ impl OlmMachine {
     /// have stored locally.
     #[wasm_bindgen(js_name = "crossSigningStatus")]
     pub fn cross_signing_status(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise::<_, olm::CrossSigningStatus>(async move {
             Ok(me.cross_signing_status().await.into())
@@ -566,7 +1010,7 @@ impl OlmMachine {
     /// i.e. if an existing device is sharing this with a new device.
     #[wasm_bindgen(js_name = "exportSecretsBundle")]
     pub async fn export_secrets_bundle(&self) -> Result<store::SecretsBundle, JsError> {
-        Ok(self.inner.store().export_secrets_bundle().await?.into())
+        Ok(self.inner().store().export_secrets_bundle().await?.into())
     }
 
     /// Import and persists secrets from a {@link SecretsBundle}.
@@ -586,7 +1030,7 @@ impl OlmMachine {
     /// use it once this method has been called.
     #[wasm_bindgen(js_name = "importSecretsBundle")]
     pub async fn import_secrets_bundle(&self, bundle: store::SecretsBundle) -> Result<(), JsError> {
-        self.inner.store().import_secrets_bundle(&bundle.inner).await?;
+        self.writable_inner()?.store().import_secrets_bundle(&bundle.inner).await?;
         Ok(())
     }
 
@@ -599,7 +1043,7 @@ impl OlmMachine {
     /// otherwise returns a `CrossSigningKeyExport`.
     #[wasm_bindgen(js_name = "exportCrossSigningKeys")]
     pub fn export_cross_signing_keys(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move {
             Ok(me.export_cross_signing_keys().await?.map(store::CrossSigningKeyExport::from))
@@ -618,7 +1062,7 @@ impl OlmMachine {
         self_signing_key: Option<String>,
         user_signing_key: Option<String>,
     ) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
         let export = matrix_sdk_crypto::store::CrossSigningKeyExport {
             master_key,
             self_signing_key,
@@ -626,6 +1070,7 @@ impl OlmMachine {
         };
 
         future_to_promise(async move {
+            let me = me?;
             Ok(me.import_cross_signing_keys(export).await.map(olm::CrossSigningStatus::from)?)
         })
     }
@@ -652,9 +1097,10 @@ impl OlmMachine {
     /// Returns a {@link CrossSigningBootstrapRequests}.
     #[wasm_bindgen(js_name = "bootstrapCrossSigning")]
     pub fn bootstrap_cross_signing(&self, reset: bool) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
 
         future_to_promise(async move {
+            let me = me?;
             let requests = me.bootstrap_cross_signing(reset).await?;
             Ok(CrossSigningBootstrapRequests::try_from(requests)?)
         })
@@ -666,8 +1112,9 @@ impl OlmMachine {
     /// {@link OtherUserIdentity}, or `undefined`.
     #[wasm_bindgen(js_name = "getIdentity")]
     pub fn get_identity(&self, user_id: &identifiers::UserId) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
         let user_id = user_id.inner.clone();
+        let read_only = self.read_only;
 
         future_to_promise(async move {
             // wait for up to a second for any in-flight device list requests to complete.
@@ -675,20 +1122,134 @@ impl OlmMachine {
             Ok(me
                 .get_identity(user_id.as_ref(), Some(Duration::from_secs(1)))
                 .await?
-                .map(identities::UserIdentity::from))
+                .map(|inner| identities::UserIdentity { inner, read_only }))
         })
     }
 
     /// Sign the given message using our device key and if available
     /// cross-signing master key.
     pub fn sign(&self, message: String) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise::<_, types::Signatures>(
             async move { Ok(me.sign(&message).await?.into()) },
         )
     }
 
+    /// Sign a JSON object with our own keys.
+    ///
+    /// The object is canonicalised as described in the
+    /// {@link https://spec.matrix.org/v1.11/appendices/#signing-json | spec},
+    /// ignoring its `signatures` and `unsigned` properties, and signed with
+    /// the keys selected in `options`. The new signatures are merged into the
+    /// `signatures` property of the object, alongside any existing
+    /// signatures.
+    ///
+    /// # Arguments
+    ///
+    /// * `object` - the JSON object to be signed.
+    ///
+    /// * `options` - an optional object with the following properties:
+    ///     * `withDeviceKey` - whether to sign with the Ed25519 key of this
+    ///       device. Defaults to `true`.
+    ///     * `withMasterKey` - whether to sign with our cross-signing master
+    ///       key. Defaults to `false`. If the private master key is not
+    ///       available, the returned promise is rejected.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<object>`: a copy of `object`, including the new signatures.
+    #[wasm_bindgen(js_name = "signJson")]
+    pub async fn sign_json(&self, object: JsValue, options: JsValue) -> Result<JsValue, JsError> {
+        let mut value: serde_json::Value = serde_wasm_bindgen::from_value(object)?;
+        let options: types::SignJsonOptions = if options.is_undefined() || options.is_null() {
+            Default::default()
+        } else {
+            serde_wasm_bindgen::from_value(options)?
+        };
+        let canonical_json = types::to_signable_json(value.clone())?;
+
+        let me = self.inner();
+        let user_id = me.user_id();
+        let device_key_id =
+            ruma::DeviceKeyId::from_parts(ruma::DeviceKeyAlgorithm::Ed25519, me.device_id());
+        let mut signatures = matrix_sdk_crypto::types::Signatures::new();
+        let mut signed_with_master_key = false;
+
+        for (key_id, signature) in
+            me.sign(&canonical_json).await?.get(user_id).into_iter().flatten()
+        {
+            let is_device_key = *key_id == device_key_id;
+            let wanted =
+                if is_device_key { options.with_device_key } else { options.with_master_key };
+
+            if let (true, Ok(signature)) = (wanted, signature) {
+                if let Some(signature) = signature.ed25519() {
+                    signatures.add_signature(user_id.to_owned(), key_id.clone(), signature);
+                    signed_with_master_key |= !is_device_key;
+                }
+            }
+        }
+
+        if options.with_master_key && !signed_with_master_key {
+            return Err(JsError::new(
+                "Unable to sign with the cross-signing master key: the private key is not available",
+            ));
+        }
+
+        types::merge_signatures(&mut value, &signatures)?;
+
+        Ok(value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+    }
+
+    /// Check the signatures that a user has made on a signed JSON object.
+    ///
+    /// The object is canonicalised, with its `signatures` and `unsigned`
+    /// properties removed, and the signatures made by `signer` are checked
+    /// against the Ed25519 keys of each of the signer's known devices, as well
+    /// as the master and self-signing keys of their cross-signing identity.
+    ///
+    /// This can be used to check things like backup `auth_data`, device keys
+    /// obtained out-of-band, or the `sender_device_keys` of a to-device event.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - the signed JSON object, as a JSON-encoded string.
+    ///
+    /// * `signer` - the user whose signatures should be checked.
+    ///
+    /// Returns a `Promise` for a {@link SignatureVerification}, whose
+    /// `keyStates` holds the result for each key that was checked.
+    #[wasm_bindgen(js_name = "verifyJsonSignatures")]
+    pub fn verify_json_signatures(
+        &self,
+        json: &str,
+        signer: &identifiers::UserId,
+    ) -> Result<Promise, JsError> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let signatures: matrix_sdk_crypto::types::Signatures =
+            serde_json::from_value(value.get("signatures").cloned().unwrap_or_else(|| json!({})))?;
+        let canonical_json = types::to_signable_json(value)?;
+
+        let signer = signer.inner.clone();
+        let me = self.inner();
+
+        Ok(future_to_promise(async move {
+            let devices = me.get_user_devices(&signer, None).await?;
+            let identity = me.get_identity(&signer, None).await?;
+
+            Ok(SignatureVerification::check_json(
+                me.user_id(),
+                me.device_id(),
+                &signer,
+                &signatures,
+                &canonical_json,
+                devices.devices(),
+                identity,
+            ))
+        }))
+    }
+
     /// Invalidate the currently active outbound group session for the
     /// given room.
     ///
@@ -697,9 +1258,12 @@ impl OlmMachine {
     #[wasm_bindgen(js_name = "invalidateGroupSession")]
     pub fn invalidate_group_session(&self, room_id: &identifiers::RoomId) -> Promise {
         let room_id = room_id.inner.clone();
-        let me = self.inner.clone();
+        let me = self.writable_inner();
 
-        future_to_promise(async move { Ok(me.discard_room_key(&room_id).await?) })
+        future_to_promise(async move {
+            let me = me?;
+            Ok(me.discard_room_key(&room_id).await?)
+        })
     }
 
     /// Get to-device requests to share a room key with users in a room.
@@ -711,6 +1275,13 @@ impl OlmMachine {
     /// Note: Care should be taken that only one such request at a
     /// time is in flight for the same room, e.g. using a lock.
     ///
+    /// If `users` is `undefined`, the room key is shared with the members of
+    /// the room tracked with {@link OlmMachine.receiveRoomStateEvents}, and
+    /// this fails if none were. Only the members of the state events passed
+    /// so far are known: with lazy-loaded members, the full member list, for
+    /// example from `/members`, must be passed first, or some members will
+    /// not receive the room key.
+    ///
     /// Returns an array of `ToDeviceRequest`s.
     ///
     /// Items inside `users` will be invalidated by this method. Be careful not
@@ -719,31 +1290,183 @@ impl OlmMachine {
     pub fn share_room_key(
         &self,
         room_id: &identifiers::RoomId,
-        users: Vec<identifiers::UserId>,
+        users: Option<Vec<identifiers::UserId>>,
         encryption_settings: &encryption::EncryptionSettings,
     ) -> Promise {
         let room_id = room_id.inner.clone();
-        let users = users.iter().map(|user| user.inner.clone()).collect::<Vec<_>>();
+        let users = users.map(|users| users.iter().map(|user| user.inner.clone()).collect());
         let encryption_settings =
             matrix_sdk_crypto::olm::EncryptionSettings::from(encryption_settings);
 
-        let me = self.inner.clone();
+        let me = self.writable_inner();
+        let span = debug_span!("shareRoomKey", %room_id);
+
+        future_to_promise(
+            async move {
+                let me = me?;
+                let users = match users {
+                    Some(users) => users,
+                    None => RoomMembers::load_tracked_recipients(&me, &room_id).await?,
+                };
+                let to_device_requests = me
+                    .share_room_key(&room_id, users.iter().map(AsRef::as_ref), encryption_settings)
+                    .await?;
 
-        future_to_promise(async move {
-            let to_device_requests = me
-                .share_room_key(&room_id, users.iter().map(AsRef::as_ref), encryption_settings)
-                .await?;
-
-            // convert each request to our own ToDeviceRequest struct, and then wrap it in a
-            // JsValue.
-            //
-            // Then collect the results into a javascript Array, throwing any errors into
-            // the promise.
-            Ok(to_device_requests
-                .into_iter()
-                .map(|td| ToDeviceRequest::try_from(td.deref()).map(JsValue::from))
-                .collect::<Result<Array, _>>()?)
-        })
+                // convert each request to our own ToDeviceRequest struct, and then wrap it in a
+                // JsValue.
+                //
+                // Then collect the results into a javascript Array, throwing any errors into
+                // the promise.
+                Ok(to_device_requests
+                    .into_iter()
+                    .map(|td| ToDeviceRequest::try_from(td.deref()).map(JsValue::from))
+                    .collect::<Result<Array, _>>()?)
+            }
+            .instrument(span),
+        )
+    }
+
+    /// Pass state events of an encrypted room, from the `state` or the
+    /// `timeline` of the room in a `/sync` response, so that its members are
+    /// tracked: this is done by {@link OlmMachine.receiveSyncResponse} for
+    /// the rooms of the response.
+    ///
+    /// The `m.room.member` and `m.room.history_visibility` events are used to
+    /// work out who should receive the room keys of the room: its joined
+    /// members, and its invited members unless the history visibility is
+    /// `joined`. They are stored, and the devices of these users are tracked.
+    ///
+    /// If any user who received the current room key no longer should, for
+    /// example because they left the room, the room key is discarded, so that
+    /// a new one is created the next time one is shared.
+    ///
+    /// The events of rooms which are not encrypted are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - the room the events belong to.
+    /// * `events` - the events, as an array of plain objects or a JSON-encoded
+    ///   string. The events other than state events are ignored.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<boolean>`: whether the room key of the room was discarded.
+    #[wasm_bindgen(js_name = "receiveRoomStateEvents")]
+    pub async fn receive_room_state_events(
+        &self,
+        room_id: &identifiers::RoomId,
+        events: JsValue,
+    ) -> Result<bool, JsError> {
+        let events: Vec<Raw<sync_events::RoomEvent>> = json::raw_from_string_or_object(events)?;
+        let me = self.writable_inner()?;
+
+        if me.room_settings(&room_id.inner).await?.is_none() {
+            return Ok(false);
+        }
+
+        let mut changes = MembershipChanges::default();
+        for event in events.iter().filter_map(|event| event.deserialize().ok()) {
+            changes.add(&event);
+        }
+
+        Ok(changes.apply(&me, &self.room_members_locks, &room_id.inner).await?)
+    }
+
+    /// Get the members of a room who receive its room keys when {@link
+    /// OlmMachine.shareRoomKey} is called without users, as tracked with
+    /// {@link OlmMachine.receiveRoomStateEvents}.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<UserId[]>`
+    #[wasm_bindgen(js_name = "getRoomKeyRecipients")]
+    pub async fn get_room_key_recipients(
+        &self,
+        room_id: &identifiers::RoomId,
+    ) -> Result<Array, JsError> {
+        Ok(RoomMembers::load_recipients(&self.inner(), &room_id.inner)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|user_id| JsValue::from(identifiers::UserId::from(user_id)))
+            .collect())
+    }
+
+    /// Share a room key with users in a room, like {@link
+    /// OlmMachine.shareRoomKey}, and report what happened to it for each of
+    /// their devices: whether it was shared, withheld (and with which code),
+    /// or skipped.
+    ///
+    /// Note: Care should be taken that only one such request at a
+    /// time is in flight for the same room, e.g. using a lock.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<RoomKeyShareReport>`
+    ///
+    /// If `users` is `undefined`, the room key is shared with the members of
+    /// the room tracked with {@link OlmMachine.receiveRoomStateEvents}, and
+    /// this fails if none were. Only the members of the state events passed
+    /// so far are known: with lazy-loaded members, the full member list, for
+    /// example from `/members`, must be passed first, or some members will
+    /// not receive the room key.
+    ///
+    /// Items inside `users` will be invalidated by this method. Be careful not
+    /// to use the `UserId`s after this method has been called.
+    #[wasm_bindgen(js_name = "shareRoomKeyWithReport")]
+    pub async fn share_room_key_with_report(
+        &self,
+        room_id: &identifiers::RoomId,
+        users: Option<Vec<identifiers::UserId>>,
+        encryption_settings: &encryption::EncryptionSettings,
+    ) -> Result<room_key_sharing::RoomKeyShareReport, JsError> {
+        let me = self.writable_inner()?;
+        let users = match users {
+            Some(users) => users.into_iter().map(|user| user.inner).collect::<Vec<_>>(),
+            None => RoomMembers::load_tracked_recipients(&me, &room_id.inner)
+                .await
+                .map_err(|e| JsError::new(&e.to_string()))?,
+        };
+        let encryption_settings =
+            matrix_sdk_crypto::olm::EncryptionSettings::from(encryption_settings);
+
+        let requests = me
+            .share_room_key(
+                &room_id.inner,
+                users.iter().map(AsRef::as_ref),
+                encryption_settings.clone(),
+            )
+            .await?;
+
+        room_key_sharing::RoomKeyShareReport::for_users(
+            &me,
+            &room_id.inner,
+            &users,
+            &requests,
+            &encryption_settings,
+        )
+        .await
+        .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Get the outbound group session currently used to encrypt the messages
+    /// of the given room, to find out who can read them.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<OutboundGroupSessionInfo|undefined>`: `undefined` if no room
+    /// key was shared in the room yet.
+    #[wasm_bindgen(js_name = "getOutboundGroupSession")]
+    pub async fn get_outbound_group_session(
+        &self,
+        room_id: &identifiers::RoomId,
+    ) -> Result<Option<olm::OutboundGroupSessionInfo>, JsError> {
+        let Some(session) = self.inner().store().get_outbound_group_session(&room_id.inner).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(olm::OutboundGroupSessionInfo::new(&session).await))
     }
 
     /// Generate an "out-of-band" key query request for the given set of users.
@@ -764,11 +1487,90 @@ impl OlmMachine {
         let users = users.iter().map(|user| user.inner.clone()).collect::<Vec<_>>();
 
         let (request_id, request) =
-            self.inner.query_keys_for_users(users.iter().map(AsRef::as_ref));
+            self.writable_inner()?.query_keys_for_users(users.iter().map(AsRef::as_ref));
 
         Ok(requests::KeysQueryRequest::try_from((request_id.to_string(), &request))?)
     }
 
+    /// Get the Olm sessions we have with the given device, to check their
+    /// health when to-device messages from the device fail to decrypt.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<OlmSessionInfo[]>`: the sessions with the device, the most
+    /// recently used first. It is empty if the device, or its Curve25519 key,
+    /// is not known.
+    #[wasm_bindgen(js_name = "getOlmSessions")]
+    pub async fn get_olm_sessions(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> Result<Array, JsError> {
+        let me = self.inner();
+        let Some(curve_key) = me
+            .get_device(&user_id.inner, &device_id.inner, None)
+            .await?
+            .and_then(|device| device.curve25519_key())
+        else {
+            return Ok(Array::new());
+        };
+
+        let mut sessions =
+            (**me.store()).get_sessions(&curve_key.to_base64()).await?.unwrap_or_default();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_use_time));
+
+        Ok(sessions
+            .iter()
+            .map(|session| JsValue::from(olm::OlmSessionInfo::from(session)))
+            .collect())
+    }
+
+    /// Establish a new Olm session with the given device, when the existing
+    /// ones are broken ("wedged"): for example, when room keys stop arriving
+    /// from the device, or its to-device messages fail to decrypt with {@link
+    /// OlmDecryptionErrorCode.BadMessage}.
+    ///
+    /// This returns a request claiming a one-time key of the device, which
+    /// should be sent, and its response passed back with {@link
+    /// OlmMachine.markRequestAsSent}. A new session is then created from the
+    /// one-time key, and an `m.dummy` event is queued in {@link
+    /// OlmMachine.outgoingRequests} so that the device learns about the new
+    /// session. If the response holds no valid one-time key for the device,
+    /// no session is created, and {@link OlmMachine.markRequestAsSent} fails
+    /// once it has processed the rest of the response.
+    ///
+    /// The claims awaiting a response and the `m.dummy` events not sent yet
+    /// are only kept in memory: they are lost if the machine is closed or
+    /// reloaded (see {@link acquireStoreLock}), in which case this method
+    /// should be called again.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<KeysClaimRequest|undefined>`: `undefined` if the device is not
+    /// known.
+    #[wasm_bindgen(js_name = "forceNewOlmSession")]
+    pub async fn force_new_olm_session(
+        &self,
+        user_id: &identifiers::UserId,
+        device_id: &identifiers::DeviceId,
+    ) -> Result<Option<requests::KeysClaimRequest>, JsError> {
+        let me = self.writable_inner()?;
+        let (user_id, device_id) = (user_id.inner.clone(), device_id.inner.clone());
+
+        if me.get_device(&user_id, &device_id, None).await?.is_none() {
+            return Ok(None);
+        }
+
+        let request = RumaKeysClaimRequest::new(BTreeMap::from([(
+            user_id.clone(),
+            BTreeMap::from([(device_id.clone(), OneTimeKeyAlgorithm::SignedCurve25519)]),
+        )]));
+        let request_id = TransactionId::new();
+        self.unwedging.add_claim(request_id.clone(), user_id, device_id);
+
+        Ok(Some(requests::KeysClaimRequest::try_from((request_id.to_string(), &request))?))
+    }
+
     /// Get the a key claiming request for the user/device pairs that
     /// we are missing Olm sessions for.
     ///
@@ -801,9 +1603,15 @@ impl OlmMachine {
     pub fn get_missing_sessions(&self, users: Vec<identifiers::UserId>) -> Promise {
         let users = users.iter().map(|user| user.inner.clone()).collect::<Vec<_>>();
 
-        let me = self.inner.clone();
+        let me = self.writable_inner();
+        let backing_off = self.backoff.is_backing_off(requests::RequestType::KeysClaim);
 
         future_to_promise(async move {
+            let me = me?;
+            if backing_off {
+                return Ok(JsValue::NULL);
+            }
+
             match me.get_missing_sessions(users.iter().map(AsRef::as_ref)).await? {
                 Some((transaction_id, keys_claim_request)) => {
                     Ok(JsValue::from(requests::KeysClaimRequest::try_from((
@@ -842,10 +1650,12 @@ impl OlmMachine {
         let user_id = user_id.inner.clone();
         let timeout_duration = timeout_secs.map(Duration::from_secs_f64);
 
-        let me = self.inner.clone();
+        let me = self.inner();
+        let read_only = self.read_only;
 
         future_to_promise::<_, device::UserDevices>(async move {
-            Ok(me.get_user_devices(&user_id, timeout_duration).await.map(Into::into)?)
+            let inner = me.get_user_devices(&user_id, timeout_duration).await?;
+            Ok(device::UserDevices { inner, read_only })
         })
     }
 
@@ -878,10 +1688,14 @@ impl OlmMachine {
         let device_id = device_id.inner.clone();
         let timeout_duration = timeout_secs.map(Duration::from_secs_f64);
 
-        let me = self.inner.clone();
+        let me = self.inner();
+        let read_only = self.read_only;
 
         future_to_promise::<_, Option<device::Device>>(async move {
-            Ok(me.get_device(&user_id, &device_id, timeout_duration).await?.map(Into::into))
+            Ok(me
+                .get_device(&user_id, &device_id, timeout_duration)
+                .await?
+                .map(|inner| device::Device { inner, read_only }))
         })
     }
 
@@ -898,7 +1712,7 @@ impl OlmMachine {
         user_id: &identifiers::UserId,
         flow_id: &str,
     ) -> Result<JsValue, JsError> {
-        self.inner
+        self.inner()
             .get_verification(&user_id.inner, flow_id)
             .map(verification::Verification)
             .map(JsValue::try_from)
@@ -913,13 +1727,13 @@ impl OlmMachine {
         user_id: &identifiers::UserId,
         flow_id: &str,
     ) -> Option<verification::VerificationRequest> {
-        self.inner.get_verification_request(&user_id.inner, flow_id).map(Into::into)
+        self.inner().get_verification_request(&user_id.inner, flow_id).map(Into::into)
     }
 
     /// Get all the verification requests of a given user.
     #[wasm_bindgen(js_name = "getVerificationRequests")]
     pub fn get_verification_requests(&self, user_id: &identifiers::UserId) -> Array {
-        self.inner
+        self.inner()
             .get_verification_requests(&user_id.inner)
             .into_iter()
             .map(verification::VerificationRequest::from)
@@ -930,24 +1744,67 @@ impl OlmMachine {
     /// Receive a verification event.
     ///
     /// This method can be used to pass verification events that are happening
-    /// in rooms to the `OlmMachine`. The event should be in the decrypted form.
+    /// in rooms to the `OlmMachine`. The event should be in the decrypted form,
+    /// either as a JSON-encoded string or as a plain object.
     #[wasm_bindgen(js_name = "receiveVerificationEvent")]
     pub fn receive_verification_event(
         &self,
-        event: &str,
+        event: JsValue,
         room_id: &identifiers::RoomId,
     ) -> Result<Promise, JsError> {
         let room_id = room_id.inner.clone();
-        let event: ruma::events::AnySyncMessageLikeEvent = serde_json::from_str(event)?;
+        let event: ruma::events::AnySyncMessageLikeEvent = json::raw_from_string_or_object(event)?;
         let event = event.into_full_event(room_id);
 
-        let me = self.inner.clone();
+        let me = self.writable_inner()?;
 
         Ok(future_to_promise(async move {
             Ok(me.receive_verification_event(&event).await.map(|_| JsValue::UNDEFINED)?)
         }))
     }
 
+    /// Get the inbound group session, or room key, with the given ID in the
+    /// given room.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<InboundGroupSession|undefined>`: `undefined` if we don't have
+    /// the room key.
+    #[wasm_bindgen(js_name = "getInboundGroupSession")]
+    pub async fn get_inbound_group_session(
+        &self,
+        room_id: &identifiers::RoomId,
+        session_id: String,
+    ) -> Result<Option<olm::InboundGroupSession>, JsError> {
+        let session =
+            self.inner().store().get_inbound_group_session(&room_id.inner, &session_id).await?;
+
+        Ok(session.map(Into::into))
+    }
+
+    /// Get all the inbound group sessions, or room keys, we have for the given
+    /// room.
+    ///
+    /// This goes through all the room keys in the store, so should not be
+    /// called often.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<InboundGroupSession[]>`
+    #[wasm_bindgen(js_name = "getInboundGroupSessions")]
+    pub async fn get_inbound_group_sessions(
+        &self,
+        room_id: &identifiers::RoomId,
+    ) -> Result<Array, JsError> {
+        let sessions = self.inner().store().get_inbound_group_sessions().await?;
+
+        Ok(sessions
+            .into_iter()
+            .filter(|session| session.room_id() == room_id.inner)
+            .map(|session| JsValue::from(olm::InboundGroupSession::from(session)))
+            .collect())
+    }
+
     /// Export the keys that match the given predicate.
     ///
     /// `predicate` is a closure that will be called for every known
@@ -959,7 +1816,7 @@ impl OlmMachine {
     /// JSON-encoded array of ExportedRoomKey objects.
     #[wasm_bindgen(js_name = "exportRoomKeys")]
     pub fn export_room_keys(&self, predicate: Function) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move {
             stream_to_json_array(pin!(
@@ -998,7 +1855,7 @@ impl OlmMachine {
         exported_room_keys: &str,
         progress_listener: Function,
     ) -> Result<Promise, JsError> {
-        let me = self.inner.clone();
+        let me = self.writable_inner()?;
         let exported_room_keys = serde_json::from_str(exported_room_keys)?;
 
         Ok(future_to_promise(async move {
@@ -1030,7 +1887,7 @@ impl OlmMachine {
         exported_room_keys: &str,
         progress_listener: Function,
     ) -> Result<Promise, JsError> {
-        let me = self.inner.clone();
+        let me = self.writable_inner()?;
         let exported_room_keys = serde_json::from_str(exported_room_keys)?;
 
         Ok(future_to_promise(async move {
@@ -1067,7 +1924,7 @@ impl OlmMachine {
         progress_listener: Option<Function>,
         backup_version: String,
     ) -> Result<Promise, JsValue> {
-        let me = self.inner.clone();
+        let me = self.writable_inner().map_err(JsError::from)?;
 
         // convert the js-side data into rust data
         let mut keys = Vec::new();
@@ -1133,10 +1990,11 @@ impl OlmMachine {
         decryption_key: &BackupDecryptionKey,
         version: String,
     ) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
         let inner_key = decryption_key.inner.clone();
 
         future_to_promise(async move {
+            let me = me?;
             me.backup_machine().save_decryption_key(Some(inner_key), Some(version)).await?;
             Ok(JsValue::UNDEFINED)
         })
@@ -1146,7 +2004,7 @@ impl OlmMachine {
     /// Returns a `Promise` for {@link BackupKeys}.
     #[wasm_bindgen(js_name = "getBackupKeys")]
     pub fn get_backup_keys(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move {
             let inner = me.backup_machine().get_backup_keys().await?;
@@ -1178,11 +2036,11 @@ impl OlmMachine {
     pub fn verify_backup(&self, backup_info: JsValue) -> Result<Promise, JsError> {
         let backup_info: RoomKeyBackupInfo = serde_wasm_bindgen::from_value(backup_info)?;
 
-        let me = self.inner.clone();
+        let me = self.inner();
 
         Ok(future_to_promise(async move {
             let result = me.backup_machine().verify_backup(backup_info, false).await?;
-            Ok(SignatureVerification { inner: result })
+            Ok(SignatureVerification::from(result))
         }))
     }
 
@@ -1204,7 +2062,7 @@ impl OlmMachine {
         let backup_key = MegolmV1BackupKey::from_base64(&public_key_base_64)?;
         backup_key.set_version(version);
 
-        let me = self.inner.clone();
+        let me = self.writable_inner()?;
 
         Ok(future_to_promise(async move {
             me.backup_machine().enable_backup_v1(backup_key).await?;
@@ -1220,7 +2078,7 @@ impl OlmMachine {
     /// Returns `Promise<bool>`.
     #[wasm_bindgen(js_name = "isBackupEnabled")]
     pub fn is_backup_enabled(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move {
             let enabled = me.backup_machine().enabled().await;
@@ -1236,9 +2094,10 @@ impl OlmMachine {
     /// Returns `Promise<void>`.
     #[wasm_bindgen(js_name = "disableBackup")]
     pub fn disable_backup(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
 
         future_to_promise(async move {
+            let me = me?;
             me.backup_machine().disable_backup().await?;
             Ok(JsValue::UNDEFINED)
         })
@@ -1250,9 +2109,15 @@ impl OlmMachine {
     /// Returns an optional {@link KeysBackupRequest}.
     #[wasm_bindgen(js_name = "backupRoomKeys")]
     pub fn backup_room_keys(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
+        let backing_off = self.backoff.is_backing_off(requests::RequestType::KeysBackup);
 
         future_to_promise(async move {
+            let me = me?;
+            if backing_off {
+                return Ok(None);
+            }
+
             match me.backup_machine().backup().await? {
                 Some((transaction_id, keys_backup_request)) => {
                     Ok(Some(requests::KeysBackupRequest::try_from((
@@ -1270,7 +2135,7 @@ impl OlmMachine {
     /// Returns a {@link RoomKeyCounts}.
     #[wasm_bindgen(js_name = "roomKeyCounts")]
     pub fn room_key_counts(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.inner();
         future_to_promise::<_, RoomKeyCounts>(async move {
             Ok(me.backup_machine().room_key_counts().await?.into())
         })
@@ -1321,22 +2186,7 @@ impl OlmMachine {
     /// of {@link RoomKeyInfo}) and returns a Promise.
     #[wasm_bindgen(js_name = "registerRoomKeyUpdatedCallback")]
     pub async fn register_room_key_updated_callback(&self, callback: Function) {
-        let stream = self.inner.store().room_keys_received_stream();
-
-        copy_stream_to_callback(
-            stream,
-            |input| match input {
-                Ok(keys) => iter::once(
-                    keys.into_iter().map(RoomKeyInfo::from).map(JsValue::from).collect::<Array>(),
-                ),
-                Err(e) => {
-                    warn!("Error reading  room_keys_received_stream {:?}", e);
-                    iter::once(Array::new())
-                }
-            },
-            callback,
-            "room-key-received",
-        );
+        self.register_callback(CallbackKind::RoomKeyUpdated, callback);
     }
 
     /// Register a callback which will be called whenever we receive a
@@ -1346,22 +2196,7 @@ impl OlmMachine {
     /// of {@link RoomKeyWithheldInfo}) and returns a Promise.
     #[wasm_bindgen(js_name = "registerRoomKeysWithheldCallback")]
     pub async fn register_room_keys_withheld_callback(&self, callback: Function) {
-        let stream = self.inner.store().room_keys_withheld_received_stream();
-
-        copy_stream_to_callback(
-            stream,
-            |input| {
-                iter::once(
-                    input
-                        .into_iter()
-                        .map(RoomKeyWithheldInfo::from)
-                        .map(JsValue::from)
-                        .collect::<Array>(),
-                )
-            },
-            callback,
-            "room-key-withheld",
-        );
+        self.register_callback(CallbackKind::RoomKeysWithheld, callback);
     }
 
     /// Register a callback which will be called whenever there is an update to
@@ -1371,20 +2206,7 @@ impl OlmMachine {
     /// UserId}) and returns a Promise.
     #[wasm_bindgen(js_name = "registerUserIdentityUpdatedCallback")]
     pub async fn register_user_identity_updated_callback(&self, callback: Function) {
-        let stream = self.inner.store().identities_stream_raw();
-
-        copy_stream_to_callback(
-            stream,
-            |(identity_updates, _)| {
-                identity_updates
-                    .new
-                    .into_iter()
-                    .chain(identity_updates.changed.into_iter())
-                    .map(|update| identifiers::UserId::from(update.user_id().to_owned()))
-            },
-            callback,
-            "user-identity-updated",
-        );
+        self.register_callback(CallbackKind::UserIdentityUpdated, callback);
     }
 
     /// Register a callback which will be called whenever there is an update to
@@ -1394,27 +2216,7 @@ impl OlmMachine {
     /// of user IDs as strings) and returns a Promise.
     #[wasm_bindgen(js_name = "registerDevicesUpdatedCallback")]
     pub async fn register_devices_updated_callback(&self, callback: Function) {
-        let stream = self.inner.store().identities_stream_raw();
-
-        fn mapper(changes: (IdentityChanges, DeviceChanges)) -> iter::Once<Array> {
-            let (_, device_updates) = changes;
-
-            // get the user IDs of all the devices that have changed
-            let updated_chain = device_updates
-                .new
-                .into_iter()
-                .chain(device_updates.changed.into_iter())
-                .chain(device_updates.deleted.into_iter());
-
-            // put them in a set to make them unique
-            let updated_users: HashSet<String> =
-                HashSet::from_iter(updated_chain.map(|device| device.user_id().to_string()));
-
-            // ... and collect to a JS Array
-            iter::once(updated_users.into_iter().map(JsValue::from).collect())
-        }
-
-        copy_stream_to_callback(stream, mapper, callback, "device-updated");
+        self.register_callback(CallbackKind::DevicesUpdated, callback);
     }
 
     /// Register a callback which will be called whenever a secret
@@ -1442,16 +2244,17 @@ impl OlmMachine {
     /// `delete_secrets_from_inbox`.
     #[wasm_bindgen(js_name = "registerReceiveSecretCallback")]
     pub async fn register_receive_secret_callback(&self, callback: Function) {
-        let stream = self.inner.store().secrets_stream();
-        // fire up a promise chain which will call `callback` on each result from the
-        // stream
-        spawn_local(async move {
-            // Pin the stream to ensure it can be safely moved across threads
-            pin_mut!(stream);
-            while let Some(secret) = stream.next().await {
-                send_secret_gossip_to_callback(&callback, &secret).await;
-            }
-        });
+        self.register_callback(CallbackKind::ReceiveSecret, callback);
+    }
+
+    /// Register a callback which will be called whenever a request is marked
+    /// as failed with {@link OlmMachine.markRequestAsFailed}.
+    ///
+    /// `callback` should be a function that takes a single argument (a {@link
+    /// RequestFailure}) and returns nothing.
+    #[wasm_bindgen(js_name = "registerRequestFailedCallback")]
+    pub fn register_request_failed_callback(&self, callback: Function) {
+        self.backoff.set_callback(callback);
     }
 
     /// Get all the secrets with the given secret_name we have currently
@@ -1476,7 +2279,7 @@ impl OlmMachine {
     #[wasm_bindgen(js_name = "getSecretsFromInbox")]
     pub async fn get_secrets_from_inbox(&self, secret_name: String) -> Promise {
         let set = Set::new(&JsValue::UNDEFINED);
-        let me = self.inner.clone();
+        let me = self.inner();
 
         future_to_promise(async move {
             let name = SecretName::from(secret_name);
@@ -1497,8 +2300,9 @@ impl OlmMachine {
     /// * `secret_name` - The name of the secret to delete.
     #[wasm_bindgen(js_name = "deleteSecretsFromInbox")]
     pub async fn delete_secrets_from_inbox(&self, secret_name: String) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
         future_to_promise(async move {
+            let me = me?;
             let name = SecretName::from(secret_name);
             me.store().delete_secrets_from_inbox(&name).await?;
             Ok(JsValue::UNDEFINED)
@@ -1522,8 +2326,9 @@ impl OlmMachine {
     /// missing, and a request was generated.
     #[wasm_bindgen(js_name = "requestMissingSecretsIfNeeded")]
     pub async fn request_missing_secrets_if_needed(&self) -> Promise {
-        let me = self.inner.clone();
+        let me = self.writable_inner();
         future_to_promise(async move {
+            let me = me?;
             let has_missing_secrets = me.query_missing_secrets_from_other_sessions().await?;
             Ok(JsValue::from_bool(has_missing_secrets))
         })
@@ -1542,7 +2347,7 @@ impl OlmMachine {
         &self,
         room_id: &identifiers::RoomId,
     ) -> Result<JsValue, JsError> {
-        let result = self.inner.room_settings(&room_id.inner).await?;
+        let result = self.inner().room_settings(&room_id.inner).await?;
         Ok(result.map(RoomSettings::from).into())
     }
 
@@ -1561,14 +2366,127 @@ impl OlmMachine {
         room_id: &identifiers::RoomId,
         room_settings: &RoomSettings,
     ) -> Result<(), JsError> {
-        self.inner.set_room_settings(&room_id.inner, &room_settings.into()).await?;
+        self.writable_inner()?.set_room_settings(&room_id.inner, &room_settings.into()).await?;
         Ok(())
     }
 
-    /// Manage dehydrated devices
+    /// Store the encryption settings of the given room from its
+    /// `m.room.encryption` state event, instead of filling a {@link
+    /// RoomSettings} by hand: this is done by {@link
+    /// OlmMachine.receiveSyncResponse} for the rooms of the response.
+    ///
+    /// The event is rejected if it is not an `m.room.encryption` state event,
+    /// if its content is invalid, or if its algorithm is not supported. Its
+    /// `rotation_period_ms` and `rotation_period_msgs` are stored as the
+    /// rotation periods of the settings.
+    ///
+    /// The settings of a room cannot change once stored: if the room is
+    /// already encrypted, an event with another algorithm is rejected as a
+    /// downgrade, and the rotation periods of an event with the same
+    /// algorithm are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - the room the event belongs to.
+    /// * `event` - the state event, either as a JSON-encoded string or as a
+    ///   plain object.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<RoomSettings>`: the settings of the room.
+    #[wasm_bindgen(js_name = "receiveRoomEncryptionEvent")]
+    pub async fn receive_room_encryption_event(
+        &self,
+        room_id: &identifiers::RoomId,
+        event: JsValue,
+    ) -> Result<RoomSettings, JsError> {
+        let event: sync_events::RoomEvent = json::from_string_or_object(event)?;
+
+        room_settings::receive_encryption_event(&self.writable_inner()?, &room_id.inner, event)
+            .await
+            .map(RoomSettings::from)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Get the settings to pass to {@link OlmMachine.shareRoomKey} for the
+    /// given room, derived from its stored {@link RoomSettings} so that the
+    /// two cannot drift apart.
+    ///
+    /// The rotation periods which the room settings leave out get their
+    /// default values. The sharing strategy only shares with trusted devices
+    /// if {@link RoomSettings.onlyAllowTrustedDevices} is set, and with all
+    /// devices otherwise. The history visibility is the one tracked with
+    /// {@link OlmMachine.receiveRoomStateEvents}, or `shared` if none was
+    /// seen.
+    ///
+    /// # Returns
+    ///
+    /// `Promise<EncryptionSettings|undefined>`: `undefined` if the room is
+    /// not encrypted.
+    #[wasm_bindgen(js_name = "getEncryptionSettings")]
+    pub async fn get_encryption_settings(
+        &self,
+        room_id: &identifiers::RoomId,
+    ) -> Result<Option<encryption::EncryptionSettings>, JsError> {
+        Ok(room_settings::encryption_settings(&self.inner(), &room_id.inner)
+            .await?
+            .map(|settings| encryption::EncryptionSettings::from(&settings)))
+    }
+
+    /// Manage dehydrated devices. Not available on a read-only machine.
     #[wasm_bindgen(js_name = "dehydratedDevices")]
-    pub fn dehydrated_devices(&self) -> DehydratedDevices {
-        self.inner.dehydrated_devices().into()
+    pub fn dehydrated_devices(&self) -> Result<DehydratedDevices, JsError> {
+        Ok(self.writable_inner()?.dehydrated_devices().into())
+    }
+
+    /// Acquire the cross-process lock on the store backing this machine,
+    /// waiting for it to be released if it is held by another process, such
+    /// as another browser tab. See {@link StoreHandle.acquireLock}. Not
+    /// available on a read-only machine.
+    ///
+    /// If another holder of the lock has written to the store since this
+    /// machine last held it, the machine reloads its state from the store, and
+    /// any callbacks registered with the `register*Callback` methods are
+    /// moved over to the reloaded machine.
+    ///
+    /// The objects obtained from the machine before it was reloaded, such as
+    /// {@link Device}s, {@link VerificationRequest}s or {@link
+    /// DehydratedDevices}, and the operations still in progress, can then no
+    /// longer access the store: they fail, rather than overwriting the
+    /// reloaded state with their stale one. Get them again from the machine.
+    ///
+    /// The lock is advisory: the methods of the machine do not check that it
+    /// is held. It only protects the store if every process writing to it
+    /// acquires the lock before doing so.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_backoff_ms` - the maximum time to wait between two attempts to
+    ///   take the lock. Once reached, the attempt fails. Defaults to one
+    ///   second.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the machine was reloaded, `false` otherwise.
+    #[wasm_bindgen(js_name = "acquireStoreLock")]
+    pub async fn acquire_store_lock(&self, max_backoff_ms: Option<u32>) -> Result<bool, JsError> {
+        // Acquiring the lock writes to the store.
+        self.writable_inner()?;
+
+        let reload = self.store_handle.acquire_lock(max_backoff_ms).await?;
+
+        if reload {
+            self.reload().await?;
+        }
+
+        Ok(reload)
+    }
+
+    /// Release the cross-process lock on the store backing this machine,
+    /// taken by {@link acquireStoreLock}.
+    #[wasm_bindgen(js_name = "releaseStoreLock")]
+    pub fn release_store_lock(&self) {
+        self.store_handle.release_lock();
     }
 
     /// Shut down the `OlmMachine`.
@@ -1581,6 +2499,231 @@ impl OlmMachine {
 }
 
 impl OlmMachine {
+    /// Get the underlying machine.
+    fn inner(&self) -> matrix_sdk_crypto::OlmMachine {
+        self.inner.borrow().clone()
+    }
+
+    /// Get the outgoing requests, except those of a type for which we are
+    /// backing off.
+    pub(crate) async fn pending_outgoing_requests(
+        &self,
+    ) -> Result<Vec<OutgoingRequest>, anyhow::Error> {
+        Ok(self
+            .writable_inner()?
+            .outgoing_requests()
+            .await?
+            .into_iter()
+            .chain(self.unwedging.outgoing_requests())
+            .filter(|request| {
+                !self.backoff.is_backing_off(requests::outgoing_request_type(request.request()))
+            })
+            .collect())
+    }
+
+    /// Pass the response to a request back to the underlying machine.
+    pub(crate) async fn receive_response(
+        &self,
+        request_id: &TransactionId,
+        request_type: requests::RequestType,
+        response: &responses::OwnedResponse,
+    ) -> Result<(), anyhow::Error> {
+        let me = self.writable_inner()?;
+        let claim = match request_type {
+            requests::RequestType::KeysClaim => self.unwedging.take_claim(&me, request_id).await?,
+            _ => None,
+        };
+
+        me.mark_request_as_sent(request_id, response).await?;
+        self.backoff.record_success(request_type);
+
+        if let Some(claim) = claim {
+            self.unwedging.receive_claim_response(&me, claim).await?;
+        }
+        if request_type == requests::RequestType::ToDevice {
+            self.unwedging.mark_request_as_sent(request_id);
+        }
+
+        Ok(())
+    }
+
+    /// Get the underlying machine, for an operation which modifies its state,
+    /// failing if this machine is read-only.
+    fn writable_inner(&self) -> Result<matrix_sdk_crypto::OlmMachine, ReadOnlyError> {
+        ReadOnlyError::check(self.read_only)?;
+        Ok(self.inner())
+    }
+
+    /// Replace the underlying machine with a fresh one, loaded from the
+    /// store, so that any state that another holder of the store lock has
+    /// written is picked up.
+    ///
+    /// The old machine is retired: its view of the store fails any further
+    /// access, so that the futures and objects still holding a clone of it
+    /// cannot overwrite the state of the new one, and the callbacks are
+    /// moved over to the new machine.
+    async fn reload(&self) -> Result<(), CryptoStoreError> {
+        let old = self.inner();
+        let new_store = self.store_handle.fenced();
+        let new = matrix_sdk_crypto::OlmMachine::with_store(
+            old.user_id(),
+            old.device_id(),
+            new_store.clone(),
+            None,
+        )
+        .await?;
+
+        // These settings are only kept in memory.
+        new.set_room_key_requests_enabled(old.are_room_key_requests_enabled());
+        new.set_room_key_forwarding_enabled(old.is_room_key_forwarding_enabled());
+
+        let subscriptions = self
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|(kind, callback)| Self::subscribe_callback(&new, *kind, callback.clone()))
+            .collect();
+
+        for subscription in self.subscriptions.replace(subscriptions) {
+            subscription.abort();
+        }
+
+        self.inner_store.replace(new_store).retire();
+        *self.inner.borrow_mut() = new;
+
+        Ok(())
+    }
+
+    /// Shared helper for the `register_*_callback` methods.
+    ///
+    /// Remembers the callback, so that it can be registered again if the
+    /// underlying machine is reloaded, and subscribes it to the relevant
+    /// stream.
+    fn register_callback(&self, kind: CallbackKind, callback: Function) {
+        self.callbacks.borrow_mut().push((kind, callback.clone()));
+
+        let subscription = Self::subscribe_callback(&self.inner(), kind, callback);
+        self.subscriptions.borrow_mut().push(subscription);
+    }
+
+    /// Subscribe a callback registered via one of the `register_*_callback`
+    /// methods to the relevant stream of `inner`.
+    ///
+    /// Returns a handle to cancel the subscription.
+    fn subscribe_callback(
+        inner: &matrix_sdk_crypto::OlmMachine,
+        kind: CallbackKind,
+        callback: Function,
+    ) -> AbortHandle {
+        match kind {
+            CallbackKind::RoomKeyUpdated => {
+                let (stream, subscription) =
+                    stream::abortable(inner.store().room_keys_received_stream());
+
+                copy_stream_to_callback(
+                    stream,
+                    |input| match input {
+                        Ok(keys) => iter::once(
+                            keys.into_iter()
+                                .map(RoomKeyInfo::from)
+                                .map(JsValue::from)
+                                .collect::<Array>(),
+                        ),
+                        Err(e) => {
+                            warn!("Error reading  room_keys_received_stream {:?}", e);
+                            iter::once(Array::new())
+                        }
+                    },
+                    callback,
+                    "room-key-received",
+                );
+
+                subscription
+            }
+            CallbackKind::RoomKeysWithheld => {
+                let (stream, subscription) =
+                    stream::abortable(inner.store().room_keys_withheld_received_stream());
+
+                copy_stream_to_callback(
+                    stream,
+                    |input| {
+                        iter::once(
+                            input
+                                .into_iter()
+                                .map(RoomKeyWithheldInfo::from)
+                                .map(JsValue::from)
+                                .collect::<Array>(),
+                        )
+                    },
+                    callback,
+                    "room-key-withheld",
+                );
+
+                subscription
+            }
+            CallbackKind::UserIdentityUpdated => {
+                let (stream, subscription) =
+                    stream::abortable(inner.store().identities_stream_raw());
+
+                copy_stream_to_callback(
+                    stream,
+                    |(identity_updates, _)| {
+                        identity_updates
+                            .new
+                            .into_iter()
+                            .chain(identity_updates.changed.into_iter())
+                            .map(|update| identifiers::UserId::from(update.user_id().to_owned()))
+                    },
+                    callback,
+                    "user-identity-updated",
+                );
+
+                subscription
+            }
+            CallbackKind::DevicesUpdated => {
+                let (stream, subscription) =
+                    stream::abortable(inner.store().identities_stream_raw());
+
+                fn mapper(changes: (IdentityChanges, DeviceChanges)) -> iter::Once<Array> {
+                    let (_, device_updates) = changes;
+
+                    // get the user IDs of all the devices that have changed
+                    let updated_chain = device_updates
+                        .new
+                        .into_iter()
+                        .chain(device_updates.changed.into_iter())
+                        .chain(device_updates.deleted.into_iter());
+
+                    // put them in a set to make them unique
+                    let updated_users: HashSet<String> = HashSet::from_iter(
+                        updated_chain.map(|device| device.user_id().to_string()),
+                    );
+
+                    // ... and collect to a JS Array
+                    iter::once(updated_users.into_iter().map(JsValue::from).collect())
+                }
+
+                copy_stream_to_callback(stream, mapper, callback, "device-updated");
+
+                subscription
+            }
+            CallbackKind::ReceiveSecret => {
+                let (stream, subscription) = stream::abortable(inner.store().secrets_stream());
+                // fire up a promise chain which will call `callback` on each result from the
+                // stream
+                spawn_local(async move {
+                    // Pin the stream to ensure it can be safely moved across threads
+                    pin_mut!(stream);
+                    while let Some(secret) = stream.next().await {
+                        send_secret_gossip_to_callback(&callback, &secret).await;
+                    }
+                });
+
+                subscription
+            }
+        }
+    }
+
     /// Shared helper for `import_exported_room_keys` and `import_room_keys`.
     ///
     /// Wraps the progress listener in a Rust closure and runs
@@ -1679,6 +2822,38 @@ pub(crate) async fn promise_result_to_future(
     }
 }
 
+/// Convert the one-time key counts and unused fallback keys passed to
+/// {@link OlmMachine.receiveSyncChanges}.
+fn key_counts_from_js(
+    one_time_keys_counts: &Map,
+    unused_fallback_keys: Option<Set>,
+) -> (BTreeMap<OneTimeKeyAlgorithm, UInt>, Option<Vec<OneTimeKeyAlgorithm>>) {
+    let one_time_keys_counts = one_time_keys_counts
+        .entries()
+        .into_iter()
+        .filter_map(|js_value| {
+            let pair = Array::from(&js_value.ok()?);
+            let (key, value) = (
+                OneTimeKeyAlgorithm::from(pair.at(0).as_string()?),
+                UInt::new(pair.at(1).as_f64()? as u64)?,
+            );
+
+            Some((key, value))
+        })
+        .collect();
+
+    // Convert the unused_fallback_keys JS Set to a `Vec<OneTimeKeyAlgorithm>`
+    let unused_fallback_keys = unused_fallback_keys.map(|fallback_keys| {
+        fallback_keys
+            .values()
+            .into_iter()
+            .filter_map(|js_value| Some(OneTimeKeyAlgorithm::from(js_value.ok()?.as_string()?)))
+            .collect()
+    });
+
+    (one_time_keys_counts, unused_fallback_keys)
+}
+
 async fn stream_to_json_array<T, S>(mut stream: Pin<&mut S>) -> Result<String, anyhow::Error>
 where
     T: Serialize,
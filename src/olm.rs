@@ -1,8 +1,21 @@
 //! Olm types.
 
+use std::{collections::BTreeMap, time::Duration};
+
+use js_sys::Array;
+use matrix_sdk_common::ruma::{OwnedDeviceId, OwnedUserId};
+use matrix_sdk_crypto::olm::{SenderData, ShareInfo};
 use wasm_bindgen::prelude::*;
 
-use crate::{identifiers, impl_from_to_inner, vodozemac::Curve25519PublicKey};
+use crate::{
+    encryption::{EncryptionAlgorithm, EncryptionSettings},
+    identifiers, impl_from_to_inner,
+    vodozemac::Curve25519PublicKey,
+};
+
+/// The shortest rotation period the underlying machine uses for outbound
+/// group sessions, whatever their settings say.
+const MINIMUM_ROTATION_PERIOD: Duration = Duration::from_secs(60 * 60);
 
 /// Struct representing the state of our private cross signing keys,
 /// it shows which private cross signing keys we have locally stored.
@@ -76,4 +89,305 @@ impl InboundGroupSession {
     pub fn has_been_imported(&self) -> bool {
         self.inner.has_been_imported()
     }
+
+    /// The index of the first message which this session can decrypt.
+    #[wasm_bindgen(getter, js_name = "firstKnownIndex")]
+    pub fn first_known_index(&self) -> u32 {
+        self.inner.first_known_index()
+    }
+
+    /// The encryption algorithm of this session.
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> EncryptionAlgorithm {
+        self.inner.algorithm().clone().into()
+    }
+
+    /// Has the session been backed up to the server-side key backup?
+    #[wasm_bindgen(getter, js_name = "backedUp")]
+    pub fn backed_up(&self) -> bool {
+        self.inner.backed_up()
+    }
+
+    /// Can the session be shared with users who are invited to the room in
+    /// the future, allowing them to read its history ([MSC3061])?
+    ///
+    /// [MSC3061]: https://github.com/matrix-org/matrix-spec-proposals/pull/3061
+    #[wasm_bindgen(getter, js_name = "sharedHistory")]
+    pub fn shared_history(&self) -> bool {
+        self.inner.shared_history()
+    }
+
+    /// How much we know about, and trust, the device which created this
+    /// session.
+    #[wasm_bindgen(getter, js_name = "senderDataType")]
+    pub fn sender_data_type(&self) -> SenderDataType {
+        self.inner.sender_data_type().into()
+    }
+
+    /// The user who created this session, if the device which created it is
+    /// known.
+    #[wasm_bindgen(getter, js_name = "senderUserId")]
+    pub fn sender_user_id(&self) -> Option<identifiers::UserId> {
+        match &self.inner.sender_data {
+            SenderData::UnknownDevice { .. } => None,
+            SenderData::DeviceInfo { device_keys, .. } => Some(device_keys.user_id.clone().into()),
+            SenderData::VerificationViolation(known)
+            | SenderData::SenderUnverified(known)
+            | SenderData::SenderVerified(known) => Some(known.user_id.clone().into()),
+        }
+    }
+
+    /// The device which created this session, if it is known.
+    #[wasm_bindgen(getter, js_name = "senderDeviceId")]
+    pub fn sender_device_id(&self) -> Option<identifiers::DeviceId> {
+        match &self.inner.sender_data {
+            SenderData::UnknownDevice { .. } => None,
+            SenderData::DeviceInfo { device_keys, .. } => {
+                Some(device_keys.device_id.clone().into())
+            }
+            SenderData::VerificationViolation(known)
+            | SenderData::SenderUnverified(known)
+            | SenderData::SenderVerified(known) => known.device_id.clone().map(Into::into),
+        }
+    }
+}
+
+/// How much we know about the device which created an inbound group session,
+/// as reported by {@link InboundGroupSession.senderDataType}.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderDataType {
+    /// We have not found the device which created the session, or the device
+    /// we found does not own the session.
+    UnknownDevice,
+
+    /// We found the device which created the session, but not the
+    /// cross-signing identity of its user.
+    DeviceInfo,
+
+    /// The device which created the session is cross-signed by its user,
+    /// whose identity is not verified, although a previous identity of theirs
+    /// was.
+    VerificationViolation,
+
+    /// The device which created the session is cross-signed by its user,
+    /// whose identity is not verified.
+    SenderUnverified,
+
+    /// The device which created the session is cross-signed by its user,
+    /// whose identity is verified.
+    SenderVerified,
+}
+
+impl From<matrix_sdk_crypto::olm::SenderDataType> for SenderDataType {
+    fn from(value: matrix_sdk_crypto::olm::SenderDataType) -> Self {
+        use matrix_sdk_crypto::olm::SenderDataType::*;
+
+        match value {
+            UnknownDevice => Self::UnknownDevice,
+            DeviceInfo => Self::DeviceInfo,
+            VerificationViolation => Self::VerificationViolation,
+            SenderUnverified => Self::SenderUnverified,
+            SenderVerified => Self::SenderVerified,
+        }
+    }
+}
+
+/// Information on an Olm session with another device, as returned by
+/// {@link OlmMachine.getOlmSessions}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct OlmSessionInfo {
+    /// The unique identifier of the session.
+    #[wasm_bindgen(readonly, js_name = "sessionId")]
+    pub session_id: String,
+
+    /// The time, in milliseconds since the unix epoch, at which the session
+    /// was created.
+    #[wasm_bindgen(readonly, js_name = "creationTimeMs")]
+    pub creation_time_ms: f64,
+
+    /// The time, in milliseconds since the unix epoch, at which the session
+    /// was last used to encrypt or decrypt a message.
+    #[wasm_bindgen(readonly, js_name = "lastUseTimeMs")]
+    pub last_use_time_ms: f64,
+
+    /// Whether the session was created from a fallback key of the other
+    /// device, rather than a one-time key.
+    #[wasm_bindgen(readonly, js_name = "createdUsingFallbackKey")]
+    pub created_using_fallback_key: bool,
+}
+
+impl From<&matrix_sdk_crypto::Session> for OlmSessionInfo {
+    fn from(session: &matrix_sdk_crypto::Session) -> Self {
+        Self {
+            session_id: session.session_id().to_owned(),
+            creation_time_ms: u64::from(session.creation_time.get()) as f64 * 1000.,
+            last_use_time_ms: u64::from(session.last_use_time.get()) as f64 * 1000.,
+            created_using_fallback_key: session.created_using_fallback_key,
+        }
+    }
+}
+
+/// The state of an outbound group session for one device, as reported by
+/// {@link RoomKeyShareInfo.state}.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKeyShareState {
+    /// The room key was sent to the device.
+    Shared,
+
+    /// An `m.room_key.withheld` message was sent to the device instead of the
+    /// room key.
+    Withheld,
+
+    /// The room key, or the `m.room_key.withheld` message, is waiting to be
+    /// sent in a to-device request returned by {@link OlmMachine.shareRoomKey}.
+    Pending,
+}
+
+/// How an outbound group session was shared with one device.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct RoomKeyShareInfo {
+    /// The user the device belongs to.
+    #[wasm_bindgen(readonly, js_name = "userId")]
+    pub user_id: identifiers::UserId,
+
+    /// The device.
+    #[wasm_bindgen(readonly, js_name = "deviceId")]
+    pub device_id: identifiers::DeviceId,
+
+    /// Whether the room key was shared, withheld, or is still waiting to be
+    /// sent.
+    #[wasm_bindgen(readonly)]
+    pub state: RoomKeyShareState,
+
+    /// The message index from which the device can decrypt the messages, if
+    /// the room key was or is to be shared with it.
+    #[wasm_bindgen(readonly, js_name = "messageIndex")]
+    pub message_index: Option<u32>,
+
+    /// The withheld code, such as `m.unverified`, if the room key was or is to
+    /// be withheld from the device.
+    #[wasm_bindgen(readonly, js_name = "withheldCode")]
+    pub withheld_code: Option<String>,
+}
+
+impl RoomKeyShareInfo {
+    fn new(
+        user_id: &OwnedUserId,
+        device_id: &OwnedDeviceId,
+        share_info: &ShareInfo,
+        pending: bool,
+    ) -> Self {
+        let (state, message_index, withheld_code) = match share_info {
+            ShareInfo::Shared(shared) => {
+                (RoomKeyShareState::Shared, Some(shared.message_index), None)
+            }
+            ShareInfo::Withheld(code) => {
+                (RoomKeyShareState::Withheld, None, Some(code.as_str().to_owned()))
+            }
+        };
+
+        Self {
+            user_id: user_id.clone().into(),
+            device_id: device_id.clone().into(),
+            state: if pending { RoomKeyShareState::Pending } else { state },
+            message_index,
+            withheld_code,
+        }
+    }
+}
+
+/// Information on the outbound group session of a room, as returned by
+/// {@link OlmMachine.getOutboundGroupSession}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct OutboundGroupSessionInfo {
+    /// The unique identifier of the session.
+    #[wasm_bindgen(readonly, js_name = "sessionId")]
+    pub session_id: String,
+
+    /// The time, in milliseconds since the unix epoch, at which the session
+    /// was created.
+    #[wasm_bindgen(readonly, js_name = "creationTimeMs")]
+    pub creation_time_ms: f64,
+
+    /// The number of messages encrypted with the session.
+    #[wasm_bindgen(readonly, js_name = "messageCount")]
+    pub message_count: f64,
+
+    /// The time, in milliseconds since the unix epoch, after which the
+    /// session is rotated. It is rotated sooner if {@link
+    /// EncryptionSettings.rotationPeriodMessages} messages are encrypted with
+    /// it first.
+    ///
+    /// The rotation period of the settings is never shorter than an hour.
+    #[wasm_bindgen(readonly, js_name = "rotationDeadlineMs")]
+    pub rotation_deadline_ms: f64,
+
+    /// The settings the session was created with.
+    #[wasm_bindgen(readonly)]
+    pub settings: EncryptionSettings,
+
+    /// Whether the session was shared with all the devices it was to be
+    /// shared with, so that messages can be encrypted with it.
+    #[wasm_bindgen(readonly)]
+    pub shared: bool,
+
+    /// Whether the session expired, having reached its rotation deadline or
+    /// its number of messages, or was invalidated with {@link
+    /// OlmMachine.invalidateGroupSession}. A new session is created the next
+    /// time the room key is shared.
+    #[wasm_bindgen(readonly, js_name = "needsRotation")]
+    pub needs_rotation: bool,
+
+    /// How the session was shared with each device, by user then device ID.
+    ///
+    /// Typescript type: `RoomKeyShareInfo[]`.
+    #[wasm_bindgen(readonly)]
+    pub devices: Array,
+}
+
+impl OutboundGroupSessionInfo {
+    pub(crate) async fn new(session: &matrix_sdk_crypto::olm::OutboundGroupSession) -> Self {
+        let pickle = session.pickle().await;
+        let creation_time_ms = u64::from(pickle.creation_time.get()) as f64 * 1000.;
+        let rotation_period = pickle.settings.rotation_period.max(MINIMUM_ROTATION_PERIOD);
+
+        let mut share_infos: BTreeMap<_, _> = pickle
+            .shared_with_set
+            .iter()
+            .flat_map(|(user_id, devices)| {
+                devices.iter().map(move |(device_id, share_info)| {
+                    (
+                        (user_id, device_id),
+                        RoomKeyShareInfo::new(user_id, device_id, share_info, false),
+                    )
+                })
+            })
+            .collect();
+
+        for (_, pending) in pickle.requests.values() {
+            for (user_id, devices) in pending {
+                for (device_id, share_info) in devices {
+                    share_infos.entry((user_id, device_id)).or_insert_with(|| {
+                        RoomKeyShareInfo::new(user_id, device_id, share_info, true)
+                    });
+                }
+            }
+        }
+
+        Self {
+            session_id: session.session_id().to_owned(),
+            creation_time_ms,
+            message_count: pickle.message_count as f64,
+            rotation_deadline_ms: creation_time_ms + rotation_period.as_millis() as f64,
+            settings: (&*pickle.settings).into(),
+            shared: session.shared(),
+            needs_rotation: session.expired() || session.invalidated(),
+            devices: share_infos.into_values().map(JsValue::from).collect(),
+        }
+    }
 }
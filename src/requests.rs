@@ -1,16 +1,24 @@
 //! Types to handle requests.
 
-use std::time::Duration;
+use std::{fmt::Display, time::Duration};
 
-use js_sys::JsString;
+use js_sys::{JsString, Map};
 use matrix_sdk_common::ruma::{
-    api::client::{
-        dehydrated_device::put_dehydrated_device::unstable::Request as OriginalPutDehydratedDeviceRequest,
-        keys::{
-            claim_keys::v3::Request as OriginalKeysClaimRequest,
-            upload_keys::v3::Request as OriginalKeysUploadRequest,
-            upload_signatures::v3::Request as OriginalSignatureUploadRequest,
+    api::{
+        client::{
+            backup::add_backup_keys::v3::Request as RumaKeysBackupRequest,
+            dehydrated_device::put_dehydrated_device::unstable::Request as OriginalPutDehydratedDeviceRequest,
+            keys::{
+                claim_keys::v3::Request as OriginalKeysClaimRequest,
+                get_keys::v3::Request as RumaKeysQueryRequest,
+                upload_keys::v3::Request as OriginalKeysUploadRequest,
+                upload_signatures::v3::Request as OriginalSignatureUploadRequest,
+                upload_signing_keys::v3::Request as RumaUploadSigningKeysRequest,
+            },
+            message::send_message_event::v3::Request as RumaRoomMessageRequest,
+            to_device::send_event_to_device::v3::Request as RumaToDeviceRequest,
         },
+        MatrixVersion, Metadata, OutgoingRequest,
     },
     events::EventContent,
     exports::serde::ser::Error,
@@ -63,6 +71,24 @@ impl KeysUploadRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::KeysUpload
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &OriginalKeysUploadRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 /// Data for a request to the `/keys/query` API endpoint
@@ -99,6 +125,24 @@ impl KeysQueryRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::KeysQuery
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &RumaKeysQueryRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 /// Data for a request to the `/keys/claim` API endpoint
@@ -136,6 +180,24 @@ impl KeysClaimRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::KeysClaim
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &OriginalKeysClaimRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 /// Data for a request to the `/sendToDevice` API endpoint
@@ -188,6 +250,24 @@ impl ToDeviceRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::ToDevice
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &RumaToDeviceRequest::METADATA,
+            homeserver_url,
+            &[&String::from(&self.event_type), &String::from(&self.txn_id)],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 /// Data for a request to the `/keys/signatures/upload` API endpoint
@@ -226,6 +306,24 @@ impl SignatureUploadRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::SignatureUpload
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &OriginalSignatureUploadRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.signed_keys,
+        )
+    }
 }
 
 /// A customized owned request type for sending out room messages
@@ -279,6 +377,28 @@ impl RoomMessageRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::RoomMessage
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &RumaRoomMessageRequest::METADATA,
+            homeserver_url,
+            &[
+                &String::from(&self.room_id),
+                &String::from(&self.event_type),
+                &String::from(&self.txn_id),
+            ],
+            None,
+            access_token,
+            &self.content,
+        )
+    }
 }
 
 /// A request that will back up a batch of room keys to the server
@@ -316,6 +436,24 @@ impl KeysBackupRequest {
     pub fn request_type(&self) -> RequestType {
         RequestType::KeysBackup
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &RumaKeysBackupRequest::METADATA,
+            homeserver_url,
+            &[],
+            Some(("version", &String::from(&self.version))),
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 macro_rules! request {
@@ -518,9 +656,63 @@ pub fn outgoing_request_to_js_value(
     })
 }
 
+/// Build the HTTP request to send to the homeserver at `homeserver_url` for an
+/// `OutgoingRequest`.
+pub(crate) fn outgoing_request_to_http_request(
+    outgoing_request: &matrix_sdk_crypto::types::requests::OutgoingRequest,
+    homeserver_url: &str,
+    access_token: Option<String>,
+) -> Result<HttpRequest, JsError> {
+    let request_id = outgoing_request.request_id().to_string();
+
+    match outgoing_request.request() {
+        AnyOutgoingRequest::KeysUpload(request) => {
+            KeysUploadRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+
+        AnyOutgoingRequest::KeysQuery(request) => {
+            KeysQueryRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+
+        AnyOutgoingRequest::KeysClaim(request) => {
+            KeysClaimRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+
+        AnyOutgoingRequest::ToDeviceRequest(request) => {
+            ToDeviceRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+
+        AnyOutgoingRequest::SignatureUpload(request) => {
+            SignatureUploadRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+
+        AnyOutgoingRequest::RoomMessage(request) => {
+            RoomMessageRequest::try_from((request_id, request))?
+                .to_http_request(homeserver_url, access_token)
+        }
+    }
+}
+
+/// The type of an outgoing request.
+pub(crate) fn outgoing_request_type(outgoing_request: &AnyOutgoingRequest) -> RequestType {
+    match outgoing_request {
+        AnyOutgoingRequest::KeysUpload(_) => RequestType::KeysUpload,
+        AnyOutgoingRequest::KeysQuery(_) => RequestType::KeysQuery,
+        AnyOutgoingRequest::KeysClaim(_) => RequestType::KeysClaim,
+        AnyOutgoingRequest::ToDeviceRequest(_) => RequestType::ToDevice,
+        AnyOutgoingRequest::SignatureUpload(_) => RequestType::SignatureUpload,
+        AnyOutgoingRequest::RoomMessage(_) => RequestType::RoomMessage,
+    }
+}
+
 /// Represent the type of a request.
 #[wasm_bindgen]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RequestType {
     /// Represents a `KeysUploadRequest`.
     KeysUpload,
@@ -544,6 +736,72 @@ pub enum RequestType {
     KeysBackup,
 }
 
+/// A complete HTTP request, ready to be sent to the homeserver, as returned by
+/// the `toHttpRequest` method of the requests.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct HttpRequest {
+    /// The HTTP method, such as `POST` or `PUT`.
+    #[wasm_bindgen(readonly)]
+    pub method: JsString,
+
+    /// The full URL of the request, including the path and query parameters.
+    #[wasm_bindgen(readonly)]
+    pub url: JsString,
+
+    /// The headers of the request.
+    ///
+    /// Typescript type: `Map<string, string>`.
+    #[wasm_bindgen(readonly)]
+    pub headers: Map,
+
+    /// The JSON-encoded body of the request.
+    #[wasm_bindgen(readonly)]
+    pub body: JsString,
+}
+
+impl HttpRequest {
+    /// The Matrix version whose paths are used for the requests: all the
+    /// endpoints we use have had a stable path since then.
+    const MATRIX_VERSIONS: &'static [MatrixVersion] = &[MatrixVersion::V1_1];
+
+    fn new(
+        metadata: &Metadata,
+        homeserver_url: &str,
+        path_args: &[&dyn Display],
+        query: Option<(&str, &str)>,
+        access_token: Option<String>,
+        body: &JsString,
+    ) -> Result<Self, JsError> {
+        let query_string = query
+            .map(|(key, value)| {
+                url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair(key, value)
+                    .finish()
+            })
+            .unwrap_or_default();
+        let url = metadata.make_endpoint_url(
+            Self::MATRIX_VERSIONS,
+            homeserver_url,
+            path_args,
+            &query_string,
+        )?;
+
+        let headers = Map::new();
+        headers.set(&"Content-Type".into(), &"application/json".into());
+        if let Some(access_token) = access_token {
+            headers.set(&"Authorization".into(), &format!("Bearer {access_token}").into());
+        }
+
+        Ok(Self {
+            method: metadata.method.as_str().into(),
+            url: url.into(),
+            headers,
+            body: body.clone(),
+        })
+    }
+}
+
 /** Other Requests * */
 
 /// Request that will publish a cross signing identity.
@@ -567,6 +825,28 @@ impl UploadSigningKeysRequest {
     pub fn new(body: JsString) -> UploadSigningKeysRequest {
         Self { body }
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    ///
+    /// The homeserver may require user-interactive authentication for this
+    /// request, in which case an `auth` property has to be added to the
+    /// body.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &RumaUploadSigningKeysRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 impl TryFrom<&OriginalUploadSigningKeysRequest> for UploadSigningKeysRequest {
@@ -614,6 +894,24 @@ impl PutDehydratedDeviceRequest {
     pub fn new(body: JsString) -> PutDehydratedDeviceRequest {
         Self { body }
     }
+
+    /// Build the HTTP request to send to the homeserver at `homeserver_url`,
+    /// optionally authenticated with `access_token`.
+    #[wasm_bindgen(js_name = "toHttpRequest")]
+    pub fn to_http_request(
+        &self,
+        homeserver_url: &str,
+        access_token: Option<String>,
+    ) -> Result<HttpRequest, JsError> {
+        HttpRequest::new(
+            &OriginalPutDehydratedDeviceRequest::METADATA,
+            homeserver_url,
+            &[],
+            None,
+            access_token,
+            &self.body,
+        )
+    }
 }
 
 impl TryFrom<OriginalPutDehydratedDeviceRequest> for PutDehydratedDeviceRequest {
@@ -1,6 +1,8 @@
 //! Types related to responses.
 
-use js_sys::{Array, JsString};
+use std::time::Duration;
+
+use js_sys::{Array, JsString, Object, JSON};
 pub(crate) use matrix_sdk_common::ruma::api::client::{
     backup::add_backup_keys::v3::Response as KeysBackupResponse,
     keys::{
@@ -13,15 +15,21 @@ pub(crate) use matrix_sdk_common::ruma::api::client::{
 };
 use matrix_sdk_common::{
     deserialized_responses::AlgorithmInfo,
-    ruma::{self, api::IncomingResponse as RumaIncomingResponse},
+    ruma::{
+        self,
+        api::{
+            client::error::{Error as MatrixError, ErrorBody, ErrorKind, RetryAfter},
+            EndpointError, IncomingResponse as RumaIncomingResponse,
+        },
+    },
 };
 use matrix_sdk_crypto::types::requests::AnyIncomingResponse;
 use wasm_bindgen::prelude::*;
 
 use crate::{encryption, identifiers, impl_from_to_inner, requests::RequestType};
 
-pub(crate) fn response_from_string(body: &str) -> http::Result<http::Response<Vec<u8>>> {
-    http::Response::builder().status(200).body(body.as_bytes().to_vec())
+pub(crate) fn response_from_bytes(body: Vec<u8>) -> http::Result<http::Response<Vec<u8>>> {
+    http::Response::builder().status(200).body(body)
 }
 
 /// Intermediate private type to store an incoming owned response,
@@ -147,6 +155,112 @@ impl<'a> From<&'a OwnedResponse> for AnyIncomingResponse<'a> {
     }
 }
 
+/// The outcome of a request which failed, as returned by
+/// {@link OlmMachine.markRequestAsFailed}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct RequestFailure {
+    /// The ID of the request which failed.
+    #[wasm_bindgen(readonly, js_name = "requestId")]
+    pub request_id: JsString,
+
+    /// The type of the request which failed.
+    #[wasm_bindgen(readonly, js_name = "type")]
+    pub request_type: RequestType,
+
+    /// The HTTP status of the response, or `0` if no response was received.
+    #[wasm_bindgen(readonly)]
+    pub status: u16,
+
+    /// The Matrix error code, such as `M_LIMIT_EXCEEDED`, if the response
+    /// was a standard Matrix error.
+    #[wasm_bindgen(readonly)]
+    pub errcode: Option<JsString>,
+
+    /// The human-readable error message, if the response was a standard
+    /// Matrix error.
+    #[wasm_bindgen(readonly)]
+    pub message: Option<JsString>,
+
+    /// Whether the request may succeed if it is sent again: this is the case
+    /// when no response was received, when the server was rate-limiting us,
+    /// and for server errors.
+    #[wasm_bindgen(readonly)]
+    pub retryable: bool,
+
+    /// How long, in milliseconds, the `OlmMachine` will hold back requests of
+    /// this type before returning them again.
+    #[wasm_bindgen(readonly, js_name = "retryAfterMs")]
+    pub retry_after_ms: f64,
+
+    /// Whether the backup was disabled, because the server told us that
+    /// its current backup version is not the one we were using.
+    #[wasm_bindgen(readonly, js_name = "backupDisabled")]
+    pub backup_disabled: bool,
+}
+
+/// What we learn from a failed response.
+#[derive(Debug, Default)]
+pub(crate) struct ParsedFailure {
+    pub errcode: Option<String>,
+    pub message: Option<String>,
+    pub retryable: bool,
+
+    /// The delay before retrying, if the server asked for one.
+    pub server_delay: Option<Duration>,
+
+    /// Whether the server told us we are using the wrong backup version.
+    pub wrong_backup_version: bool,
+}
+
+impl ParsedFailure {
+    /// Parse the response to a failed request. `status` is `0` if no response
+    /// was received.
+    pub(crate) fn parse(status: u16, body: Vec<u8>) -> Self {
+        let Ok(status) = http::StatusCode::from_u16(status) else {
+            // Without a response, such as after a network error, there is nothing to
+            // parse, and the request may well succeed next time.
+            return Self { retryable: true, ..Default::default() };
+        };
+
+        let mut response = http::Response::new(body);
+        *response.status_mut() = status;
+        let error = MatrixError::from_http_response(response);
+
+        let mut parsed = Self {
+            retryable: status.is_server_error() || status == http::StatusCode::TOO_MANY_REQUESTS,
+            ..Default::default()
+        };
+
+        if let ErrorBody::Standard { kind, message } = error.body {
+            parsed.errcode = Some(kind.errcode().to_string());
+            parsed.message = Some(message);
+
+            match kind {
+                ErrorKind::LimitExceeded { retry_after } => {
+                    parsed.retryable = true;
+                    parsed.server_delay = retry_after.map(|retry_after| match retry_after {
+                        RetryAfter::Delay(delay) => delay,
+                        RetryAfter::DateTime(time) => {
+                            // `SystemTime::now` is not available in WASM.
+                            let time_ms = time
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .map_or(0., |since_epoch| since_epoch.as_millis() as f64);
+                            Duration::from_millis((time_ms - js_sys::Date::now()).max(0.) as u64)
+                        }
+                    });
+                }
+                ErrorKind::WrongRoomKeysVersion { .. } => {
+                    parsed.wrong_backup_version = true;
+                }
+                _ => {}
+            }
+        }
+
+        parsed
+    }
+}
+
 /// A decrypted room event.
 #[wasm_bindgen(getter_with_clone)]
 #[derive(Debug)]
@@ -160,6 +274,16 @@ pub struct DecryptedRoomEvent {
 
 #[wasm_bindgen]
 impl DecryptedRoomEvent {
+    /// The decrypted event, as a plain object.
+    ///
+    /// The event is parsed from {@link event} each time this is accessed.
+    #[wasm_bindgen(getter, js_name = "eventObject")]
+    pub fn event_object(&self) -> Object {
+        JSON::parse(&String::from(&self.event))
+            .expect("The decrypted event should be valid JSON")
+            .unchecked_into()
+    }
+
     /// The user ID of the event sender, note this is untrusted data
     /// unless the `verification_state` is as well trusted.
     #[wasm_bindgen(getter)]
@@ -0,0 +1,247 @@
+//! Sharing room keys, and reporting which devices received them.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use js_sys::Array;
+use matrix_sdk_common::ruma::{DeviceId, OwnedDeviceId, OwnedUserId, RoomId, UserId};
+use matrix_sdk_crypto::{
+    olm::{EncryptionSettings, OutboundGroupSession, ShareInfo},
+    types::requests::ToDeviceRequest as OriginalToDeviceRequest,
+    CollectStrategy, Device,
+};
+use wasm_bindgen::prelude::*;
+
+use crate::{identifiers, requests::ToDeviceRequest};
+
+/// What happened to the room key for one device, as reported by {@link
+/// RoomKeyRecipient.state}.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKeyRecipientState {
+    /// The room key is, or was, sent to the device.
+    Shared,
+
+    /// An `m.room_key.withheld` message is, or was, sent to the device
+    /// instead of the room key. See {@link RoomKeyRecipient.withheldCode}.
+    Withheld,
+
+    /// Nothing is sent to the device. See {@link RoomKeyRecipient.skipReason}.
+    Skipped,
+}
+
+/// Why the room key is not sent to a device, as reported by {@link
+/// RoomKeyRecipient.skipReason}.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKeySkipReason {
+    /// The device is blacklisted.
+    Blacklisted,
+
+    /// The device is not verified, and the sharing strategy only shares with
+    /// trusted devices.
+    Unverified,
+
+    /// The device is not cross-signed by its owner, and the sharing strategy
+    /// is identity-based.
+    Unsigned,
+
+    /// The device is a dehydrated device which is not cross-signed by its
+    /// owner.
+    Dehydrated,
+
+    /// We have no Olm session with the device. It can be established with
+    /// {@link OlmMachine.getMissingSessions}.
+    ///
+    /// An `m.no_olm` withheld code may have been sent to the device for an
+    /// earlier room key: it is only sent once to each device, whatever the
+    /// room key, so it is not reported as withheld from this one.
+    MissingOlmSession,
+}
+
+/// What happened to the room key for one device, as part of a {@link
+/// RoomKeyShareReport}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct RoomKeyRecipient {
+    /// The user the device belongs to.
+    #[wasm_bindgen(readonly, js_name = "userId")]
+    pub user_id: identifiers::UserId,
+
+    /// The device.
+    #[wasm_bindgen(readonly, js_name = "deviceId")]
+    pub device_id: identifiers::DeviceId,
+
+    /// Whether the room key is shared with the device, withheld from it, or
+    /// skipped.
+    #[wasm_bindgen(readonly)]
+    pub state: RoomKeyRecipientState,
+
+    /// The message index from which the device can decrypt the messages, if
+    /// the room key is shared with it.
+    #[wasm_bindgen(readonly, js_name = "messageIndex")]
+    pub message_index: Option<u32>,
+
+    /// The withheld code, such as `m.unverified` or `m.blacklisted`, if the
+    /// room key is withheld from the device.
+    #[wasm_bindgen(readonly, js_name = "withheldCode")]
+    pub withheld_code: Option<String>,
+
+    /// Why nothing is sent to the device, if it is skipped.
+    ///
+    /// The underlying machine does not record why it skips a device, so this
+    /// is worked out afterwards from the current state of the device, and may
+    /// not be accurate if the device changed in between.
+    #[wasm_bindgen(readonly, js_name = "skipReason")]
+    pub skip_reason: Option<RoomKeySkipReason>,
+}
+
+impl RoomKeyRecipient {
+    fn new(user_id: &UserId, device_id: &DeviceId, state: RoomKeyRecipientState) -> Self {
+        Self {
+            user_id: user_id.to_owned().into(),
+            device_id: device_id.to_owned().into(),
+            state,
+            message_index: None,
+            withheld_code: None,
+            skip_reason: None,
+        }
+    }
+
+    fn shared(user_id: &UserId, device_id: &DeviceId, message_index: u32) -> Self {
+        Self {
+            message_index: Some(message_index),
+            ..Self::new(user_id, device_id, RoomKeyRecipientState::Shared)
+        }
+    }
+
+    fn withheld(user_id: &UserId, device_id: &DeviceId, code: &str) -> Self {
+        Self {
+            withheld_code: Some(code.to_owned()),
+            ..Self::new(user_id, device_id, RoomKeyRecipientState::Withheld)
+        }
+    }
+
+    fn skipped(user_id: &UserId, device_id: &DeviceId, reason: RoomKeySkipReason) -> Self {
+        Self {
+            skip_reason: Some(reason),
+            ..Self::new(user_id, device_id, RoomKeyRecipientState::Skipped)
+        }
+    }
+}
+
+/// The outcome of {@link OlmMachine.shareRoomKeyWithReport}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct RoomKeyShareReport {
+    /// The to-device requests to send, as returned by {@link
+    /// OlmMachine.shareRoomKey}.
+    ///
+    /// Typescript type: `ToDeviceRequest[]`.
+    #[wasm_bindgen(readonly)]
+    pub requests: Array,
+
+    /// What happened to the room key for each device.
+    ///
+    /// Typescript type: `RoomKeyRecipient[]`.
+    #[wasm_bindgen(readonly)]
+    pub recipients: Array,
+}
+
+impl RoomKeyShareReport {
+    /// Report what happened to the room key for the devices of the given
+    /// users, once it was shared by the underlying machine with `requests`.
+    ///
+    /// Whether a device was shared with or withheld from is read from the
+    /// session, but the machine does not record why it skipped the others:
+    /// their skip reason is guessed from the current state of the device,
+    /// and assumed to be a missing Olm session if the sharing strategy allows
+    /// the device.
+    pub(crate) async fn for_users(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        room_id: &RoomId,
+        users: &[OwnedUserId],
+        requests: &[Arc<OriginalToDeviceRequest>],
+        settings: &EncryptionSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let recipients = Array::new();
+
+        if let Some(session) = machine.store().get_outbound_group_session(room_id).await? {
+            let share_infos = share_infos(&session).await;
+
+            for user_id in users {
+                for device in machine.get_user_devices(user_id, None).await?.devices() {
+                    if device.is_our_own_device() {
+                        continue;
+                    }
+
+                    let device_id = device.device_id();
+                    let recipient = match share_infos.get(&(user_id.clone(), device_id.to_owned()))
+                    {
+                        Some(ShareInfo::Shared(shared)) => {
+                            RoomKeyRecipient::shared(user_id, device_id, shared.message_index)
+                        }
+                        Some(ShareInfo::Withheld(code)) => {
+                            RoomKeyRecipient::withheld(user_id, device_id, code.as_str())
+                        }
+                        None => RoomKeyRecipient::skipped(
+                            user_id,
+                            device_id,
+                            skip_reason(&device, &settings.sharing_strategy)
+                                .unwrap_or(RoomKeySkipReason::MissingOlmSession),
+                        ),
+                    };
+
+                    recipients.push(&recipient.into());
+                }
+            }
+        }
+
+        Ok(Self { requests: to_device_requests(requests)?, recipients })
+    }
+}
+
+/// How the given session was, or is to be, shared with each device.
+async fn share_infos(
+    session: &OutboundGroupSession,
+) -> BTreeMap<(OwnedUserId, OwnedDeviceId), ShareInfo> {
+    let pickle = session.pickle().await;
+
+    // The devices the session was shared with take precedence over the pending
+    // ones.
+    pickle
+        .requests
+        .into_values()
+        .flat_map(|(_, share_infos)| share_infos)
+        .chain(pickle.shared_with_set)
+        .flat_map(|(user_id, devices)| {
+            devices
+                .into_iter()
+                .map(move |(device_id, share_info)| ((user_id.clone(), device_id), share_info))
+        })
+        .collect()
+}
+
+/// Why the room key should not be sent to the given device, according to the
+/// sharing strategy.
+fn skip_reason(device: &Device, sharing_strategy: &CollectStrategy) -> Option<RoomKeySkipReason> {
+    if device.is_blacklisted() {
+        Some(RoomKeySkipReason::Blacklisted)
+    } else if device.is_dehydrated() && !device.is_cross_signed_by_owner() {
+        Some(RoomKeySkipReason::Dehydrated)
+    } else if *sharing_strategy == CollectStrategy::IdentityBasedStrategy
+        && !device.is_cross_signed_by_owner()
+    {
+        Some(RoomKeySkipReason::Unsigned)
+    } else if *sharing_strategy == CollectStrategy::OnlyTrustedDevices && !device.is_verified() {
+        Some(RoomKeySkipReason::Unverified)
+    } else {
+        None
+    }
+}
+
+fn to_device_requests(requests: &[Arc<OriginalToDeviceRequest>]) -> Result<Array, anyhow::Error> {
+    Ok(requests
+        .iter()
+        .map(|request| ToDeviceRequest::try_from(&**request).map(JsValue::from))
+        .collect::<Result<Array, _>>()?)
+}
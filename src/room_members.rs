@@ -0,0 +1,217 @@
+//! Tracking the members of encrypted rooms, to share room keys with them.
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use futures_util::lock::Mutex;
+use matrix_sdk_common::ruma::{
+    events::room::{history_visibility::HistoryVisibility, member::MembershipState},
+    OwnedRoomId, OwnedUserId, RoomId, UserId,
+};
+use matrix_sdk_crypto::CryptoStoreError;
+use serde::{Deserialize, Serialize};
+
+use crate::sync_events::RoomEvent;
+
+/// The prefix of the keys under which the members of each room are stored.
+const STORE_KEY_PREFIX: &str = "room_members:";
+
+/// The members of an encrypted room, as stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RoomMembers {
+    /// The history visibility of the room, if an `m.room.history_visibility`
+    /// event was seen.
+    history_visibility: Option<HistoryVisibility>,
+
+    /// The latest membership of each user seen in the room.
+    members: BTreeMap<OwnedUserId, MembershipState>,
+}
+
+impl RoomMembers {
+    /// Load the members of the given room from the store.
+    pub(crate) async fn load(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        room_id: &RoomId,
+    ) -> Result<Self, CryptoStoreError> {
+        Ok(machine.store().get_value(&store_key(room_id)).await?.unwrap_or_default())
+    }
+
+    /// The users who should receive the room keys of the given room, as
+    /// returned by [`RoomMembers::recipients`], or `None` if no state event of
+    /// the room was ever tracked.
+    pub(crate) async fn load_recipients(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        room_id: &RoomId,
+    ) -> Result<Option<Vec<OwnedUserId>>, CryptoStoreError> {
+        let members: Option<Self> = machine.store().get_value(&store_key(room_id)).await?;
+
+        Ok(members.map(|members| members.recipients().map(ToOwned::to_owned).collect()))
+    }
+
+    /// Like [`RoomMembers::load_recipients`], but fails if the members of the
+    /// room are not tracked, rather than sharing its room keys with nobody.
+    pub(crate) async fn load_tracked_recipients(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, anyhow::Error> {
+        Self::load_recipients(machine, room_id).await?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "The members of the room {room_id} are not tracked: pass its state events to \
+                 `receiveRoomStateEvents`, or the users to share the room key with"
+            )
+        })
+    }
+
+    /// The users who should receive the room keys of the room: the joined
+    /// members, and the invited ones unless the history visibility is
+    /// `joined`, as they could otherwise read the messages sent before they
+    /// join.
+    pub(crate) fn recipients(&self) -> impl Iterator<Item = &UserId> {
+        let share_with_invited = self.history_visibility != Some(HistoryVisibility::Joined);
+
+        self.members
+            .iter()
+            .filter(move |(_, membership)| match membership {
+                MembershipState::Join => true,
+                MembershipState::Invite => share_with_invited,
+                _ => false,
+            })
+            .map(|(user_id, _)| &**user_id)
+    }
+
+    /// The history visibility of the room, if an `m.room.history_visibility`
+    /// event with a known value was seen.
+    pub(crate) fn history_visibility(&self) -> Option<HistoryVisibility> {
+        // Unknown values were stored before they were ignored.
+        self.history_visibility.clone().filter(is_known)
+    }
+}
+
+/// The locks serializing the updates of the stored members of each room,
+/// shared between the clones of an `OlmMachine`.
+///
+/// Applying [`MembershipChanges`] loads the members of a room, modifies them,
+/// then stores them back, with awaits in between: without the lock, two
+/// updates of the same room, for example by `receiveRoomStateEvents` and
+/// `receiveSyncResponse`, could interleave, and the changes of one would be
+/// lost.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RoomMembersLocks {
+    locks: Rc<RefCell<BTreeMap<OwnedRoomId, Rc<Mutex<()>>>>>,
+}
+
+impl RoomMembersLocks {
+    /// The lock of the given room, created the first time it is needed.
+    fn get(&self, room_id: &RoomId) -> Rc<Mutex<()>> {
+        self.locks.borrow_mut().entry(room_id.to_owned()).or_default().clone()
+    }
+}
+
+/// The changes to the membership and history visibility of a room, as found
+/// in its state events.
+#[derive(Debug, Default)]
+pub(crate) struct MembershipChanges {
+    pub memberships: BTreeMap<OwnedUserId, MembershipState>,
+    pub history_visibility: Option<HistoryVisibility>,
+}
+
+impl MembershipChanges {
+    /// Take the change described by the given state event into account, if
+    /// any. Later events take precedence over earlier ones.
+    pub(crate) fn add(&mut self, event: &RoomEvent) {
+        let Some(state_key) = &event.state_key else { return };
+
+        match event.event_type.as_str() {
+            "m.room.member" => {
+                let user_id = state_key.as_str().try_into().ok();
+                let membership = event
+                    .content
+                    .get("membership")
+                    .and_then(|m| serde_json::from_value(m.clone()).ok());
+
+                if let (Some(user_id), Some(membership)) = (user_id, membership) {
+                    self.memberships.insert(user_id, membership);
+                }
+            }
+
+            "m.room.history_visibility" => {
+                if let Some(history_visibility) = event
+                    .content
+                    .get("history_visibility")
+                    .and_then(|h| serde_json::from_value(h.clone()).ok())
+                    .filter(is_known)
+                {
+                    self.history_visibility = Some(history_visibility);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Apply the changes to the stored members of the given encrypted room.
+    ///
+    /// The new recipients of the room keys are tracked, so that their devices
+    /// are known, and the current room key is discarded if any of its
+    /// recipients is no longer one, so that they cannot read the next
+    /// messages.
+    ///
+    /// The update holds the lock of the room in `locks` until the members
+    /// are stored, so that concurrent updates of the room are applied one
+    /// after the other.
+    ///
+    /// Returns whether the room key was discarded.
+    pub(crate) async fn apply(
+        self,
+        machine: &matrix_sdk_crypto::OlmMachine,
+        locks: &RoomMembersLocks,
+        room_id: &RoomId,
+    ) -> Result<bool, CryptoStoreError> {
+        if self.memberships.is_empty() && self.history_visibility.is_none() {
+            return Ok(false);
+        }
+
+        let lock = locks.get(room_id);
+        let _guard = lock.lock().await;
+
+        let mut members = RoomMembers::load(machine, room_id).await?;
+        let previous_recipients =
+            members.recipients().map(ToOwned::to_owned).collect::<Vec<OwnedUserId>>();
+
+        members.members.extend(self.memberships);
+        if self.history_visibility.is_some() {
+            members.history_visibility = self.history_visibility;
+        }
+
+        machine.update_tracked_users(members.recipients()).await?;
+
+        let recipients = members.recipients().collect::<Vec<_>>();
+        let discarded =
+            if previous_recipients.iter().any(|user_id| !recipients.contains(&&**user_id)) {
+                machine.discard_room_key(room_id).await?
+            } else {
+                false
+            };
+
+        machine.store().set_value(&store_key(room_id), &members).await?;
+
+        Ok(discarded)
+    }
+}
+
+/// Whether the given history visibility is one of those of the spec.
+///
+/// Custom values are ignored, as the settings to share the room keys cannot
+/// hold them.
+fn is_known(history_visibility: &HistoryVisibility) -> bool {
+    matches!(
+        history_visibility,
+        HistoryVisibility::Invited
+            | HistoryVisibility::Joined
+            | HistoryVisibility::Shared
+            | HistoryVisibility::WorldReadable
+    )
+}
+
+fn store_key(room_id: &RoomId) -> String {
+    format!("{STORE_KEY_PREFIX}{room_id}")
+}
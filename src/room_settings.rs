@@ -0,0 +1,118 @@
+//! Keeping the settings of encrypted rooms in line with their
+//! `m.room.encryption` state events.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use matrix_sdk_common::ruma::{events::room::encryption::RoomEncryptionEventContent, RoomId};
+use matrix_sdk_crypto::{
+    olm::EncryptionSettings, store::RoomSettings, types::EventEncryptionAlgorithm, CollectStrategy,
+    CryptoStoreError,
+};
+
+use crate::{room_members::RoomMembers, sync_events::RoomEvent};
+
+/// The room settings described by the content of an `m.room.encryption`
+/// event.
+pub(crate) fn room_settings_from_content(content: &RoomEncryptionEventContent) -> RoomSettings {
+    RoomSettings {
+        algorithm: content.algorithm.as_str().into(),
+        only_allow_trusted_devices: false,
+        session_rotation_period: content
+            .rotation_period_ms
+            .map(|millis| Duration::from_millis(millis.into())),
+        session_rotation_period_messages: content
+            .rotation_period_msgs
+            .map(|count| u64::from(count).try_into().unwrap_or(usize::MAX)),
+    }
+}
+
+/// Validate the given `m.room.encryption` state event, and store the
+/// settings it describes for the given room.
+///
+/// The settings of a room cannot change once stored: if the room is already
+/// encrypted, an event with another algorithm is rejected as a downgrade,
+/// and the rotation periods of an event with the same algorithm are ignored.
+///
+/// Returns the settings of the room.
+pub(crate) async fn receive_encryption_event(
+    machine: &matrix_sdk_crypto::OlmMachine,
+    room_id: &RoomId,
+    event: RoomEvent,
+) -> Result<RoomSettings, anyhow::Error> {
+    if event.event_type != "m.room.encryption" {
+        bail!("Expected an `m.room.encryption` event, got `{}`", event.event_type);
+    }
+
+    if event.state_key.as_deref() != Some("") {
+        bail!("An `m.room.encryption` event must have an empty state key");
+    }
+
+    let content = serde_json::from_value::<RoomEncryptionEventContent>(event.content)
+        .map_err(|e| anyhow!("Invalid `m.room.encryption` content: {e}"))?;
+
+    store_room_settings(machine, room_id, &content).await
+}
+
+/// Store the settings described by the given `m.room.encryption` content for
+/// the given room, as described in [`receive_encryption_event`].
+pub(crate) async fn store_room_settings(
+    machine: &matrix_sdk_crypto::OlmMachine,
+    room_id: &RoomId,
+    content: &RoomEncryptionEventContent,
+) -> Result<RoomSettings, anyhow::Error> {
+    let settings = room_settings_from_content(content);
+
+    if settings.algorithm != EventEncryptionAlgorithm::MegolmV1AesSha2 {
+        bail!("Unsupported encryption algorithm `{}`", settings.algorithm);
+    }
+
+    if let Some(current) = machine.room_settings(room_id).await? {
+        if current.algorithm != settings.algorithm {
+            bail!(
+                "Refusing to change the encryption algorithm of {room_id} from `{}` to `{}`",
+                current.algorithm,
+                settings.algorithm
+            );
+        }
+
+        return Ok(current);
+    }
+
+    machine.set_room_settings(room_id, &settings).await?;
+
+    Ok(settings)
+}
+
+/// The settings to share the room keys of the given room with, derived from
+/// its stored settings and its tracked history visibility, or `None` if the
+/// room is not encrypted.
+///
+/// The rotation periods which the settings leave out get their default
+/// values, and so does the history visibility if no
+/// `m.room.history_visibility` event was seen.
+pub(crate) async fn encryption_settings(
+    machine: &matrix_sdk_crypto::OlmMachine,
+    room_id: &RoomId,
+) -> Result<Option<EncryptionSettings>, CryptoStoreError> {
+    let Some(settings) = machine.room_settings(room_id).await? else {
+        return Ok(None);
+    };
+
+    let history_visibility = RoomMembers::load(machine, room_id).await?.history_visibility();
+    let default = EncryptionSettings::default();
+
+    Ok(Some(EncryptionSettings {
+        algorithm: settings.algorithm,
+        rotation_period: settings.session_rotation_period.unwrap_or(default.rotation_period),
+        rotation_period_msgs: settings
+            .session_rotation_period_messages
+            .map_or(default.rotation_period_msgs, |count| count as u64),
+        history_visibility: history_visibility.unwrap_or(default.history_visibility),
+        sharing_strategy: if settings.only_allow_trusted_devices {
+            CollectStrategy::OnlyTrustedDevices
+        } else {
+            default.sharing_strategy
+        },
+    }))
+}
@@ -0,0 +1,341 @@
+//! A crypto store wrapper which can be retired, so that an `OlmMachine` which
+//! was replaced by a reloaded one can no longer access the store.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use matrix_sdk_common::ruma::{
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId,
+};
+use matrix_sdk_crypto::{
+    olm::{
+        InboundGroupSession, OlmMessageHash, OutboundGroupSession, PrivateCrossSigningIdentity,
+        SenderDataType, Session,
+    },
+    store::{
+        BackupKeys, Changes, CryptoStore, DehydratedDeviceKey, DynCryptoStore, PendingChanges,
+        RoomKeyCounts, RoomSettings,
+    },
+    types::events::room_key_withheld::RoomKeyWithheldEvent,
+    vodozemac::Curve25519PublicKey,
+    Account, CryptoStoreError, DeviceData, GossipRequest, GossippedSecret, SecretInfo, TrackedUser,
+    UserIdentityData,
+};
+
+/// The error returned by a [`FencedStore`] once it is retired.
+#[derive(Debug)]
+struct RetiredStoreError;
+
+impl fmt::Display for RetiredStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "This object belongs to an OlmMachine whose state was reloaded from the store, and \
+            can no longer be used: get it again from the OlmMachine",
+        )
+    }
+}
+
+impl std::error::Error for RetiredStoreError {}
+
+/// A [`CryptoStore`] which forwards everything to the underlying store, until
+/// it is retired.
+///
+/// Each underlying `OlmMachine` gets its own `FencedStore`, which is retired
+/// when the machine is replaced by a reloaded one. From then on, the old
+/// machine, and any object which still holds a clone of it, fails to read or
+/// write the store, rather than overwriting the state of the new machine with
+/// its stale one.
+#[derive(Debug)]
+pub(crate) struct FencedStore {
+    inner: Arc<DynCryptoStore>,
+    retired: AtomicBool,
+}
+
+impl FencedStore {
+    pub(super) fn new(inner: Arc<DynCryptoStore>) -> Self {
+        Self { inner, retired: AtomicBool::new(false) }
+    }
+
+    /// Make any further access to the store fail.
+    pub(crate) fn retire(&self) {
+        self.retired.store(true, Ordering::SeqCst);
+    }
+
+    fn check(&self) -> Result<(), CryptoStoreError> {
+        if self.retired.load(Ordering::SeqCst) {
+            Err(CryptoStoreError::backend(RetiredStoreError))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl CryptoStore for FencedStore {
+    type Error = CryptoStoreError;
+
+    async fn load_account(&self) -> Result<Option<Account>, Self::Error> {
+        self.check()?;
+        self.inner.load_account().await
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>, Self::Error> {
+        self.check()?;
+        self.inner.load_identity().await
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.save_changes(changes).await
+    }
+
+    async fn save_pending_changes(&self, changes: PendingChanges) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.save_pending_changes(changes).await
+    }
+
+    async fn save_inbound_group_sessions(
+        &self,
+        sessions: Vec<InboundGroupSession>,
+        backed_up_to_version: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.save_inbound_group_sessions(sessions, backed_up_to_version).await
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Vec<Session>>, Self::Error> {
+        self.check()?;
+        self.inner.get_sessions(sender_key).await
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>, Self::Error> {
+        self.check()?;
+        self.inner.get_inbound_group_session(room_id, session_id).await
+    }
+
+    async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<RoomKeyWithheldEvent>, Self::Error> {
+        self.check()?;
+        self.inner.get_withheld_info(room_id, session_id).await
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.check()?;
+        self.inner.get_inbound_group_sessions().await
+    }
+
+    async fn inbound_group_session_counts(
+        &self,
+        backup_version: Option<&str>,
+    ) -> Result<RoomKeyCounts, Self::Error> {
+        self.check()?;
+        self.inner.inbound_group_session_counts(backup_version).await
+    }
+
+    async fn get_inbound_group_sessions_for_device_batch(
+        &self,
+        curve_key: Curve25519PublicKey,
+        sender_data_type: SenderDataType,
+        after_session_id: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.check()?;
+        self.inner
+            .get_inbound_group_sessions_for_device_batch(
+                curve_key,
+                sender_data_type,
+                after_session_id,
+                limit,
+            )
+            .await
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        backup_version: &str,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.check()?;
+        self.inner.inbound_group_sessions_for_backup(backup_version, limit).await
+    }
+
+    async fn mark_inbound_group_sessions_as_backed_up(
+        &self,
+        backup_version: &str,
+        room_and_session_ids: &[(&RoomId, &str)],
+    ) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner
+            .mark_inbound_group_sessions_as_backed_up(backup_version, room_and_session_ids)
+            .await
+    }
+
+    async fn reset_backup_state(&self) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.reset_backup_state().await
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys, Self::Error> {
+        self.check()?;
+        self.inner.load_backup_keys().await
+    }
+
+    async fn load_dehydrated_device_pickle_key(
+        &self,
+    ) -> Result<Option<DehydratedDeviceKey>, Self::Error> {
+        self.check()?;
+        self.inner.load_dehydrated_device_pickle_key().await
+    }
+
+    async fn delete_dehydrated_device_pickle_key(&self) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.delete_dehydrated_device_pickle_key().await
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>, Self::Error> {
+        self.check()?;
+        self.inner.get_outbound_group_session(room_id).await
+    }
+
+    async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>, Self::Error> {
+        self.check()?;
+        self.inner.load_tracked_users().await
+    }
+
+    async fn save_tracked_users(&self, users: &[(&UserId, bool)]) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.save_tracked_users(users).await
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceData>, Self::Error> {
+        self.check()?;
+        self.inner.get_device(user_id, device_id).await
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, DeviceData>, Self::Error> {
+        self.check()?;
+        self.inner.get_user_devices(user_id).await
+    }
+
+    async fn get_own_device(&self) -> Result<DeviceData, Self::Error> {
+        self.check()?;
+        self.inner.get_own_device().await
+    }
+
+    async fn get_user_identity(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<UserIdentityData>, Self::Error> {
+        self.check()?;
+        self.inner.get_user_identity(user_id).await
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool, Self::Error> {
+        self.check()?;
+        self.inner.is_message_known(message_hash).await
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        self.check()?;
+        self.inner.get_outgoing_secret_requests(request_id).await
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        secret_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        self.check()?;
+        self.inner.get_secret_request_by_info(secret_info).await
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>, Self::Error> {
+        self.check()?;
+        self.inner.get_unsent_secret_requests().await
+    }
+
+    async fn delete_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.delete_outgoing_secret_requests(request_id).await
+    }
+
+    async fn get_secrets_from_inbox(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Vec<GossippedSecret>, Self::Error> {
+        self.check()?;
+        self.inner.get_secrets_from_inbox(secret_name).await
+    }
+
+    async fn delete_secrets_from_inbox(&self, secret_name: &SecretName) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.delete_secrets_from_inbox(secret_name).await
+    }
+
+    async fn get_room_settings(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomSettings>, Self::Error> {
+        self.check()?;
+        self.inner.get_room_settings(room_id).await
+    }
+
+    async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.check()?;
+        self.inner.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(&self, key: &str, value: Vec<u8>) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.set_custom_value(key, value).await
+    }
+
+    async fn remove_custom_value(&self, key: &str) -> Result<(), Self::Error> {
+        self.check()?;
+        self.inner.remove_custom_value(key).await
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error> {
+        self.check()?;
+        self.inner.try_take_leased_lock(lease_duration_ms, key, holder).await
+    }
+
+    async fn next_batch_token(&self) -> Result<Option<String>, Self::Error> {
+        self.check()?;
+        self.inner.next_batch_token().await
+    }
+}
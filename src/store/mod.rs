@@ -1,11 +1,21 @@
 //! Store types.
 
-use std::sync::Arc;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::Arc,
+};
 
 use anyhow::Context;
+use js_sys::{Array, Function};
+use matrix_sdk_common::{
+    ruma::TransactionId,
+    store_locks::{BackingStore, CrossProcessStoreLock, CrossProcessStoreLockGuard},
+};
 use matrix_sdk_crypto::{
     store::{DynCryptoStore, IntoCryptoStore, MemoryStore},
     types::BackupSecrets,
+    CryptoStoreError,
 };
 use wasm_bindgen::prelude::*;
 use zeroize::{Zeroize, Zeroizing};
@@ -17,6 +27,17 @@ use crate::{
     vodozemac::Curve25519PublicKey,
 };
 
+mod fenced;
+mod progress;
+mod read_only;
+mod statistics;
+
+pub(crate) use self::fenced::FencedStore;
+pub use self::{
+    progress::StoreOpenPhase,
+    statistics::{StoreStatistics, UnreadableRecord},
+};
+
 /// A struct containing an open connection to a CryptoStore.
 ///
 /// Opening the CryptoStore can take some time, due to the PBKDF calculation
@@ -26,6 +47,51 @@ use crate::{
 #[derive(Clone, Debug)]
 pub struct StoreHandle {
     pub(crate) store: Arc<DynCryptoStore>,
+    lock: Rc<StoreLock>,
+}
+
+/// The key of the cross-process lock in the store.
+const STORE_LOCK_KEY: &str = "crypto-store-lock";
+
+/// The key of the generation counter in the store, which is bumped by each
+/// holder of the cross-process lock that finds out that another holder has
+/// held the lock since it last did.
+///
+/// This is the same key as the one used by
+/// `OlmMachine::maintain_crypto_store_generation` in `matrix-sdk-crypto`.
+const STORE_GENERATION_KEY: &str = "generation-counter";
+
+/// The cross-process lock on a store, and the state of this process with
+/// regards to it.
+#[derive(Debug)]
+struct StoreLock {
+    lock: CrossProcessStoreLock<LockableStore>,
+
+    /// The guard of the lock, while we hold it.
+    guard: RefCell<Option<CrossProcessStoreLockGuard>>,
+
+    /// The value of the generation counter when we last held the lock.
+    generation: Cell<Option<u64>>,
+}
+
+/// A [`BackingStore`] for the cross-process lock, based on the leases
+/// provided by the crypto store.
+#[derive(Clone, Debug)]
+struct LockableStore(Arc<DynCryptoStore>);
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl BackingStore for LockableStore {
+    type LockError = CryptoStoreError;
+
+    async fn try_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::LockError> {
+        self.0.try_take_leased_lock(lease_duration_ms, key, holder).await
+    }
 }
 
 #[wasm_bindgen]
@@ -45,20 +111,38 @@ impl StoreHandle {
     ///
     /// * `store_passphrase` - The passphrase that should be used to encrypt the
     ///   store, for IndexedDB-based stores
+    ///
+    /// * `progress` - An optional callback, called with a {@link
+    ///   StoreOpenPhase} as each phase of opening an IndexedDB-based store
+    ///   starts.
+    ///
+    /// The key derived from `store_passphrase` cannot be returned to skip the
+    /// key derivation when opening the store again: `matrix-sdk-indexeddb`
+    /// does not expose it, and {@link openWithKey} expects a store created
+    /// with a key rather than a passphrase. Stores which should be opened
+    /// without a key derivation must be created with {@link openWithKey}.
     #[wasm_bindgen(js_name = "open")]
     pub async fn open_for_js(
         store_name: Option<String>,
         store_passphrase: Option<String>,
+        progress: Option<Function>,
     ) -> Result<StoreHandle, JsError> {
-        StoreHandle::open(store_name, store_passphrase).await.map_err(|e| JsError::from(&*e))
+        StoreHandle::open(store_name, store_passphrase, progress)
+            .await
+            .map_err(|e| JsError::from(&*e))
     }
 
     pub(crate) async fn open(
         store_name: Option<String>,
         store_passphrase: Option<String>,
+        progress: Option<Function>,
     ) -> Result<StoreHandle, anyhow::Error> {
+        let progress = progress::OpenProgress::new(progress);
+
         let store = match store_name {
-            Some(store_name) => Self::open_indexeddb(&store_name, store_passphrase).await?,
+            Some(store_name) => {
+                Self::open_indexeddb(&store_name, store_passphrase, &progress).await?
+            }
 
             None => {
                 if store_passphrase.is_some() {
@@ -72,17 +156,49 @@ impl StoreHandle {
             }
         };
 
-        Ok(Self { store })
+        Ok(Self::new(store))
+    }
+
+    /// Get a handle on the same store, which never writes to it.
+    pub(crate) fn read_only(&self) -> StoreHandle {
+        Self::new(Arc::new(read_only::ReadOnlyStore(self.store.clone())))
+    }
+
+    /// Get a view of the store for one underlying `OlmMachine`, which can be
+    /// retired once that machine is replaced by a reloaded one.
+    pub(crate) fn fenced(&self) -> Arc<FencedStore> {
+        Arc::new(FencedStore::new(self.store.clone()))
+    }
+
+    fn new(store: Arc<DynCryptoStore>) -> Self {
+        // Each handle gets its own, random, identity as a holder of the lock.
+        let holder = TransactionId::new().to_string();
+        let lock = CrossProcessStoreLock::new(
+            LockableStore(store.clone()),
+            STORE_LOCK_KEY.to_owned(),
+            holder,
+        );
+
+        Self {
+            store,
+            lock: Rc::new(StoreLock {
+                lock,
+                guard: Default::default(),
+                generation: Cell::new(None),
+            }),
+        }
     }
 
     async fn open_indexeddb(
         store_name: &str,
         store_passphrase: Option<String>,
+        progress: &progress::OpenProgress,
     ) -> Result<Arc<DynCryptoStore>, matrix_sdk_indexeddb::IndexeddbCryptoStoreError> {
         let store = match store_passphrase {
             Some(mut store_passphrase) => {
                 use zeroize::Zeroize;
 
+                progress.report(StoreOpenPhase::KeyDerivation);
                 let store = matrix_sdk_indexeddb::IndexeddbCryptoStore::open_with_passphrase(
                     store_name,
                     &store_passphrase,
@@ -93,7 +209,10 @@ impl StoreHandle {
                 store
             }
 
-            None => matrix_sdk_indexeddb::IndexeddbCryptoStore::open_with_name(store_name).await?,
+            None => {
+                progress.report(StoreOpenPhase::Opening);
+                matrix_sdk_indexeddb::IndexeddbCryptoStore::open_with_name(store_name).await?
+            }
         };
 
         Ok(store.into_crypto_store())
@@ -109,11 +228,16 @@ impl StoreHandle {
     ///
     /// * `store_key` - The key that should be used to encrypt the store, for
     ///   IndexedDB-based stores. Must be a 32-byte array.
+    ///
+    /// * `progress` - An optional callback, as for {@link open}.
     #[wasm_bindgen(js_name = "openWithKey")]
     pub async fn open_with_key(
         store_name: String,
         mut store_key: Vec<u8>,
+        progress: Option<Function>,
     ) -> Result<StoreHandle, JsError> {
+        let progress = progress::OpenProgress::new(progress);
+
         let store_key_array: Zeroizing<[u8; 32]> = Zeroizing::new(
             store_key
                 .as_slice()
@@ -123,13 +247,127 @@ impl StoreHandle {
         );
         store_key.zeroize();
 
+        progress.report(StoreOpenPhase::Opening);
         let store = matrix_sdk_indexeddb::IndexeddbCryptoStore::open_with_key(
             &store_name,
             &store_key_array,
         )
         .await?;
 
-        Ok(Self { store: store.into_crypto_store() })
+        Ok(Self::new(store.into_crypto_store()))
+    }
+
+    /// Acquire the cross-process lock on the store, waiting for it to be
+    /// released if it is held by another process, such as another browser
+    /// tab using the same IndexedDB database.
+    ///
+    /// The lock is held until {@link releaseLock} is called. While it is held,
+    /// it is periodically renewed; if this process goes away without releasing
+    /// it, it is freed after a short delay.
+    ///
+    /// Acquiring the lock again while it is already held by this handle
+    /// succeeds immediately.
+    ///
+    /// The lock is advisory: nothing stops a holder of this store, or another
+    /// process, from writing to it without holding the lock. It only protects
+    /// the store if every process writing to it acquires the lock first.
+    ///
+    /// Note that acquiring the lock via the `StoreHandle` does not reload the
+    /// state of an {@link OlmMachine} using this store: use
+    /// {@link OlmMachine.acquireStoreLock} for that.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_backoff_ms` - the maximum time to wait between two attempts to
+    ///   take the lock. Once reached, the attempt fails. Defaults to one
+    ///   second.
+    ///
+    /// # Returns
+    ///
+    /// `true` if another holder of the lock may have written to the store
+    /// since this handle last held it, in which case any state cached from
+    /// the store must be reloaded; `false` otherwise.
+    #[wasm_bindgen(js_name = "acquireLock")]
+    pub async fn acquire_lock(&self, max_backoff_ms: Option<u32>) -> Result<bool, JsError> {
+        let guard = self.lock.lock.spin_lock(max_backoff_ms).await?;
+        let changed = self.update_generation().await?;
+
+        // If we already held the lock, this drops the previous guard, so that
+        // the lock is only held once by this handle.
+        *self.lock.guard.borrow_mut() = Some(guard);
+
+        Ok(changed)
+    }
+
+    /// Release the cross-process lock on the store, taken by
+    /// {@link acquireLock}. Does nothing if the lock is not held.
+    #[wasm_bindgen(js_name = "releaseLock")]
+    pub fn release_lock(&self) {
+        self.lock.guard.borrow_mut().take();
+    }
+
+    /// Count the objects in the store, to help diagnose problems with
+    /// encryption. This extends {@link OlmMachine.roomKeyCounts}.
+    ///
+    /// This reads the whole store, so can be slow for large stores.
+    ///
+    /// # Returns
+    ///
+    /// A {@link StoreStatistics}.
+    pub async fn statistics(&self) -> StoreStatistics {
+        statistics::StoreWalk::run(&*self.store).await.into()
+    }
+
+    /// Try to read every object in the store, and list those which cannot be
+    /// read, for example because they cannot be decrypted or deserialized.
+    ///
+    /// Objects are read with the granularity that the store allows: for
+    /// example, all the room keys are read together, so an unreadable room key
+    /// is reported as a single {@link UnreadableRecord} of kind
+    /// `inboundGroupSessions`.
+    ///
+    /// This reads the whole store, so can be slow for large stores.
+    ///
+    /// # Returns
+    ///
+    /// An array of {@link UnreadableRecord}, empty if every object could be
+    /// read.
+    #[wasm_bindgen(js_name = "verifyIntegrity")]
+    pub async fn verify_integrity(&self) -> Array {
+        statistics::StoreWalk::run(&*self.store).await.unreadable_records()
+    }
+
+    /// Compare the generation counter in the store with the value we saw when
+    /// we last held the lock, and bump it if another holder has held the lock
+    /// since.
+    ///
+    /// Must be called while holding the lock. Returns whether another holder
+    /// may have written to the store.
+    async fn update_generation(&self) -> Result<bool, JsError> {
+        let actual = self
+            .store
+            .get_custom_value(STORE_GENERATION_KEY)
+            .await?
+            .map(|value| <[u8; 8]>::try_from(value).map(u64::from_le_bytes))
+            .transpose()
+            .map_err(|_| JsError::new("Invalid format for the store generation counter"))?;
+        let expected = self.lock.generation.get();
+
+        let (changed, new) = match (expected, actual) {
+            (Some(expected), Some(actual)) if expected == actual => return Ok(false),
+            (Some(expected), Some(actual)) => (true, expected.max(actual).wrapping_add(1)),
+            // Another holder took the lock before we ever did.
+            (None, Some(actual)) => (true, actual.wrapping_add(1)),
+            // Nobody ever held the lock.
+            (None, None) => (false, 0),
+            // The counter went missing: be on the safe side.
+            (Some(expected), None) => (true, expected.wrapping_add(1)),
+        };
+
+        self.store.set_custom_value(STORE_GENERATION_KEY, new.to_le_bytes().to_vec()).await?;
+        self.lock.generation.set(Some(new));
+
+        Ok(changed)
     }
 }
 
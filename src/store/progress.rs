@@ -0,0 +1,48 @@
+//! Reporting the progress of opening a store.
+
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+
+/// A phase of opening a store, as reported to the progress callback of
+/// {@link StoreHandle.open} and {@link StoreHandle.openWithKey}.
+///
+/// Only the start of each phase is reported. The progress of the migrations
+/// (which of how many is running) and the key derived from a passphrase are
+/// out of scope: `matrix-sdk-indexeddb` exposes neither.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOpenPhase {
+    /// The key encrypting the store is being derived from the passphrase.
+    ///
+    /// This only happens when the store is opened with a passphrase, and is
+    /// usually the slowest phase. It is directly followed by the
+    /// {@link Opening} phase, which is not reported separately in this case,
+    /// as `matrix-sdk-indexeddb` does both at once.
+    KeyDerivation,
+
+    /// The store is being opened, and its schema and data migrated if they
+    /// were written by an older version.
+    ///
+    /// `matrix-sdk-indexeddb` does not report which migrations it runs, so
+    /// whether any are run, and how many, is not known.
+    Opening,
+}
+
+/// The progress callback passed to {@link StoreHandle.open}, if any.
+#[derive(Debug, Clone)]
+pub(super) struct OpenProgress(Option<Function>);
+
+impl OpenProgress {
+    pub(super) fn new(callback: Option<Function>) -> Self {
+        Self(callback)
+    }
+
+    /// Call the callback with the given phase.
+    pub(super) fn report(&self, phase: StoreOpenPhase) {
+        let Some(callback) = &self.0 else { return };
+
+        if let Err(e) = callback.call1(&JsValue::NULL, &phase.into()) {
+            tracing::warn!("Progress callback of `StoreHandle.open` failed: {e:?}");
+        }
+    }
+}
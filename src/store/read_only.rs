@@ -0,0 +1,278 @@
+//! A crypto store wrapper which never writes to the underlying store.
+
+use std::{collections::HashMap, sync::Arc};
+
+use matrix_sdk_common::ruma::{
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, RoomId, TransactionId, UserId,
+};
+use matrix_sdk_crypto::{
+    olm::{
+        InboundGroupSession, OlmMessageHash, OutboundGroupSession, PrivateCrossSigningIdentity,
+        SenderDataType, Session,
+    },
+    store::{
+        BackupKeys, Changes, CryptoStore, DehydratedDeviceKey, DynCryptoStore, PendingChanges,
+        RoomKeyCounts, RoomSettings,
+    },
+    types::events::room_key_withheld::RoomKeyWithheldEvent,
+    vodozemac::Curve25519PublicKey,
+    Account, CryptoStoreError, DeviceData, GossipRequest, GossippedSecret, SecretInfo, TrackedUser,
+    UserIdentityData,
+};
+use tracing::debug;
+
+/// A [`CryptoStore`] which forwards reads to the underlying store, and
+/// discards writes.
+///
+/// Even when the `OlmMachine` is only used to decrypt events, it performs some
+/// incidental writes, such as recording improved sender data on a room key.
+/// Discarding them means that a read-only machine can never overwrite state
+/// written concurrently by another machine using the same store.
+#[derive(Debug)]
+pub(super) struct ReadOnlyStore(pub(super) Arc<DynCryptoStore>);
+
+impl ReadOnlyStore {
+    fn discard(&self, what: &str) {
+        debug!("Discarding a write of {what} to a read-only store");
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl CryptoStore for ReadOnlyStore {
+    type Error = CryptoStoreError;
+
+    async fn load_account(&self) -> Result<Option<Account>, Self::Error> {
+        self.0.load_account().await
+    }
+
+    async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>, Self::Error> {
+        self.0.load_identity().await
+    }
+
+    async fn save_changes(&self, _changes: Changes) -> Result<(), Self::Error> {
+        self.discard("changes");
+        Ok(())
+    }
+
+    async fn save_pending_changes(&self, _changes: PendingChanges) -> Result<(), Self::Error> {
+        self.discard("the account");
+        Ok(())
+    }
+
+    async fn save_inbound_group_sessions(
+        &self,
+        _sessions: Vec<InboundGroupSession>,
+        _backed_up_to_version: Option<&str>,
+    ) -> Result<(), Self::Error> {
+        self.discard("inbound group sessions");
+        Ok(())
+    }
+
+    async fn get_sessions(&self, sender_key: &str) -> Result<Option<Vec<Session>>, Self::Error> {
+        self.0.get_sessions(sender_key).await
+    }
+
+    async fn get_inbound_group_session(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<InboundGroupSession>, Self::Error> {
+        self.0.get_inbound_group_session(room_id, session_id).await
+    }
+
+    async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<Option<RoomKeyWithheldEvent>, Self::Error> {
+        self.0.get_withheld_info(room_id, session_id).await
+    }
+
+    async fn get_inbound_group_sessions(&self) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.0.get_inbound_group_sessions().await
+    }
+
+    async fn inbound_group_session_counts(
+        &self,
+        backup_version: Option<&str>,
+    ) -> Result<RoomKeyCounts, Self::Error> {
+        self.0.inbound_group_session_counts(backup_version).await
+    }
+
+    async fn get_inbound_group_sessions_for_device_batch(
+        &self,
+        curve_key: Curve25519PublicKey,
+        sender_data_type: SenderDataType,
+        after_session_id: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.0
+            .get_inbound_group_sessions_for_device_batch(
+                curve_key,
+                sender_data_type,
+                after_session_id,
+                limit,
+            )
+            .await
+    }
+
+    async fn inbound_group_sessions_for_backup(
+        &self,
+        backup_version: &str,
+        limit: usize,
+    ) -> Result<Vec<InboundGroupSession>, Self::Error> {
+        self.0.inbound_group_sessions_for_backup(backup_version, limit).await
+    }
+
+    async fn mark_inbound_group_sessions_as_backed_up(
+        &self,
+        _backup_version: &str,
+        _room_and_session_ids: &[(&RoomId, &str)],
+    ) -> Result<(), Self::Error> {
+        self.discard("the backup state of inbound group sessions");
+        Ok(())
+    }
+
+    async fn reset_backup_state(&self) -> Result<(), Self::Error> {
+        self.discard("the backup state");
+        Ok(())
+    }
+
+    async fn load_backup_keys(&self) -> Result<BackupKeys, Self::Error> {
+        self.0.load_backup_keys().await
+    }
+
+    async fn load_dehydrated_device_pickle_key(
+        &self,
+    ) -> Result<Option<DehydratedDeviceKey>, Self::Error> {
+        self.0.load_dehydrated_device_pickle_key().await
+    }
+
+    async fn delete_dehydrated_device_pickle_key(&self) -> Result<(), Self::Error> {
+        self.discard("the dehydrated device pickle key");
+        Ok(())
+    }
+
+    async fn get_outbound_group_session(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<OutboundGroupSession>, Self::Error> {
+        self.0.get_outbound_group_session(room_id).await
+    }
+
+    async fn load_tracked_users(&self) -> Result<Vec<TrackedUser>, Self::Error> {
+        self.0.load_tracked_users().await
+    }
+
+    async fn save_tracked_users(&self, _users: &[(&UserId, bool)]) -> Result<(), Self::Error> {
+        self.discard("tracked users");
+        Ok(())
+    }
+
+    async fn get_device(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceData>, Self::Error> {
+        self.0.get_device(user_id, device_id).await
+    }
+
+    async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> Result<HashMap<OwnedDeviceId, DeviceData>, Self::Error> {
+        self.0.get_user_devices(user_id).await
+    }
+
+    async fn get_own_device(&self) -> Result<DeviceData, Self::Error> {
+        self.0.get_own_device().await
+    }
+
+    async fn get_user_identity(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<UserIdentityData>, Self::Error> {
+        self.0.get_user_identity(user_id).await
+    }
+
+    async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool, Self::Error> {
+        self.0.is_message_known(message_hash).await
+    }
+
+    async fn get_outgoing_secret_requests(
+        &self,
+        request_id: &TransactionId,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        self.0.get_outgoing_secret_requests(request_id).await
+    }
+
+    async fn get_secret_request_by_info(
+        &self,
+        secret_info: &SecretInfo,
+    ) -> Result<Option<GossipRequest>, Self::Error> {
+        self.0.get_secret_request_by_info(secret_info).await
+    }
+
+    async fn get_unsent_secret_requests(&self) -> Result<Vec<GossipRequest>, Self::Error> {
+        self.0.get_unsent_secret_requests().await
+    }
+
+    async fn delete_outgoing_secret_requests(
+        &self,
+        _request_id: &TransactionId,
+    ) -> Result<(), Self::Error> {
+        self.discard("outgoing secret requests");
+        Ok(())
+    }
+
+    async fn get_secrets_from_inbox(
+        &self,
+        secret_name: &SecretName,
+    ) -> Result<Vec<GossippedSecret>, Self::Error> {
+        self.0.get_secrets_from_inbox(secret_name).await
+    }
+
+    async fn delete_secrets_from_inbox(
+        &self,
+        _secret_name: &SecretName,
+    ) -> Result<(), Self::Error> {
+        self.discard("the secrets inbox");
+        Ok(())
+    }
+
+    async fn get_room_settings(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Option<RoomSettings>, Self::Error> {
+        self.0.get_room_settings(room_id).await
+    }
+
+    async fn get_custom_value(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.0.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(&self, _key: &str, _value: Vec<u8>) -> Result<(), Self::Error> {
+        self.discard("a custom value");
+        Ok(())
+    }
+
+    async fn remove_custom_value(&self, _key: &str) -> Result<(), Self::Error> {
+        self.discard("a custom value");
+        Ok(())
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        _lease_duration_ms: u32,
+        _key: &str,
+        _holder: &str,
+    ) -> Result<bool, Self::Error> {
+        // Taking the lock would be a write, so a read-only store never gets it.
+        Ok(false)
+    }
+
+    async fn next_batch_token(&self) -> Result<Option<String>, Self::Error> {
+        self.0.next_batch_token().await
+    }
+}
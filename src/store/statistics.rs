@@ -0,0 +1,234 @@
+//! Statistics about, and integrity checks of, the contents of a crypto store.
+
+use std::collections::BTreeMap;
+
+use js_sys::{Array, Map};
+use matrix_sdk_common::ruma::{events::secret::request::SecretName, OwnedRoomId};
+use matrix_sdk_crypto::store::DynCryptoStore;
+use wasm_bindgen::prelude::*;
+
+use crate::backup::RoomKeyCounts;
+
+/// The secrets which may be found in the secrets inbox.
+const INBOX_SECRET_NAMES: [SecretName; 4] = [
+    SecretName::CrossSigningMasterKey,
+    SecretName::CrossSigningSelfSigningKey,
+    SecretName::CrossSigningUserSigningKey,
+    SecretName::RecoveryKey,
+];
+
+/// Counts of the objects in a crypto store, as returned by
+/// {@link StoreHandle.statistics}.
+///
+/// Only counts are reported, not sizes: the store backends do not expose the
+/// size of their records, and serializing the sessions to measure them would
+/// copy their secret keys around.
+///
+/// Devices, identities and Olm sessions are only counted for the users whose
+/// devices we are tracking.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct StoreStatistics {
+    /// The total number of room keys, and how many of them are backed up.
+    #[wasm_bindgen(readonly, js_name = "roomKeyCounts")]
+    pub room_key_counts: RoomKeyCounts,
+
+    /// The number of room keys (inbound group sessions) in each room.
+    ///
+    /// Typescript type: `Map<string, number>`.
+    #[wasm_bindgen(readonly, js_name = "inboundGroupSessionsPerRoom")]
+    pub inbound_group_sessions_per_room: Map,
+
+    /// The number of Olm sessions.
+    #[wasm_bindgen(readonly, js_name = "olmSessions")]
+    pub olm_sessions: u32,
+
+    /// The number of users whose devices we are tracking.
+    #[wasm_bindgen(readonly, js_name = "trackedUsers")]
+    pub tracked_users: u32,
+
+    /// The number of devices.
+    #[wasm_bindgen(readonly)]
+    pub devices: u32,
+
+    /// The number of cross-signing identities.
+    #[wasm_bindgen(readonly)]
+    pub identities: u32,
+
+    /// The number of secrets waiting in the secrets inbox.
+    #[wasm_bindgen(readonly, js_name = "secretsInInbox")]
+    pub secrets_in_inbox: u32,
+}
+
+/// An object in a crypto store which could not be read, as returned by
+/// {@link StoreHandle.verifyIntegrity}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct UnreadableRecord {
+    /// The kind of object, such as `account`, `inboundGroupSessions`,
+    /// `devices` or `olmSessions`.
+    #[wasm_bindgen(readonly)]
+    pub kind: String,
+
+    /// What identifies the object within its kind, such as a user ID for
+    /// `devices`, or a Curve25519 key for `olmSessions`. Empty if there is only
+    /// one object of this kind, or if all the objects of this kind are
+    /// loaded together.
+    #[wasm_bindgen(readonly)]
+    pub id: String,
+
+    /// The error which occurred when reading the object.
+    #[wasm_bindgen(readonly)]
+    pub error: String,
+}
+
+/// The result of walking through the whole store.
+#[derive(Debug, Default)]
+pub(super) struct StoreWalk {
+    room_key_counts: matrix_sdk_crypto::store::RoomKeyCounts,
+    inbound_group_sessions_per_room: BTreeMap<OwnedRoomId, u32>,
+    olm_sessions: u32,
+    tracked_users: u32,
+    devices: u32,
+    identities: u32,
+    secrets_in_inbox: u32,
+    unreadable: Vec<UnreadableRecord>,
+}
+
+impl StoreWalk {
+    /// Read every object in the store that we know how to enumerate,
+    /// recording what could not be read rather than stopping at the first
+    /// failure.
+    ///
+    /// Objects are loaded with the same granularity as the store API allows:
+    /// for example, all the room keys are loaded at once, so if one of them
+    /// is unreadable they are all reported as unreadable together.
+    pub(super) async fn run(store: &DynCryptoStore) -> Self {
+        let mut walk = Self::default();
+
+        if let Err(e) = store.load_account().await {
+            walk.unreadable("account", "", e);
+        }
+
+        if let Err(e) = store.load_identity().await {
+            walk.unreadable("privateIdentity", "", e);
+        }
+
+        let backup_version = match store.load_backup_keys().await {
+            Ok(keys) => keys.backup_version,
+            Err(e) => {
+                walk.unreadable("backupKeys", "", e);
+                None
+            }
+        };
+
+        match store.inbound_group_session_counts(backup_version.as_deref()).await {
+            Ok(counts) => walk.room_key_counts = counts,
+            Err(e) => walk.unreadable("roomKeyCounts", "", e),
+        }
+
+        match store.get_inbound_group_sessions().await {
+            Ok(sessions) => {
+                for session in sessions {
+                    *walk
+                        .inbound_group_sessions_per_room
+                        .entry(session.room_id().to_owned())
+                        .or_default() += 1;
+                }
+            }
+            Err(e) => walk.unreadable("inboundGroupSessions", "", e),
+        }
+
+        for room_id in walk.inbound_group_sessions_per_room.keys().cloned().collect::<Vec<_>>() {
+            if let Err(e) = store.get_room_settings(&room_id).await {
+                walk.unreadable("roomSettings", room_id.as_str(), e);
+            }
+        }
+
+        let tracked_users = match store.load_tracked_users().await {
+            Ok(users) => users,
+            Err(e) => {
+                walk.unreadable("trackedUsers", "", e);
+                Vec::new()
+            }
+        };
+        walk.tracked_users = tracked_users.len().try_into().unwrap_or(u32::MAX);
+
+        for user in tracked_users {
+            let user_id = user.user_id;
+
+            match store.get_user_identity(&user_id).await {
+                Ok(Some(_)) => walk.identities += 1,
+                Ok(None) => {}
+                Err(e) => walk.unreadable("identities", user_id.as_str(), e),
+            }
+
+            let devices = match store.get_user_devices(&user_id).await {
+                Ok(devices) => devices,
+                Err(e) => {
+                    walk.unreadable("devices", user_id.as_str(), e);
+                    continue;
+                }
+            };
+
+            for device in devices.values() {
+                walk.devices += 1;
+
+                let Some(curve_key) = device.curve25519_key() else { continue };
+                let curve_key = curve_key.to_base64();
+
+                match store.get_sessions(&curve_key).await {
+                    Ok(sessions) => {
+                        let count = sessions.map_or(0, |sessions| sessions.len());
+                        walk.olm_sessions += u32::try_from(count).unwrap_or(u32::MAX);
+                    }
+                    Err(e) => walk.unreadable("olmSessions", &curve_key, e),
+                }
+            }
+        }
+
+        for secret_name in INBOX_SECRET_NAMES {
+            match store.get_secrets_from_inbox(&secret_name).await {
+                Ok(secrets) => {
+                    walk.secrets_in_inbox += secrets.len().try_into().unwrap_or(u32::MAX)
+                }
+                Err(e) => walk.unreadable("secretsInbox", secret_name.as_str(), e),
+            }
+        }
+
+        walk
+    }
+
+    fn unreadable(&mut self, kind: &str, id: &str, error: impl std::fmt::Display) {
+        self.unreadable.push(UnreadableRecord {
+            kind: kind.to_owned(),
+            id: id.to_owned(),
+            error: error.to_string(),
+        });
+    }
+
+    /// The objects which could not be read, as a JS array of
+    /// {@link UnreadableRecord}.
+    pub(super) fn unreadable_records(&self) -> Array {
+        self.unreadable.iter().cloned().map(JsValue::from).collect()
+    }
+}
+
+impl From<StoreWalk> for StoreStatistics {
+    fn from(walk: StoreWalk) -> Self {
+        let inbound_group_sessions_per_room = Map::new();
+        for (room_id, count) in walk.inbound_group_sessions_per_room {
+            inbound_group_sessions_per_room.set(&room_id.as_str().into(), &count.into());
+        }
+
+        Self {
+            room_key_counts: walk.room_key_counts.into(),
+            inbound_group_sessions_per_room,
+            olm_sessions: walk.olm_sessions,
+            tracked_users: walk.tracked_users,
+            devices: walk.devices,
+            identities: walk.identities,
+            secrets_in_inbox: walk.secrets_in_inbox,
+        }
+    }
+}
@@ -1,9 +1,28 @@
 //! `GET /_matrix/client/*/sync`
 
-use matrix_sdk_common::ruma;
+use std::collections::{BTreeMap, BTreeSet};
+
+use js_sys::{Array, Map};
+use matrix_sdk_common::ruma::{
+    self,
+    events::{
+        room::{encryption::RoomEncryptionEventContent, member::MembershipState},
+        AnyToDeviceEvent,
+    },
+    serde::Raw,
+    OneTimeKeyAlgorithm, OwnedRoomId, OwnedUserId, UInt,
+};
+use matrix_sdk_crypto::CryptoStoreError;
+use serde::Deserialize;
+use tracing::warn;
 use wasm_bindgen::prelude::*;
 
-use crate::identifiers;
+use crate::{
+    identifiers,
+    room_members::{MembershipChanges, RoomMembersLocks},
+    room_settings,
+    to_device::ProcessedSyncChanges,
+};
 
 /// Information on E2E device updates.
 #[wasm_bindgen]
@@ -54,3 +73,231 @@ impl DeviceLists {
         self.inner.left.iter().map(|user| identifiers::UserId::from(user.clone())).collect()
     }
 }
+
+/// The parts of a `/sync` response which matter to the `OlmMachine`, as
+/// passed to {@link OlmMachine.receiveSyncResponse}.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct SyncResponse {
+    pub next_batch: Option<String>,
+
+    pub to_device: ToDevice,
+
+    #[serde(flatten)]
+    pub e2ee: E2ee,
+
+    pub rooms: Rooms,
+}
+
+/// The to-device events of a `/sync` response, or the `to_device` extension
+/// of a sliding sync response, which has its own `next_batch` token.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct ToDevice {
+    pub next_batch: Option<String>,
+    pub events: Vec<Raw<AnyToDeviceEvent>>,
+}
+
+/// The device list changes and key counts of a `/sync` response, or the
+/// `e2ee` extension of a sliding sync response.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct E2ee {
+    #[serde(alias = "org.matrix.msc3202.device_lists")]
+    pub device_lists: ruma::api::client::sync::sync_events::DeviceLists,
+
+    #[serde(alias = "org.matrix.msc3202.device_one_time_keys_count")]
+    pub device_one_time_keys_count: BTreeMap<OneTimeKeyAlgorithm, UInt>,
+
+    #[serde(
+        alias = "org.matrix.msc2732.device_unused_fallback_key_types",
+        alias = "org.matrix.msc3202.device_unused_fallback_key_types"
+    )]
+    pub device_unused_fallback_key_types: Option<Vec<OneTimeKeyAlgorithm>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Rooms {
+    pub join: BTreeMap<OwnedRoomId, JoinedRoom>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct JoinedRoom {
+    pub state: RoomEvents,
+    pub timeline: RoomEvents,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct RoomEvents {
+    pub events: Vec<Raw<RoomEvent>>,
+}
+
+/// The fields we look at in the events of a room. Each event is deserialized
+/// on its own, so that one bad event does not spoil the whole response.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoomEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub state_key: Option<String>,
+    pub content: serde_json::Value,
+}
+
+/// What changed in the encrypted rooms of a `/sync` response.
+#[derive(Debug, Default)]
+pub(crate) struct RoomChanges {
+    /// The rooms which we now know are encrypted.
+    pub encrypted_rooms: Vec<OwnedRoomId>,
+
+    /// The users who joined, or were invited to, each encrypted room.
+    pub new_members: BTreeMap<OwnedRoomId, BTreeSet<OwnedUserId>>,
+
+    /// The users who left, or were banned from, each encrypted room.
+    pub departed_members: BTreeMap<OwnedRoomId, BTreeSet<OwnedUserId>>,
+}
+
+impl RoomChanges {
+    /// Work out what changed in the joined rooms of a `/sync` response.
+    ///
+    /// The settings of the rooms which are newly encrypted are stored, and the
+    /// membership changes of encrypted rooms are applied to their tracked
+    /// members, holding their locks in `locks`.
+    pub(crate) async fn apply(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        locks: &RoomMembersLocks,
+        rooms: Rooms,
+    ) -> Result<Self, CryptoStoreError> {
+        let mut changes = Self::default();
+
+        for (room_id, room) in rooms.join {
+            let events = room
+                .state
+                .events
+                .iter()
+                .chain(&room.timeline.events)
+                .filter_map(|event| event.deserialize().ok())
+                .filter(|event| event.state_key.is_some());
+
+            let mut encrypted = machine.room_settings(&room_id).await?.is_some();
+            let mut membership_changes = MembershipChanges::default();
+
+            for event in events {
+                if event.event_type == "m.room.encryption" && !encrypted {
+                    let Ok(content) =
+                        serde_json::from_value::<RoomEncryptionEventContent>(event.content)
+                    else {
+                        continue;
+                    };
+
+                    match room_settings::store_room_settings(machine, &room_id, &content).await {
+                        Ok(_) => {
+                            encrypted = true;
+                            changes.encrypted_rooms.push(room_id.clone());
+                        }
+                        Err(e) => warn!(?room_id, "Could not store the room settings: {e}"),
+                    }
+                } else {
+                    membership_changes.add(&event);
+                }
+            }
+
+            if !encrypted {
+                continue;
+            }
+
+            for (user_id, membership) in &membership_changes.memberships {
+                let members = match membership {
+                    MembershipState::Join | MembershipState::Invite => &mut changes.new_members,
+                    MembershipState::Leave | MembershipState::Ban => &mut changes.departed_members,
+                    _ => continue,
+                };
+                members.entry(room_id.clone()).or_default().insert(user_id.clone());
+            }
+
+            membership_changes.apply(machine, locks, &room_id).await?;
+        }
+
+        Ok(changes)
+    }
+}
+
+/// What {@link OlmMachine.receiveSyncResponse} found in a `/sync` response.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct SyncResponseSummary {
+    /// The decrypted to-device events: a JSON-encoded string if the `/sync`
+    /// response was a string, or an array of plain objects otherwise.
+    #[wasm_bindgen(readonly, js_name = "toDeviceEvents")]
+    pub to_device_events: JsValue,
+
+    /// The number of encrypted to-device events, as in {@link
+    /// ProcessedSyncChanges.encryptedEvents}.
+    #[wasm_bindgen(readonly, js_name = "encryptedToDeviceEvents")]
+    pub encrypted_to_device_events: u32,
+
+    /// The encrypted to-device events which could not be decrypted.
+    ///
+    /// Typescript type: `UndecryptedToDeviceEvent[]`.
+    #[wasm_bindgen(readonly, js_name = "undecryptedToDeviceEvents")]
+    pub undecrypted_to_device_events: Array,
+
+    /// The room keys which were received.
+    ///
+    /// Typescript type: `RoomKeyInfo[]`.
+    #[wasm_bindgen(readonly, js_name = "roomKeyInfos")]
+    pub room_key_infos: Array,
+
+    /// The rooms whose `m.room.encryption` state event was seen for the first
+    /// time, and whose settings have been stored.
+    ///
+    /// Typescript type: `RoomId[]`.
+    #[wasm_bindgen(readonly, js_name = "encryptedRooms")]
+    pub encrypted_rooms: Array,
+
+    /// The users who joined, or were invited to, each encrypted room. Those
+    /// who receive its room keys are now tracked.
+    ///
+    /// Typescript type: `Map<string, UserId[]>`.
+    #[wasm_bindgen(readonly, js_name = "newMembers")]
+    pub new_members: Map,
+
+    /// The users who left, or were banned from, each encrypted room. The
+    /// current room key is discarded, so that they cannot read the next
+    /// messages.
+    ///
+    /// Typescript type: `Map<string, UserId[]>`.
+    #[wasm_bindgen(readonly, js_name = "departedMembers")]
+    pub departed_members: Map,
+}
+
+impl SyncResponseSummary {
+    pub(crate) fn new(processed: ProcessedSyncChanges, changes: RoomChanges) -> Self {
+        let members_to_map = |members: BTreeMap<OwnedRoomId, BTreeSet<OwnedUserId>>| {
+            let map = Map::new();
+            for (room_id, users) in members {
+                let users = users
+                    .into_iter()
+                    .map(|user_id| JsValue::from(identifiers::UserId::from(user_id)))
+                    .collect::<Array>();
+                map.set(&room_id.as_str().into(), &users);
+            }
+            map
+        };
+
+        Self {
+            to_device_events: processed.to_device_events,
+            encrypted_to_device_events: processed.encrypted_events,
+            undecrypted_to_device_events: processed.undecrypted_events,
+            room_key_infos: processed.room_key_infos,
+            encrypted_rooms: changes
+                .encrypted_rooms
+                .into_iter()
+                .map(|room_id| JsValue::from(identifiers::RoomId::from(room_id)))
+                .collect(),
+            new_members: members_to_map(changes.new_members),
+            departed_members: members_to_map(changes.departed_members),
+        }
+    }
+}
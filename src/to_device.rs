@@ -0,0 +1,196 @@
+//! The outcome of processing to-device events.
+
+use std::collections::BTreeSet;
+
+use js_sys::Array;
+use matrix_sdk_common::ruma::{events::AnyToDeviceEvent, serde::Raw};
+use matrix_sdk_crypto::{
+    store::RoomKeyInfo,
+    types::events::room::encrypted::{EncryptedToDeviceEvent, ToDeviceEncryptedEventContent},
+    vodozemac::olm::OlmMessage,
+    CryptoStoreError,
+};
+use wasm_bindgen::prelude::*;
+
+use crate::{json, store};
+
+/// Why an encrypted to-device event could not be decrypted.
+///
+/// The underlying machine does not report why it could not decrypt an event,
+/// so this is worked out afterwards, from the event and from the state of the
+/// store. {@link UnsupportedAlgorithm}, {@link MissingCiphertext} and {@link
+/// Malformed} are certain; the other codes are the most likely cause, and
+/// should be treated as hints. In particular, an Olm message which was
+/// already decrypted before is reported as {@link BadMessage} or {@link
+/// PreKeyMessageRejected}, as replays cannot be told apart.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OlmDecryptionErrorCode {
+    /// The event is not encrypted with `m.olm.v1.curve25519-aes-sha2`.
+    UnsupportedAlgorithm,
+
+    /// The event has no ciphertext for this device.
+    MissingCiphertext,
+
+    /// The event is not a valid Olm-encrypted event.
+    Malformed,
+
+    /// The event is a normal Olm message, but we have no Olm session with
+    /// its sender. It was probably encrypted with a session whose pre-key
+    /// message never reached us.
+    MissingSession,
+
+    /// The event is a normal Olm message which none of our Olm sessions with
+    /// its sender could decrypt, because its MAC does not match or its
+    /// session is wedged. A new session needs to be established, with {@link
+    /// OlmMachine.forceNewOlmSession}.
+    BadMessage,
+
+    /// The event is a pre-key Olm message which we could not create a session
+    /// from, most likely because the one-time key it uses is unknown or was
+    /// already used.
+    PreKeyMessageRejected,
+}
+
+/// An encrypted to-device event which {@link OlmMachine.processSyncChanges}
+/// could not decrypt.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct UndecryptedToDeviceEvent {
+    /// The event, as it was received. It is a JSON-encoded string if the
+    /// events were passed as a string, or a plain object otherwise.
+    #[wasm_bindgen(readonly)]
+    pub event: JsValue,
+
+    /// Why the event could not be decrypted.
+    #[wasm_bindgen(readonly, js_name = "errorCode")]
+    pub error_code: OlmDecryptionErrorCode,
+}
+
+/// The outcome of {@link OlmMachine.processSyncChanges}.
+///
+/// The underlying machine does not tell which of the events it returns it
+/// decrypted, so only the events it could not decrypt are reported
+/// individually: it returns those untouched.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct ProcessedSyncChanges {
+    /// The to-device events, as returned by {@link
+    /// OlmMachine.receiveSyncChanges}: a JSON-encoded string if the events
+    /// were passed as a string, or an array of plain objects otherwise.
+    #[wasm_bindgen(readonly, js_name = "toDeviceEvents")]
+    pub to_device_events: JsValue,
+
+    /// The number of encrypted (`m.room.encrypted`) to-device events which
+    /// were received.
+    ///
+    /// Those which are not in {@link undecryptedEvents} were decrypted,
+    /// though the machine leaves out of {@link toDeviceEvents} the ones sent
+    /// by dehydrated devices.
+    #[wasm_bindgen(readonly, js_name = "encryptedEvents")]
+    pub encrypted_events: u32,
+
+    /// The encrypted to-device events which could not be decrypted.
+    ///
+    /// Typescript type: `UndecryptedToDeviceEvent[]`.
+    #[wasm_bindgen(readonly, js_name = "undecryptedEvents")]
+    pub undecrypted_events: Array,
+
+    /// The room keys which were received.
+    ///
+    /// Typescript type: `RoomKeyInfo[]`.
+    #[wasm_bindgen(readonly, js_name = "roomKeyInfos")]
+    pub room_key_infos: Array,
+}
+
+/// The encrypted to-device events passed to the underlying machine, recorded
+/// before it processes them, so that those it could not decrypt can be found
+/// in its output.
+pub(crate) struct ReceivedToDeviceEvents {
+    count: usize,
+
+    /// The JSON of the events, which the machine returns untouched unless it
+    /// decrypted them.
+    json: BTreeSet<String>,
+}
+
+impl ReceivedToDeviceEvents {
+    /// Record the encrypted events among the given ones, before passing them
+    /// to the machine.
+    pub(crate) fn record(events: &[Raw<AnyToDeviceEvent>]) -> Self {
+        let encrypted = events
+            .iter()
+            .filter(|event| {
+                event.get_field::<String>("type").ok().flatten().as_deref()
+                    == Some("m.room.encrypted")
+            })
+            .map(|event| event.json().get().to_owned())
+            .collect::<Vec<_>>();
+
+        Self { count: encrypted.len(), json: encrypted.into_iter().collect() }
+    }
+}
+
+impl ProcessedSyncChanges {
+    /// Report what happened to the encrypted events recorded in `received`,
+    /// from the events returned by the underlying machine.
+    pub(crate) async fn new(
+        machine: &matrix_sdk_crypto::OlmMachine,
+        received: ReceivedToDeviceEvents,
+        to_device_events: Vec<Raw<AnyToDeviceEvent>>,
+        room_key_infos: Vec<RoomKeyInfo>,
+        as_object: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let undecrypted_events = Array::new();
+
+        for raw_event in &to_device_events {
+            if !received.json.contains(raw_event.json().get()) {
+                continue;
+            }
+
+            let event = UndecryptedToDeviceEvent {
+                event: json::raw_to_string_or_object(raw_event, as_object)?,
+                error_code: olm_error_code(machine, raw_event).await?,
+            };
+            undecrypted_events.push(&event.into());
+        }
+
+        Ok(Self {
+            to_device_events: json::raw_to_string_or_object(&to_device_events, as_object)?,
+            encrypted_events: received.count.try_into().unwrap_or(u32::MAX),
+            undecrypted_events,
+            room_key_infos: room_key_infos
+                .into_iter()
+                .map(|info| JsValue::from(store::RoomKeyInfo::from(info)))
+                .collect(),
+        })
+    }
+}
+
+/// Work out why the machine could not decrypt the given event.
+async fn olm_error_code(
+    machine: &matrix_sdk_crypto::OlmMachine,
+    raw_event: &Raw<AnyToDeviceEvent>,
+) -> Result<OlmDecryptionErrorCode, CryptoStoreError> {
+    let Ok(event) = raw_event.deserialize_as::<EncryptedToDeviceEvent>() else {
+        return Ok(OlmDecryptionErrorCode::Malformed);
+    };
+    let ToDeviceEncryptedEventContent::OlmV1Curve25519AesSha2(content) = event.content else {
+        return Ok(OlmDecryptionErrorCode::UnsupportedAlgorithm);
+    };
+
+    Ok(if content.recipient_key != machine.identity_keys().curve25519 {
+        OlmDecryptionErrorCode::MissingCiphertext
+    } else if matches!(content.ciphertext, OlmMessage::PreKey(_)) {
+        OlmDecryptionErrorCode::PreKeyMessageRejected
+    } else if machine
+        .store()
+        .get_sessions(&content.sender_key.to_base64())
+        .await?
+        .is_some_and(|sessions| !sessions.is_empty())
+    {
+        OlmDecryptionErrorCode::BadMessage
+    } else {
+        OlmDecryptionErrorCode::MissingSession
+    })
+}
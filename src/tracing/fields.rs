@@ -0,0 +1,77 @@
+//! Collection of the fields of `tracing` events and spans, shared by the
+//! layers which need structured access to them.
+
+use std::fmt;
+
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// The fields recorded on a span, stored in the span's extensions by
+/// [`SpanFieldsLayer`].
+#[derive(Debug, Default)]
+pub(super) struct SpanFields(pub(super) Vec<(String, String)>);
+
+/// A [`Visit`]or which collects the fields of an event or span as strings.
+///
+/// The `message` field, if any, is kept separately.
+#[derive(Debug, Default)]
+pub(super) struct FieldCollector {
+    pub(super) message: String,
+    pub(super) fields: Vec<(String, String)>,
+}
+
+impl Visit for FieldCollector {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_owned();
+        } else {
+            self.fields.push((field.name().to_owned(), value.to_owned()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields.push((field.name().to_owned(), format!("{value:?}")));
+        }
+    }
+}
+
+/// A [`Layer`] which records the fields of each span as [`SpanFields`].
+///
+/// This layer must be filtered by the union of the filters of the layers
+/// which use the fields, so that the fields are available to each of them,
+/// without formatting the fields of the spans which none of them records.
+#[derive(Debug, Default)]
+pub(super) struct SpanFieldsLayer;
+
+impl<S> Layer<S> for SpanFieldsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut collector = FieldCollector::default();
+        attrs.record(&mut collector);
+
+        span.extensions_mut().insert(SpanFields(collector.fields));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut collector = FieldCollector::default();
+        values.record(&mut collector);
+
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(fields) => fields.0.extend(collector.fields),
+            None => extensions.insert(SpanFields(collector.fields)),
+        }
+    }
+}
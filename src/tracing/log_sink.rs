@@ -0,0 +1,151 @@
+//! A `tracing` layer which passes every log record to a JavaScript callback,
+//! as a structured object.
+
+use std::cell::RefCell;
+
+use js_sys::{Array, Date, Function, Object, Reflect};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use wasm_bindgen::prelude::*;
+
+use super::{
+    fields::{FieldCollector, SpanFields},
+    LoggerLevel,
+};
+
+thread_local! {
+    /// The callback registered via `Tracing.setLogSink`, if any.
+    ///
+    /// Layers need to be `Send` and `Sync`, whereas JS functions are not, so
+    /// the callback is kept in a thread-local. There is only ever one thread
+    /// in the WASM environment, so in practice this is not a limitation.
+    static LOG_SINK: RefCell<Option<Function>> = const { RefCell::new(None) };
+}
+
+/// Register the callback which will receive the log records, or remove it if
+/// `callback` is `None`.
+pub(super) fn set_log_sink(callback: Option<Function>) {
+    LOG_SINK.with(|sink| *sink.borrow_mut() = callback);
+}
+
+/// A log record, as passed to the callback registered with
+/// {@link Tracing.setLogSink}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct LogRecord {
+    /// The level of the record.
+    #[wasm_bindgen(readonly)]
+    pub level: LoggerLevel,
+
+    /// The target of the record, usually the Rust module path where it was
+    /// emitted, e.g. `matrix_sdk_crypto::olm::account`.
+    #[wasm_bindgen(readonly)]
+    pub target: String,
+
+    /// The log message.
+    #[wasm_bindgen(readonly)]
+    pub message: String,
+
+    /// The other fields of the record, as an object mapping the field names
+    /// to their formatted values.
+    ///
+    /// Typescript type: `Record<string, string>`.
+    #[wasm_bindgen(readonly)]
+    pub fields: Object,
+
+    /// The spans that were active when the record was emitted, outermost
+    /// first.
+    ///
+    /// Typescript type: `LogSpan[]`.
+    #[wasm_bindgen(readonly)]
+    pub spans: Array,
+
+    /// The time at which the record was emitted, in milliseconds since the
+    /// Unix epoch.
+    #[wasm_bindgen(readonly)]
+    pub timestamp: f64,
+}
+
+/// A span that was active when a {@link LogRecord} was emitted.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct LogSpan {
+    /// The name of the span.
+    #[wasm_bindgen(readonly)]
+    pub name: String,
+
+    /// The target of the span.
+    #[wasm_bindgen(readonly)]
+    pub target: String,
+
+    /// The fields recorded on the span, as an object mapping the field names
+    /// to their formatted values.
+    ///
+    /// Typescript type: `Record<string, string>`.
+    #[wasm_bindgen(readonly)]
+    pub fields: Object,
+}
+
+/// Turn a list of fields into a JS object.
+fn fields_to_object(fields: &[(String, String)]) -> Object {
+    let object = Object::new();
+
+    for (name, value) in fields {
+        // Setting a property on a fresh object cannot fail.
+        let _ = Reflect::set(&object, &name.into(), &value.into());
+    }
+
+    object
+}
+
+/// A [`Layer`] which passes each event to the callback registered with
+/// [`set_log_sink`].
+#[derive(Debug, Default)]
+pub(super) struct LogSinkLayer;
+
+impl<S> Layer<S> for LogSinkLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // Take a copy of the callback, rather than calling it while the
+        // thread-local is borrowed, so that the callback can itself replace
+        // the sink.
+        let Some(callback) = LOG_SINK.with(|sink| sink.borrow().clone()) else { return };
+
+        let metadata = event.metadata();
+
+        let mut collector = FieldCollector::default();
+        event.record(&mut collector);
+
+        let spans = Array::new();
+
+        for span in ctx.event_scope(event).into_iter().flat_map(|scope| scope.from_root()) {
+            let extensions = span.extensions();
+            let fields = extensions.get::<SpanFields>().map(|f| f.0.as_slice()).unwrap_or_default();
+
+            spans.push(
+                &LogSpan {
+                    name: span.name().to_owned(),
+                    target: span.metadata().target().to_owned(),
+                    fields: fields_to_object(fields),
+                }
+                .into(),
+            );
+        }
+
+        let record = LogRecord {
+            level: metadata.level().into(),
+            target: metadata.target().to_owned(),
+            message: collector.message,
+            fields: fields_to_object(&collector.fields),
+            spans,
+            timestamp: Date::now(),
+        };
+
+        // There is nowhere sensible to report an exception thrown by the
+        // callback (logging it would just bring us back here), so we ignore
+        // it.
+        let _ = callback.call1(&JsValue::NULL, &record.into());
+    }
+}
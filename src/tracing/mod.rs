@@ -0,0 +1,426 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use js_sys::Function;
+use matrix_sdk_common::js_tracing::{JsEventFormatter, MakeJsLogWriter};
+use tracing::Level;
+use tracing_subscriber::{
+    filter::{FilterExt, LevelFilter, Targets},
+    prelude::*,
+    reload, Registry,
+};
+use wasm_bindgen::prelude::*;
+
+use self::{
+    fields::SpanFieldsLayer, log_sink::LogSinkLayer, ring_buffer::RingBufferLayer,
+    timings::TimingLayer,
+};
+
+mod fields;
+mod log_sink;
+mod ring_buffer;
+mod timings;
+
+/// Logger level.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub enum LoggerLevel {
+    /// `TRACE` level.
+    ///
+    /// Designate very low priority, often extremely verbose,
+    /// information.
+    Trace,
+
+    /// `DEBUG` level.
+    ///
+    /// Designate lower priority information.
+    Debug,
+
+    /// `INFO` level.
+    ///
+    /// Designate useful information.
+    Info,
+
+    /// `WARN` level.
+    ///
+    /// Designate hazardous situations.
+    Warn,
+
+    /// `ERROR` level.
+    ///
+    /// Designate very serious errors.
+    Error,
+}
+
+/// Internal state.
+///
+/// This is a singleton: there is at most one instance in the entire process
+struct TracingInner {
+    /// The log level last set by `min_level`
+    level: Level,
+
+    /// The per-target directives last set by `set_filter_directives`
+    directives: Targets,
+
+    /// Whether the logger is on, i.e. `turn_off` was not called since it was
+    /// last turned on. The level and directives are kept while it is off.
+    enabled: bool,
+
+    /// The filter for the console and the log sink.
+    filter_reload_handle: FilterReloadHandles<Targets>,
+
+    /// The filter for the buffer of recent records, which is independent of
+    /// the console filter.
+    recent_logs_filter_reload_handle: FilterReloadHandles<LevelFilter>,
+
+    /// The filter for the spans whose timings are measured, which is
+    /// independent of the console filter.
+    timings_filter_reload_handle: reload::Handle<LevelFilter, Registry>,
+}
+
+impl TracingInner {
+    /// The filter to use when the logger is turned on: the per-target
+    /// directives, falling back to `level` for any other target unless the
+    /// directives include a default level of their own.
+    fn filter(&self) -> Targets {
+        match self.directives.default_level() {
+            Some(_) => self.directives.clone(),
+            None => self.directives.clone().with_default(self.level),
+        }
+    }
+
+    /// Update the filter of the console and the log sink, with the current
+    /// level and directives if the logger is on, or disabling everything
+    /// otherwise.
+    fn apply(&self) -> Result<(), JsError> {
+        let filter = if self.enabled { self.filter() } else { Targets::new() };
+        self.filter_reload_handle.set(filter)?;
+        Ok(())
+    }
+}
+
+/// The handles to reload a filter of layers which use the fields of the
+/// spans, and the copy of that filter in the filter of the
+/// [`SpanFieldsLayer`], which records those fields only for the spans that
+/// one of the layers lets through.
+struct FilterReloadHandles<F> {
+    layers: reload::Handle<F, Registry>,
+    span_fields: reload::Handle<F, Registry>,
+}
+
+impl<F: Clone> FilterReloadHandles<F> {
+    /// Create the two reloadable copies of `filter`: for the layers, then for
+    /// the [`SpanFieldsLayer`].
+    fn new(filter: F) -> (reload::Layer<F, Registry>, reload::Layer<F, Registry>, Self) {
+        let (layers_filter, layers) = reload::Layer::new(filter.clone());
+        let (span_fields_filter, span_fields) = reload::Layer::new(filter);
+
+        (layers_filter, span_fields_filter, Self { layers, span_fields })
+    }
+
+    /// Replace both copies of the filter.
+    fn set(&self, filter: F) -> Result<(), reload::Error> {
+        // The fields must be recorded before the layers need them.
+        self.span_fields.modify(|f| *f = filter.clone())?;
+        self.layers.modify(|f| *f = filter)
+    }
+}
+
+/// Type to install and to manipulate the tracing layer.
+#[wasm_bindgen]
+pub struct Tracing {
+    inner: Arc<Mutex<TracingInner>>,
+}
+
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracing").finish_non_exhaustive()
+    }
+}
+
+#[wasm_bindgen]
+impl Tracing {
+    /// Check whether the `tracing` feature has been enabled.
+    ///
+    /// @deprecated: `tracing` is now always enabled.
+    #[wasm_bindgen(js_name = "isAvailable")]
+    pub fn is_available() -> bool {
+        true
+    }
+
+    fn install_or_get_inner() -> Arc<Mutex<TracingInner>> {
+        static INSTALL: OnceLock<Arc<Mutex<TracingInner>>> = OnceLock::new();
+
+        // if this is the first Tracing to be created, create the TracingInner singleton
+        // and stash it in `INSTALL`
+        INSTALL
+            .get_or_init(|| {
+                // An empty `Targets` filter, with no default level, disables everything.
+                let (filter, span_fields_filter, filter_reload_handle) =
+                    FilterReloadHandles::new(Targets::new());
+                let (
+                    recent_logs_filter,
+                    span_fields_recent_logs_filter,
+                    recent_logs_filter_reload_handle,
+                ) = FilterReloadHandles::new(LevelFilter::OFF);
+                let (timings_filter, timings_filter_reload_handle) =
+                    reload::Layer::new(LevelFilter::OFF);
+
+                let console = tracing_subscriber::fmt::layer()
+                    .with_writer(MakeJsLogWriter::new())
+                    .with_ansi(false)
+                    .event_format(JsEventFormatter::new());
+
+                // Each of the layers has its own filter, so that the buffer of recent records
+                // can be more verbose than the console. The fields of the spans are only
+                // recorded for the log sink and the buffer, which use them: the timings don't.
+                let layers = SpanFieldsLayer
+                    .with_filter(span_fields_filter.or(span_fields_recent_logs_filter))
+                    .and_then(console.and_then(LogSinkLayer).with_filter(filter))
+                    .and_then(RingBufferLayer.with_filter(recent_logs_filter))
+                    .and_then(TimingLayer.with_filter(timings_filter));
+
+                tracing_subscriber::registry().with(layers).init();
+
+                Arc::new(Mutex::new(TracingInner {
+                    level: Level::ERROR,
+                    directives: Targets::new(),
+                    enabled: false,
+                    filter_reload_handle,
+                    recent_logs_filter_reload_handle,
+                    timings_filter_reload_handle,
+                }))
+            })
+            .clone()
+    }
+
+    /// Install the tracing layer.
+    #[wasm_bindgen(constructor)]
+    pub fn new(min_level: LoggerLevel) -> Result<Tracing, JsError> {
+        let tracing = Tracing { inner: Tracing::install_or_get_inner() };
+        tracing.min_level(min_level)?;
+
+        Ok(tracing)
+    }
+
+    /// Re-define the minimum logger level, and turn the logger on.
+    #[wasm_bindgen(setter, js_name = "minLevel")]
+    pub fn min_level(&self, min_level: LoggerLevel) -> Result<(), JsError> {
+        let mut inner = self.inner.lock()?;
+        // we store the level in `inner.level`, so that `turn_on` knows what to restore
+        // it to.
+        inner.level = min_level.into();
+        inner.enabled = true;
+        inner.apply()
+    }
+
+    /// Set per-target filter directives, which override the minimum logger
+    /// level for the given targets.
+    ///
+    /// `directives` is a comma-separated list of `target=level` pairs, such
+    /// as `matrix_sdk_crypto::olm=trace,matrix_sdk_indexeddb=warn`. A target
+    /// matches itself and any of its submodules, and the most specific
+    /// target wins. A bare level, such as `info`, replaces the minimum logger
+    /// level for any target which is not listed. Pass an empty string to
+    /// remove all directives.
+    ///
+    /// If the logger is turned off, the directives are only used once it is
+    /// turned on again.
+    ///
+    /// Throws if the directives cannot be parsed.
+    #[wasm_bindgen(js_name = "setFilterDirectives")]
+    pub fn set_filter_directives(&self, directives: &str) -> Result<(), JsError> {
+        let directives = if directives.trim().is_empty() {
+            Targets::new()
+        } else {
+            directives.parse::<Targets>()?
+        };
+
+        let mut inner = self.inner.lock()?;
+        inner.directives = directives;
+        inner.apply()
+    }
+
+    /// Get the per-target filter directives which are currently in force,
+    /// as set by {@link setFilterDirectives}.
+    #[wasm_bindgen(getter, js_name = "filterDirectives")]
+    pub fn filter_directives(&self) -> Result<String, JsError> {
+        Ok(self.inner.lock()?.directives.to_string())
+    }
+
+    /// Turn the logger on, i.e. it emits logs again if it was turned
+    /// off.
+    #[wasm_bindgen(js_name = "turnOn")]
+    pub fn turn_on(&self) -> Result<(), JsError> {
+        let mut inner = self.inner.lock()?;
+        inner.enabled = true;
+        inner.apply()
+    }
+
+    /// Register a callback which will be called with a {@link LogRecord} for
+    /// every log record that passes the current logger level, in addition to
+    /// the record being written to the console.
+    ///
+    /// Only one callback can be registered at a time: registering a new one
+    /// replaces the previous one, and passing `undefined` removes it.
+    ///
+    /// The callback is called synchronously, so it should be cheap. Any
+    /// exception it throws is ignored.
+    #[wasm_bindgen(js_name = "setLogSink")]
+    pub fn set_log_sink(&self, callback: Option<Function>) {
+        log_sink::set_log_sink(callback);
+    }
+
+    /// Start keeping the most recent log records in memory, so that they can
+    /// be retrieved with {@link dumpRecentLogs}, for example to attach them
+    /// to a bug report.
+    ///
+    /// The records are kept independently of the records which are written to
+    /// the console: `level` is usually more verbose than the minimum logger
+    /// level. Turning the logger off does not stop the records from being
+    /// kept.
+    ///
+    /// Sensitive data, such as session keys, pickles and passphrases, is
+    /// redacted before the records are stored: the values of fields whose
+    /// names suggest it, the values which follow such names in the messages
+    /// and in the other fields, and any run of 43 or more base64 characters.
+    /// Such runs include keys and pickles, but also session IDs and public
+    /// keys, which cannot be told apart from secret keys of the same length.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - the minimum level of the records to keep.
+    ///
+    /// * `capacity` - the maximum number of records to keep. Once the buffer is
+    ///   full, the oldest records are dropped.
+    #[wasm_bindgen(js_name = "enableRecentLogs")]
+    pub fn enable_recent_logs(&self, level: LoggerLevel, capacity: usize) -> Result<(), JsError> {
+        ring_buffer::set_capacity(capacity);
+
+        let level = Level::from(level);
+        let inner = self.inner.lock()?;
+        inner.recent_logs_filter_reload_handle.set(LevelFilter::from_level(level))?;
+        Ok(())
+    }
+
+    /// Stop keeping recent log records in memory, and drop the records which
+    /// were kept so far.
+    #[wasm_bindgen(js_name = "disableRecentLogs")]
+    pub fn disable_recent_logs(&self) -> Result<(), JsError> {
+        let inner = self.inner.lock()?;
+        inner.recent_logs_filter_reload_handle.set(LevelFilter::OFF)?;
+
+        ring_buffer::set_capacity(0);
+        Ok(())
+    }
+
+    /// Get the log records kept since {@link enableRecentLogs} was called,
+    /// oldest first, formatted as text with one record per line.
+    #[wasm_bindgen(js_name = "dumpRecentLogs")]
+    pub fn dump_recent_logs(&self) -> String {
+        ring_buffer::dump()
+    }
+
+    /// Drop all the log records which were kept so far, without disabling the
+    /// buffer.
+    #[wasm_bindgen(js_name = "clearRecentLogs")]
+    pub fn clear_recent_logs(&self) {
+        ring_buffer::clear();
+    }
+
+    /// Start measuring how long spans take, such as the ones around
+    /// {@link OlmMachine.receiveSyncChanges},
+    /// {@link OlmMachine.shareRoomKey}, {@link OlmMachine.decryptRoomEvent}
+    /// and the store operations.
+    ///
+    /// The timings are aggregated per span target and name, and can be
+    /// retrieved with {@link getTimings}. Measuring is independent of the
+    /// minimum logger level, and is not affected by turning the logger off.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - the minimum level of the spans to measure.
+    ///
+    /// * `emit_performance_measures` - whether to also emit a
+    ///   `performance.measure` entry for each span, so that the spans show up
+    ///   in the browser's performance tools.
+    #[wasm_bindgen(js_name = "enableTimings")]
+    pub fn enable_timings(
+        &self,
+        level: LoggerLevel,
+        emit_performance_measures: bool,
+    ) -> Result<(), JsError> {
+        timings::set_emit_performance_measures(emit_performance_measures);
+
+        let level = Level::from(level);
+        let inner = self.inner.lock()?;
+        inner
+            .timings_filter_reload_handle
+            .modify(|filter| *filter = LevelFilter::from_level(level))?;
+        Ok(())
+    }
+
+    /// Stop measuring how long spans take. The timings collected so far are
+    /// kept until {@link resetTimings} is called.
+    #[wasm_bindgen(js_name = "disableTimings")]
+    pub fn disable_timings(&self) -> Result<(), JsError> {
+        timings::set_emit_performance_measures(false);
+
+        let inner = self.inner.lock()?;
+        inner.timings_filter_reload_handle.modify(|filter| *filter = LevelFilter::OFF)?;
+        Ok(())
+    }
+
+    /// Get the timings collected since {@link enableTimings} was called.
+    ///
+    /// # Returns
+    ///
+    /// A `Map<string, SpanTimings>`, from the `target::name` of the spans to
+    /// their {@link SpanTimings}.
+    #[wasm_bindgen(js_name = "getTimings")]
+    pub fn get_timings(&self) -> js_sys::Map {
+        timings::timings()
+    }
+
+    /// Forget the timings collected so far.
+    #[wasm_bindgen(js_name = "resetTimings")]
+    pub fn reset_timings(&self) {
+        timings::reset();
+    }
+
+    /// Turn the logger off, i.e. it no longer emits logs.
+    #[wasm_bindgen(js_name = "turnOff")]
+    pub fn turn_off(&self) -> Result<(), JsError> {
+        let mut inner = self.inner.lock()?;
+        inner.enabled = false;
+        inner.apply()
+    }
+}
+
+impl From<LoggerLevel> for Level {
+    fn from(value: LoggerLevel) -> Self {
+        use LoggerLevel::*;
+
+        match value {
+            Trace => Self::TRACE,
+            Debug => Self::DEBUG,
+            Info => Self::INFO,
+            Warn => Self::WARN,
+            Error => Self::ERROR,
+        }
+    }
+}
+
+impl From<&Level> for LoggerLevel {
+    fn from(value: &Level) -> Self {
+        match *value {
+            Level::TRACE => Self::Trace,
+            Level::DEBUG => Self::Debug,
+            Level::INFO => Self::Info,
+            Level::WARN => Self::Warn,
+            _ => Self::Error,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! A `tracing` layer which keeps the most recent log records in memory, so
+//! that they can be attached to bug reports.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Mutex, MutexGuard},
+};
+
+use js_sys::Date;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use wasm_bindgen::JsValue;
+
+use super::fields::{FieldCollector, SpanFields};
+
+/// The placeholder which replaces the value of sensitive fields.
+const REDACTED: &str = "<redacted>";
+
+/// Parts of field names which indicate that the value of the field is
+/// sensitive, and must not be kept in the buffer.
+///
+/// Identifiers, such as session IDs and the public `sender_key`, are useful
+/// when debugging and are kept.
+const SENSITIVE_FIELD_NAMES: &[&str] =
+    &["session_key", "pickle", "passphrase", "password", "secret", "private", "recovery_key"];
+
+/// The minimum length of the runs of base64 characters which are redacted
+/// wherever they appear, such as session keys and pickles which are logged
+/// without a name.
+///
+/// This is the length of an unpadded Curve25519 or Ed25519 key, as the
+/// private ones and the room key material are this long too. Session IDs and
+/// public keys are redacted as well: they cannot be told apart.
+const MIN_REDACTED_BLOB_LENGTH: usize = 43;
+
+/// The buffer of recent records.
+static BUFFER: Mutex<RecentLogs> = Mutex::new(RecentLogs { capacity: 0, records: VecDeque::new() });
+
+/// A bounded buffer of log records, which drops the oldest record when it is
+/// full.
+#[derive(Debug)]
+struct RecentLogs {
+    capacity: usize,
+    records: VecDeque<BufferedRecord>,
+}
+
+/// A log record, with any sensitive fields already redacted.
+#[derive(Debug)]
+struct BufferedRecord {
+    timestamp: f64,
+    level: Level,
+    target: String,
+    spans: Vec<(&'static str, Vec<(String, String)>)>,
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+fn lock_buffer() -> MutexGuard<'static, RecentLogs> {
+    // A panic while the lock was held cannot have left the buffer in an
+    // inconsistent state, so we can ignore the poisoning.
+    BUFFER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Change the maximum number of records in the buffer, dropping the oldest
+/// records if there are too many.
+pub(super) fn set_capacity(capacity: usize) {
+    let mut buffer = lock_buffer();
+
+    buffer.capacity = capacity;
+
+    let excess = buffer.records.len().saturating_sub(capacity);
+    buffer.records.drain(..excess);
+}
+
+/// Remove all the records from the buffer.
+pub(super) fn clear() {
+    lock_buffer().records.clear();
+}
+
+/// Format all the records in the buffer, oldest first, one per line.
+pub(super) fn dump() -> String {
+    let buffer = lock_buffer();
+    let mut output = String::new();
+
+    for record in &buffer.records {
+        let timestamp: String =
+            Date::new(&JsValue::from_f64(record.timestamp)).to_iso_string().into();
+
+        // Writing to a `String` cannot fail.
+        let _ = write!(output, "{timestamp} {} {}: ", record.level, record.target);
+
+        for (name, fields) in &record.spans {
+            let _ = write!(output, "{name}");
+
+            if !fields.is_empty() {
+                let _ = write!(output, "{{");
+                write_fields(&mut output, fields);
+                let _ = write!(output, "}}");
+            }
+
+            let _ = write!(output, ": ");
+        }
+
+        let _ = write!(output, "{}", record.message);
+
+        if !record.fields.is_empty() {
+            let _ = write!(output, " ");
+            write_fields(&mut output, &record.fields);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+fn write_fields(output: &mut String, fields: &[(String, String)]) {
+    for (i, (name, value)) in fields.iter().enumerate() {
+        let separator = if i == 0 { "" } else { " " };
+        let _ = write!(output, "{separator}{name}={value}");
+    }
+}
+
+/// Replace the value of any sensitive field with a placeholder, and scrub
+/// the values of the other fields.
+fn redact(fields: &[(String, String)]) -> Vec<(String, String)> {
+    fields
+        .iter()
+        .map(|(name, value)| {
+            let lowercase_name = name.to_lowercase();
+
+            if SENSITIVE_FIELD_NAMES.iter().any(|sensitive| lowercase_name.contains(sensitive)) {
+                (name.clone(), REDACTED.to_owned())
+            } else {
+                (name.clone(), scrub(value))
+            }
+        })
+        .collect()
+}
+
+/// Redact the sensitive parts of a formatted message, or of the `Debug`
+/// output of a field: the values which follow a sensitive name, as in
+/// `session_key: "…"`, `"pickle":{…}` or `SessionKey(…)`, and any long run
+/// of base64 characters.
+fn scrub(text: &str) -> String {
+    redact_blobs(&redact_named_values(text))
+}
+
+fn redact_named_values(text: &str) -> String {
+    // ASCII lowercasing keeps the byte offsets, so they can be used in `text`.
+    let lowercase = text.to_ascii_lowercase();
+    let bytes = text.as_bytes();
+
+    let mut output = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut position = 0;
+
+    while let Some(name_end) = find_sensitive_name(&lowercase, position) {
+        position = name_end;
+
+        if let Some(value) = value_after(bytes, name_end) {
+            output.push_str(&text[copied..value.start]);
+            output.push_str(REDACTED);
+            copied = value.end;
+            position = value.end;
+        }
+    }
+
+    output.push_str(&text[copied..]);
+    output
+}
+
+/// The end of the first identifier from `from` which contains a sensitive
+/// name, in either `snake_case` or `CamelCase`.
+fn find_sensitive_name(lowercase: &str, from: usize) -> Option<usize> {
+    let rest = &lowercase[from..];
+
+    let start = SENSITIVE_FIELD_NAMES
+        .iter()
+        .flat_map(|name| [rest.find(name), rest.find(&name.replace('_', ""))])
+        .flatten()
+        .min()?;
+
+    let identifier_length =
+        rest[start..].bytes().take_while(|b| b.is_ascii_alphanumeric() || *b == b'_').count();
+
+    Some(from + start + identifier_length)
+}
+
+/// The range of the value which follows the name ending at `name_end`, if
+/// the name is followed by `:`, `=` or `(`, as in a struct field, a JSON
+/// object or a tuple struct.
+fn value_after(bytes: &[u8], name_end: usize) -> Option<std::ops::Range<usize>> {
+    let skip_whitespace = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        i
+    };
+
+    let mut i = name_end;
+
+    // The closing quote of a JSON key.
+    while matches!(bytes.get(i), Some(b'"' | b'\'')) {
+        i += 1;
+    }
+
+    i = skip_whitespace(i);
+
+    match bytes.get(i) {
+        Some(b':' | b'=') => i = skip_whitespace(i + 1),
+        Some(b'(') => {}
+        _ => return None,
+    }
+
+    let end = value_end(bytes, i);
+    (end > i).then_some(i..end)
+}
+
+/// The end of the value starting at `start`: a quoted string, a bracketed
+/// group, or a word, possibly followed by a bracketed group as in
+/// `SessionKey(…)`.
+fn value_end(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, &byte) in bytes.iter().enumerate().skip(start) {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => {
+                if depth == 0 {
+                    return i;
+                }
+
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            b',' | b';' if depth == 0 => return i,
+            _ if depth == 0 && byte.is_ascii_whitespace() => return i,
+            _ => {}
+        }
+    }
+
+    bytes.len()
+}
+
+/// Replace any run of at least [`MIN_REDACTED_BLOB_LENGTH`] base64
+/// characters with a placeholder.
+fn redact_blobs(text: &str) -> String {
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_');
+
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(is_base64) {
+        let length = rest[start..].find(|c| !is_base64(c)).unwrap_or(rest.len() - start);
+
+        output.push_str(&rest[..start]);
+        if length >= MIN_REDACTED_BLOB_LENGTH {
+            output.push_str(REDACTED);
+        } else {
+            output.push_str(&rest[start..start + length]);
+        }
+
+        rest = &rest[start + length..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// A [`Layer`] which stores each event in the buffer of recent records.
+#[derive(Debug, Default)]
+pub(super) struct RingBufferLayer;
+
+impl<S> Layer<S> for RingBufferLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        let mut collector = FieldCollector::default();
+        event.record(&mut collector);
+
+        let spans = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let extensions = span.extensions();
+                let fields =
+                    extensions.get::<SpanFields>().map(|f| f.0.as_slice()).unwrap_or_default();
+
+                (span.name(), redact(fields))
+            })
+            .collect();
+
+        let record = BufferedRecord {
+            timestamp: Date::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            spans,
+            message: scrub(&collector.message),
+            fields: redact(&collector.fields),
+        };
+
+        let mut buffer = lock_buffer();
+
+        if buffer.capacity == 0 {
+            return;
+        }
+
+        if buffer.records.len() >= buffer.capacity {
+            buffer.records.pop_front();
+        }
+
+        buffer.records.push_back(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+    wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_node_experimental);
+
+    use super::scrub;
+
+    #[wasm_bindgen_test]
+    fn test_scrub_named_values() {
+        assert_eq!(
+            scrub(r#"Received {"session_id":"abc","session_key":"AgAAAA"} from x"#),
+            r#"Received {"session_id":"abc","session_key":<redacted>} from x"#
+        );
+        assert_eq!(
+            scrub("Content { room_id: \"!a:b\", session_key: SessionKey(\"AgAAAA\"), index: 0 }"),
+            "Content { room_id: \"!a:b\", session_key: <redacted>, index: 0 }"
+        );
+        assert_eq!(scrub("the passphrase=hunter2 was used"), "the passphrase=<redacted> was used");
+        assert_eq!(
+            scrub("Failed to unpickle the account: bad pickle"),
+            "Failed to unpickle the account: bad pickle"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_scrub_blobs() {
+        let pickle = "A".repeat(200);
+        let device_id = "BOBDEVICE";
+
+        assert_eq!(
+            scrub(&format!("Exported {pickle} for {device_id}")),
+            format!("Exported <redacted> for {device_id}")
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_scrub_keys() {
+        let key = "iWcrVcG5QQRbgCvDbG0FV/IPjxKdOGNjlZnwSq1LqGE";
+        assert_eq!(key.len(), 43);
+
+        assert_eq!(scrub(&format!("Using the key {key}.")), "Using the key <redacted>.");
+        assert_eq!(scrub(&format!("{{\"key\":\"{key}\"}}")), "{\"key\":\"<redacted>\"}");
+    }
+}
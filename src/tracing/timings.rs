@@ -0,0 +1,207 @@
+//! A `tracing` layer which measures how long spans take, to help find out
+//! where time is spent in the crypto code.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use js_sys::{Map, Object, Reflect};
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+
+    #[wasm_bindgen(js_namespace = performance, js_name = measure, catch)]
+    fn performance_measure(name: &str, options: &Object) -> Result<JsValue, JsValue>;
+}
+
+/// The upper bounds, in milliseconds, of the buckets of the histograms in
+/// {@link SpanTimings}. The last bucket counts any longer span.
+const BUCKET_BOUNDARIES_MS: [f64; 10] = [1., 2., 5., 10., 20., 50., 100., 200., 500., 1000.];
+
+/// Whether to emit a `performance.measure` entry for each span.
+static EMIT_PERFORMANCE_MEASURES: AtomicBool = AtomicBool::new(false);
+
+/// The timings collected so far, keyed by `target::name` of the span.
+static TIMINGS: Mutex<BTreeMap<String, SpanTimings>> = Mutex::new(BTreeMap::new());
+
+fn lock_timings() -> MutexGuard<'static, BTreeMap<String, SpanTimings>> {
+    // A panic while the lock was held cannot have left the timings in an
+    // inconsistent state, so we can ignore the poisoning.
+    TIMINGS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(super) fn set_emit_performance_measures(emit: bool) {
+    EMIT_PERFORMANCE_MEASURES.store(emit, Ordering::Relaxed);
+}
+
+/// Get the timings collected so far, as a map from the `target::name` of the
+/// spans to their {@link SpanTimings}.
+pub(super) fn timings() -> Map {
+    let map = Map::new();
+
+    for (name, timings) in lock_timings().iter() {
+        map.set(&name.into(), &timings.clone().into());
+    }
+
+    map
+}
+
+/// Forget the timings collected so far.
+pub(super) fn reset() {
+    lock_timings().clear();
+}
+
+/// The timings of all the closed spans with the same target and name.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct SpanTimings {
+    /// The number of spans which were measured.
+    #[wasm_bindgen(readonly)]
+    pub count: u32,
+
+    /// The total time, in milliseconds, between the creation and the closing
+    /// of the spans.
+    #[wasm_bindgen(readonly, js_name = "totalMs")]
+    pub total_ms: f64,
+
+    /// The shortest time, in milliseconds, between the creation and the
+    /// closing of a span.
+    #[wasm_bindgen(readonly, js_name = "minMs")]
+    pub min_ms: f64,
+
+    /// The longest time, in milliseconds, between the creation and the
+    /// closing of a span.
+    #[wasm_bindgen(readonly, js_name = "maxMs")]
+    pub max_ms: f64,
+
+    /// The total time, in milliseconds, during which the spans were entered,
+    /// i.e. when code was actually running inside them, as opposed to waiting
+    /// for something else, such as the store.
+    #[wasm_bindgen(readonly, js_name = "busyMs")]
+    pub busy_ms: f64,
+
+    /// A histogram of the time between the creation and the closing of the
+    /// spans. Each entry is the number of spans which took up to the
+    /// corresponding entry of {@link bucketBoundariesMs}, the last entry
+    /// counting any longer span.
+    #[wasm_bindgen(readonly)]
+    pub histogram: Vec<u32>,
+}
+
+#[wasm_bindgen]
+impl SpanTimings {
+    /// The upper bounds, in milliseconds, of the entries of {@link histogram}.
+    #[wasm_bindgen(js_name = "bucketBoundariesMs")]
+    pub fn bucket_boundaries_ms() -> Vec<f64> {
+        BUCKET_BOUNDARIES_MS.to_vec()
+    }
+}
+
+impl SpanTimings {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            total_ms: 0.,
+            min_ms: f64::INFINITY,
+            max_ms: 0.,
+            busy_ms: 0.,
+            histogram: vec![0; BUCKET_BOUNDARIES_MS.len() + 1],
+        }
+    }
+
+    fn record(&mut self, duration_ms: f64, busy_ms: f64) {
+        self.count += 1;
+        self.total_ms += duration_ms;
+        self.min_ms = self.min_ms.min(duration_ms);
+        self.max_ms = self.max_ms.max(duration_ms);
+        self.busy_ms += busy_ms;
+
+        let bucket = BUCKET_BOUNDARIES_MS
+            .iter()
+            .position(|boundary| duration_ms <= *boundary)
+            .unwrap_or(BUCKET_BOUNDARIES_MS.len());
+        self.histogram[bucket] += 1;
+    }
+}
+
+/// The timing of a single span, stored in the span's extensions.
+#[derive(Debug)]
+struct SpanTiming {
+    created_at: f64,
+    entered_at: Option<f64>,
+    busy_ms: f64,
+}
+
+/// A [`Layer`] which measures the time each span takes.
+#[derive(Debug, Default)]
+pub(super) struct TimingLayer;
+
+impl<S> Layer<S> for TimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        span.extensions_mut().insert(SpanTiming {
+            created_at: performance_now(),
+            entered_at: None,
+            busy_ms: 0.,
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut extensions = span.extensions_mut();
+
+        if let Some(timing) = extensions.get_mut::<SpanTiming>() {
+            timing.entered_at = Some(performance_now());
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut extensions = span.extensions_mut();
+
+        if let Some(timing) = extensions.get_mut::<SpanTiming>() {
+            if let Some(entered_at) = timing.entered_at.take() {
+                timing.busy_ms += performance_now() - entered_at;
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(timing) = span.extensions_mut().remove::<SpanTiming>() else { return };
+
+        let closed_at = performance_now();
+        let name = format!("{}::{}", span.metadata().target(), span.name());
+
+        if EMIT_PERFORMANCE_MEASURES.load(Ordering::Relaxed) {
+            let options = Object::new();
+            // Setting a property on a fresh object cannot fail.
+            let _ = Reflect::set(&options, &"start".into(), &timing.created_at.into());
+            let _ = Reflect::set(&options, &"end".into(), &closed_at.into());
+
+            // `performance.measure` may not be available, in which case there is
+            // nothing we can do about it.
+            let _ = performance_measure(&name, &options);
+        }
+
+        lock_timings()
+            .entry(name)
+            .or_insert_with(SpanTimings::new)
+            .record(closed_at - timing.created_at, timing.busy_ms);
+    }
+}
@@ -0,0 +1,260 @@
+//! Sending the outgoing requests of an `OlmMachine` to the homeserver.
+
+use futures_util::{stream, StreamExt, TryStreamExt};
+use js_sys::{Array, Function, Object, Promise, Reflect};
+use matrix_sdk_common::ruma::TransactionId;
+use matrix_sdk_crypto::types::requests::OutgoingRequest;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::{
+    machine::OlmMachine,
+    requests::{self, HttpRequest},
+    responses::{self, response_from_bytes, RequestFailure},
+};
+
+/// Sends the outgoing requests of an {@link OlmMachine} to the homeserver with
+/// `fetch`, and passes the responses back to the machine.
+///
+/// This implements the loop that applications otherwise write themselves:
+/// get the requests with {@link OlmMachine.outgoingRequests}, send them, and
+/// report the outcome with {@link OlmMachine.markRequestAsSent} or {@link
+/// OlmMachine.markRequestAsFailed}.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct HomeserverTransport {
+    /// How many requests may be in flight at once. Defaults to 4.
+    #[wasm_bindgen(js_name = "maxConcurrentRequests")]
+    pub max_concurrent_requests: u32,
+
+    /// How many times a request is sent again after a failure which may not
+    /// happen again, such as a network error or rate-limiting, before giving
+    /// up on it. Defaults to 3.
+    ///
+    /// Each retry waits for the delay given by {@link
+    /// RequestFailure.retryAfterMs}.
+    #[wasm_bindgen(js_name = "maxRetries")]
+    pub max_retries: u32,
+
+    homeserver_url: String,
+    access_token_provider: Function,
+    fetch: Function,
+}
+
+#[wasm_bindgen]
+impl HomeserverTransport {
+    /// Create a new `HomeserverTransport`.
+    ///
+    /// # Arguments
+    ///
+    /// * `homeserver_url` - the base URL of the homeserver, such as `https://matrix.example.org`.
+    /// * `access_token_provider` - a function which takes no argument, and
+    ///   returns the access token to authenticate the requests with, or a
+    ///   promise for it. It is called before each request, so that the token
+    ///   can be refreshed.
+    /// * `fetch` - the `fetch` implementation to send the requests with.
+    ///   Defaults to the global `fetch`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        homeserver_url: String,
+        access_token_provider: Function,
+        fetch: Option<Function>,
+    ) -> Result<HomeserverTransport, JsError> {
+        let fetch = match fetch {
+            Some(fetch) => fetch,
+            None => Reflect::get(&js_sys::global(), &"fetch".into())
+                .ok()
+                .and_then(|fetch| fetch.dyn_into().ok())
+                .ok_or_else(|| JsError::new("No global `fetch` function is available"))?,
+        };
+
+        Ok(Self {
+            max_concurrent_requests: 4,
+            max_retries: 3,
+            homeserver_url,
+            access_token_provider,
+            fetch,
+        })
+    }
+
+    /// Send all the outgoing requests of the given machine, as returned by
+    /// {@link OlmMachine.outgoingRequests}, and pass their responses back to
+    /// it.
+    ///
+    /// Requests which fail are reported to the machine with {@link
+    /// OlmMachine.markRequestAsFailed}, which calls the callback registered
+    /// with {@link OlmMachine.registerRequestFailedCallback}, if any.
+    ///
+    /// This sends the requests which are outgoing when it is called: it
+    /// should be called again after each sync, as the machine generates new
+    /// requests when processing it.
+    ///
+    /// # Returns
+    ///
+    /// A {@link TransportReport}. The promise is rejected if the machine
+    /// could not process a response, or if the access token provider threw.
+    #[wasm_bindgen(js_name = "sendOutgoingRequests")]
+    pub async fn send_outgoing_requests(
+        &self,
+        machine: &OlmMachine,
+    ) -> Result<TransportReport, JsError> {
+        let outgoing_requests = machine
+            .pending_outgoing_requests()
+            .await
+            .map_err(|error| JsError::new(&error.to_string()))?;
+
+        let outcomes = stream::iter(outgoing_requests)
+            .map(|request| self.send_with_retries(machine, request))
+            .buffer_unordered(self.max_concurrent_requests.max(1) as usize)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut sent = 0;
+        let failures = Array::new();
+        for outcome in outcomes {
+            match outcome {
+                Ok(()) => sent += 1,
+                Err(failure) => {
+                    failures.push(&failure.into());
+                }
+            }
+        }
+
+        Ok(TransportReport { sent, failures })
+    }
+}
+
+impl HomeserverTransport {
+    /// Send a request until it succeeds, it fails for good, or we run out of
+    /// retries.
+    ///
+    /// The outer `Result` is the error of the machine or of the access token
+    /// provider, and the inner one the failure of the request itself.
+    async fn send_with_retries(
+        &self,
+        machine: &OlmMachine,
+        request: OutgoingRequest,
+    ) -> Result<Result<(), RequestFailure>, JsError> {
+        let request_id = request.request_id().to_owned();
+        let request_type = requests::outgoing_request_type(request.request());
+
+        let mut retries = 0;
+
+        loop {
+            let access_token = self.access_token().await?;
+            let http_request = requests::outgoing_request_to_http_request(
+                &request,
+                &self.homeserver_url,
+                access_token,
+            )?;
+
+            let (status, body) = match self.fetch(&http_request).await {
+                Ok((status, body)) if (200..300).contains(&status) => {
+                    return self.receive_response(machine, &request_id, request_type, body).await;
+                }
+                Ok((status, body)) => (status, Some(body)),
+                Err(_) => (0, None),
+            };
+
+            let failure = machine
+                .mark_request_as_failed(request_id.to_string(), request_type, status, body)
+                .await?;
+
+            if !failure.retryable || retries >= self.max_retries {
+                return Ok(Err(failure));
+            }
+
+            retries += 1;
+            sleep(failure.retry_after_ms).await;
+        }
+    }
+
+    /// Pass the successful response to a request back to the machine.
+    async fn receive_response(
+        &self,
+        machine: &OlmMachine,
+        request_id: &TransactionId,
+        request_type: requests::RequestType,
+        body: String,
+    ) -> Result<Result<(), RequestFailure>, JsError> {
+        let response = response_from_bytes(body.into_bytes())?;
+        let response = responses::OwnedResponse::try_from((request_type, response))?;
+
+        machine
+            .receive_response(request_id, request_type, &response)
+            .await
+            .map_err(|error| JsError::new(&error.to_string()))?;
+
+        Ok(Ok(()))
+    }
+
+    /// Call the access token provider.
+    async fn access_token(&self) -> Result<Option<String>, JsError> {
+        let mut token = self
+            .access_token_provider
+            .call0(&JsValue::NULL)
+            .map_err(|_| JsError::new("The access token provider threw an exception"))?;
+
+        if let Some(promise) = token.dyn_ref::<Promise>() {
+            token = JsFuture::from(promise.clone())
+                .await
+                .map_err(|_| JsError::new("The access token provider rejected its promise"))?;
+        }
+
+        Ok(token.as_string())
+    }
+
+    /// Send an HTTP request, returning the status and body of the response,
+    /// or the error thrown if no response was received.
+    async fn fetch(&self, request: &HttpRequest) -> Result<(u16, String), JsValue> {
+        let headers: JsValue = Object::from_entries(&request.headers)?.into();
+        let init = Object::new();
+        Reflect::set(&init, &"method".into(), &request.method)?;
+        Reflect::set(&init, &"headers".into(), &headers)?;
+        Reflect::set(&init, &"body".into(), &request.body)?;
+
+        let promise: Promise = self.fetch.call2(&JsValue::NULL, &request.url, &init)?.dyn_into()?;
+        let response = JsFuture::from(promise).await?;
+
+        let status = Reflect::get(&response, &"status".into())?.as_f64().unwrap_or_default() as u16;
+        let text: Function = Reflect::get(&response, &"text".into())?.dyn_into()?;
+        let body = JsFuture::from(text.call0(&response)?.dyn_into::<Promise>()?).await?;
+
+        Ok((status, body.as_string().unwrap_or_default()))
+    }
+}
+
+/// What {@link HomeserverTransport.sendOutgoingRequests} did.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug)]
+pub struct TransportReport {
+    /// The number of requests which were sent, and whose responses were passed
+    /// back to the machine.
+    #[wasm_bindgen(readonly)]
+    pub sent: u32,
+
+    /// The requests which failed, after any retries.
+    ///
+    /// Typescript type: `RequestFailure[]`.
+    #[wasm_bindgen(readonly)]
+    pub failures: Array,
+}
+
+/// Wait for the given number of milliseconds.
+async fn sleep(milliseconds: f64) {
+    let promise = Promise::new(&mut |resolve, _| {
+        let set_timeout: Result<Function, _> =
+            Reflect::get(&js_sys::global(), &"setTimeout".into()).and_then(|f| f.dyn_into());
+
+        match set_timeout {
+            Ok(set_timeout) => {
+                let _ = set_timeout.call2(&JsValue::NULL, &resolve, &milliseconds.into());
+            }
+            Err(_) => {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        }
+    });
+
+    let _ = JsFuture::from(promise).await;
+}
@@ -6,15 +6,22 @@ use std::{
 };
 
 use js_sys::{Array, JsString, Map, Set};
-use matrix_sdk_common::ruma::OwnedRoomId;
-use matrix_sdk_crypto::backups::{
-    SignatureState as InnerSignatureState, SignatureVerification as InnerSignatureVerification,
+use matrix_sdk_common::ruma::{
+    CanonicalJsonValue, DeviceId, DeviceKeyAlgorithm, DeviceKeyId, OwnedDeviceKeyId, OwnedRoomId,
+    UserId as RumaUserId,
 };
+use matrix_sdk_crypto::{
+    backups::{
+        SignatureState as InnerSignatureState, SignatureVerification as InnerSignatureVerification,
+    },
+    vodozemac, Device, UserIdentity,
+};
+use serde::Deserialize;
 use wasm_bindgen::prelude::*;
 
 use crate::{
     encryption::EncryptionAlgorithm,
-    identifiers::{DeviceKeyId, UserId},
+    identifiers::{self, UserId},
     impl_from_to_inner,
     vodozemac::Ed25519Signature,
 };
@@ -42,7 +49,7 @@ impl Signatures {
     pub fn add_signature(
         &mut self,
         signer: &UserId,
-        key_id: &DeviceKeyId,
+        key_id: &identifiers::DeviceKeyId,
         signature: &Ed25519Signature,
     ) -> Option<MaybeSignature> {
         self.inner
@@ -53,7 +60,11 @@ impl Signatures {
     /// Try to find an Ed25519 signature from the given signer with
     /// the given key ID.
     #[wasm_bindgen(js_name = "getSignature")]
-    pub fn get_signature(&self, signer: &UserId, key_id: &DeviceKeyId) -> Option<Ed25519Signature> {
+    pub fn get_signature(
+        &self,
+        signer: &UserId,
+        key_id: &identifiers::DeviceKeyId,
+    ) -> Option<Ed25519Signature> {
         self.inner.get_signature(signer.inner.as_ref(), key_id.inner.as_ref()).map(Into::into)
     }
 
@@ -171,11 +182,202 @@ impl MaybeSignature {
     }
 }
 
+/// Turn a JSON object into the canonical form that is used to create and check
+/// signatures: the `signatures` and `unsigned` fields are removed, and the
+/// remainder is serialized as canonical JSON.
+pub(crate) fn to_signable_json(mut value: serde_json::Value) -> Result<String, JsError> {
+    let object =
+        value.as_object_mut().ok_or_else(|| JsError::new("the signed JSON is not an object"))?;
+    object.remove("signatures");
+    object.remove("unsigned");
+
+    let canonical_json: CanonicalJsonValue = value.try_into()?;
+
+    Ok(canonical_json.to_string())
+}
+
+/// Options for {@link OlmMachine.signJson}.
+///
+/// This is deserialized from a plain JavaScript object.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SignJsonOptions {
+    /// Whether to sign with the Ed25519 key of our own device. Defaults to
+    /// `true`.
+    #[serde(default = "default_true")]
+    pub with_device_key: bool,
+
+    /// Whether to sign with the private key of our cross-signing master key.
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub with_master_key: bool,
+}
+
+impl Default for SignJsonOptions {
+    fn default() -> Self {
+        Self { with_device_key: true, with_master_key: false }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Merge the given signatures into the `signatures` property of a JSON
+/// object, keeping any signatures that were already there.
+pub(crate) fn merge_signatures(
+    value: &mut serde_json::Value,
+    signatures: &matrix_sdk_crypto::types::Signatures,
+) -> Result<(), JsError> {
+    let object =
+        value.as_object_mut().ok_or_else(|| JsError::new("the signed JSON is not an object"))?;
+    let existing = object
+        .entry("signatures")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()))
+        .as_object_mut()
+        .ok_or_else(|| JsError::new("the `signatures` property is not an object"))?;
+
+    let serde_json::Value::Object(new) = serde_json::to_value(signatures)? else {
+        return Err(JsError::new("the signatures could not be serialized as an object"));
+    };
+
+    for (user_id, user_signatures) in new {
+        let serde_json::Value::Object(user_signatures) = user_signatures else { continue };
+
+        match existing
+            .entry(user_id)
+            .or_insert_with(|| serde_json::Value::Object(Default::default()))
+        {
+            serde_json::Value::Object(entry) => entry.extend(user_signatures),
+            _ => return Err(JsError::new("the `signatures` property is malformed")),
+        }
+    }
+
+    Ok(())
+}
+
 /// The result of a signature verification of a signed JSON object.
 #[derive(Debug)]
 #[wasm_bindgen]
 pub struct SignatureVerification {
     pub(crate) inner: InnerSignatureVerification,
+
+    /// The state of the signature made by each of the keys that were checked,
+    /// keyed by key ID.
+    key_states: BTreeMap<OwnedDeviceKeyId, InnerSignatureState>,
+}
+
+impl From<InnerSignatureVerification> for SignatureVerification {
+    fn from(inner: InnerSignatureVerification) -> Self {
+        Self { inner, key_states: BTreeMap::new() }
+    }
+}
+
+impl SignatureVerification {
+    /// Check the signatures made by `signer` on a canonicalized JSON object,
+    /// against the keys of the signer's devices and cross-signing identity.
+    ///
+    /// The signature of our own device ends up in `device_signature`, the
+    /// signature of the signer's master key in `user_identity_signature`, and
+    /// the signatures of any other devices in `other_signatures`.
+    pub(crate) fn check_json(
+        own_user_id: &RumaUserId,
+        own_device_id: &DeviceId,
+        signer: &RumaUserId,
+        signatures: &matrix_sdk_crypto::types::Signatures,
+        canonical_json: &str,
+        devices: impl Iterator<Item = Device>,
+        identity: Option<UserIdentity>,
+    ) -> Self {
+        let mut result = Self::from(InnerSignatureVerification {
+            device_signature: InnerSignatureState::Missing,
+            user_identity_signature: InnerSignatureState::Missing,
+            other_signatures: BTreeMap::new(),
+        });
+
+        for device in devices {
+            let Some(key) = device.ed25519_key() else { continue };
+            let key_id = DeviceKeyId::from_parts(DeviceKeyAlgorithm::Ed25519, device.device_id());
+            let state = result.check_key(
+                signer,
+                signatures,
+                canonical_json,
+                key_id,
+                key,
+                device.is_verified(),
+            );
+
+            if device.user_id() == own_user_id && device.device_id() == own_device_id {
+                result.inner.device_signature = state;
+            } else {
+                result.inner.other_signatures.insert(device.device_id().to_owned(), state);
+            }
+        }
+
+        if let Some(identity) = identity {
+            let (master_key, self_signing_key) = match &identity {
+                UserIdentity::Own(i) => (i.master_key().as_ref(), i.self_signing_key().as_ref()),
+                UserIdentity::Other(i) => (i.master_key().as_ref(), i.self_signing_key().as_ref()),
+            };
+            let trusted = identity.is_verified();
+
+            if let Some((key_id, key)) = master_key.get_first_key_and_id() {
+                result.inner.user_identity_signature = result.check_key(
+                    signer,
+                    signatures,
+                    canonical_json,
+                    key_id.to_owned(),
+                    key,
+                    trusted,
+                );
+            }
+
+            if let Some((key_id, key)) = self_signing_key.get_first_key_and_id() {
+                result.check_key(
+                    signer,
+                    signatures,
+                    canonical_json,
+                    key_id.to_owned(),
+                    key,
+                    trusted,
+                );
+            }
+        }
+
+        result
+    }
+
+    /// Check the signature made by a single key, and record the result in
+    /// `key_states`.
+    fn check_key(
+        &mut self,
+        signer: &RumaUserId,
+        signatures: &matrix_sdk_crypto::types::Signatures,
+        canonical_json: &str,
+        key_id: OwnedDeviceKeyId,
+        key: vodozemac::Ed25519PublicKey,
+        trusted: bool,
+    ) -> InnerSignatureState {
+        let state = match signatures.get(signer).and_then(|s| s.get(&key_id)) {
+            None => InnerSignatureState::Missing,
+            Some(Ok(signature))
+                if signature
+                    .ed25519()
+                    .is_some_and(|s| key.verify(canonical_json.as_bytes(), &s).is_ok()) =>
+            {
+                if trusted {
+                    InnerSignatureState::ValidAndTrusted
+                } else {
+                    InnerSignatureState::ValidButNotTrusted
+                }
+            }
+            Some(_) => InnerSignatureState::Invalid,
+        };
+
+        self.key_states.insert(key_id, state);
+
+        state
+    }
 }
 
 /// The result of a signature check.
@@ -233,6 +435,38 @@ impl SignatureVerification {
     pub fn trusted(&self) -> bool {
         self.inner.trusted()
     }
+
+    /// The signature states of the other devices that were checked, keyed by
+    /// device ID.
+    ///
+    /// Typescript type: `Map<string, SignatureState>`.
+    #[wasm_bindgen(getter, js_name = "otherDeviceStates")]
+    pub fn other_device_states(&self) -> Map {
+        let map = Map::new();
+
+        for (device_id, state) in &self.inner.other_signatures {
+            map.set(&JsString::from(device_id.as_str()), &SignatureState::from(*state).into());
+        }
+
+        map
+    }
+
+    /// The signature state of each key that was checked, keyed by key ID
+    /// (for example `ed25519:DEVICEID`).
+    ///
+    /// This is only populated by {@link OlmMachine.verifyJsonSignatures}.
+    ///
+    /// Typescript type: `Map<string, SignatureState>`.
+    #[wasm_bindgen(getter, js_name = "keyStates")]
+    pub fn key_states(&self) -> Map {
+        let map = Map::new();
+
+        for (key_id, state) in &self.key_states {
+            map.set(&JsString::from(key_id.as_str()), &SignatureState::from(*state).into());
+        }
+
+        map
+    }
 }
 
 /// The result of a call to {@link OlmMachine.importExportedRoomKeys} or
@@ -0,0 +1,145 @@
+//! Establishing new Olm sessions with devices whose sessions are broken.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+};
+
+use anyhow::anyhow;
+use matrix_sdk_common::ruma::{
+    events::dummy::ToDeviceDummyEventContent, OwnedDeviceId, OwnedTransactionId, OwnedUserId,
+    TransactionId,
+};
+use matrix_sdk_crypto::types::requests::{
+    OutgoingRequest, OutgoingVerificationRequest, ToDeviceRequest,
+};
+
+/// The state of the unwedging of Olm sessions requested with
+/// `OlmMachine.forceNewOlmSession`, shared between the clones of an
+/// `OlmMachine`.
+///
+/// Unwedging a device takes two steps: claiming a one-time key of the device,
+/// from which the underlying machine creates a new Olm session, then sending
+/// an `m.dummy` event over that session, so that the device learns about it.
+///
+/// This state is only kept in memory: the claims awaiting a response and the
+/// `m.dummy` events not sent yet are lost when the machine is dropped or
+/// reloaded from the store, and the unwedging must then be started again.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Unwedging {
+    /// The devices being unwedged, by the ID of the `/keys/claim` request
+    /// claiming one of their one-time keys.
+    claims: Rc<RefCell<BTreeMap<OwnedTransactionId, (OwnedUserId, OwnedDeviceId)>>>,
+
+    /// The `m.dummy` to-device requests which have not been sent yet.
+    dummies: Rc<RefCell<Vec<OutgoingRequest>>>,
+}
+
+/// A device being unwedged, whose `/keys/claim` response is about to be
+/// passed to the underlying machine.
+#[derive(Debug)]
+pub(crate) struct PendingClaim {
+    user_id: OwnedUserId,
+    device_id: OwnedDeviceId,
+
+    /// The IDs of the Olm sessions with the device before the response.
+    session_ids: BTreeSet<String>,
+}
+
+impl Unwedging {
+    /// Record that the `/keys/claim` request with the given ID claims a
+    /// one-time key to unwedge the given device.
+    pub(crate) fn add_claim(
+        &self,
+        request_id: OwnedTransactionId,
+        user_id: OwnedUserId,
+        device_id: OwnedDeviceId,
+    ) {
+        self.claims.borrow_mut().insert(request_id, (user_id, device_id));
+    }
+
+    /// Take the device which the `/keys/claim` request with the given ID
+    /// unwedges, if any, along with its current Olm sessions.
+    ///
+    /// This must be called before the response is passed to the underlying
+    /// machine, so that [`Self::receive_claim_response`] can tell the session
+    /// it creates apart from the wedged ones.
+    pub(crate) async fn take_claim(
+        &self,
+        machine: &matrix_sdk_crypto::OlmMachine,
+        request_id: &TransactionId,
+    ) -> Result<Option<PendingClaim>, anyhow::Error> {
+        let Some((user_id, device_id)) = self.claims.borrow_mut().remove(request_id) else {
+            return Ok(None);
+        };
+        let session_ids = session_ids(machine, &user_id, &device_id).await?;
+
+        Ok(Some(PendingClaim { user_id, device_id, session_ids }))
+    }
+
+    /// Handle the response to a `/keys/claim` request taken with
+    /// [`Self::take_claim`], once the underlying machine has created the new
+    /// Olm sessions: queue an `m.dummy` event to the device over its new
+    /// session.
+    ///
+    /// Fails if no new session was created, for example because the response
+    /// held no valid one-time key for the device, rather than sending the
+    /// event over a wedged session.
+    pub(crate) async fn receive_claim_response(
+        &self,
+        machine: &matrix_sdk_crypto::OlmMachine,
+        claim: PendingClaim,
+    ) -> Result<(), anyhow::Error> {
+        let PendingClaim { user_id, device_id, session_ids: old_session_ids } = claim;
+
+        if session_ids(machine, &user_id, &device_id).await?.is_subset(&old_session_ids) {
+            return Err(anyhow!(
+                "No new Olm session was established with the device {device_id} of {user_id}: \
+                 the response did not hold a valid one-time key for it"
+            ));
+        }
+        let Some(device) = machine.get_device(&user_id, &device_id, None).await? else {
+            return Ok(());
+        };
+
+        // The new session was created last, so it is the most recently used
+        // one, which encrypts the event.
+        let content = device
+            .encrypt_event_raw("m.dummy", &serde_json::to_value(ToDeviceDummyEventContent::new())?)
+            .await?;
+        let request = ToDeviceRequest::new(&user_id, device_id, "m.room.encrypted", content.cast());
+
+        self.dummies.borrow_mut().push(OutgoingVerificationRequest::ToDevice(request).into());
+
+        Ok(())
+    }
+
+    /// The `m.dummy` to-device requests which have not been sent yet.
+    pub(crate) fn outgoing_requests(&self) -> Vec<OutgoingRequest> {
+        self.dummies.borrow().clone()
+    }
+
+    /// Forget about the to-device request with the given ID, which was sent.
+    pub(crate) fn mark_request_as_sent(&self, request_id: &TransactionId) {
+        self.dummies.borrow_mut().retain(|request| request.request_id() != request_id);
+    }
+}
+
+/// The IDs of the Olm sessions with the given device.
+async fn session_ids(
+    machine: &matrix_sdk_crypto::OlmMachine,
+    user_id: &OwnedUserId,
+    device_id: &OwnedDeviceId,
+) -> Result<BTreeSet<String>, anyhow::Error> {
+    let Some(curve_key) = machine
+        .get_device(user_id, device_id, None)
+        .await?
+        .and_then(|device| device.curve25519_key())
+    else {
+        return Ok(BTreeSet::new());
+    };
+    let sessions = (**machine.store()).get_sessions(&curve_key.to_base64()).await?;
+
+    Ok(sessions.into_iter().flatten().map(|session| session.session_id.to_string()).collect())
+}
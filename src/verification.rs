@@ -6,7 +6,7 @@ use std::fmt;
 use futures_util::StreamExt;
 #[cfg(feature = "qrcode")]
 use js_sys::Uint8ClampedArray;
-use js_sys::{Array, Function, JsString, Promise};
+use js_sys::{Array, Function, JsString, Object, Promise};
 use matrix_sdk_common::ruma::events::key::verification::VerificationMethod as RumaVerificationMethod;
 use matrix_sdk_crypto::{QrVerificationState, VerificationRequestState};
 use tracing::warn;
@@ -16,7 +16,7 @@ use wasm_bindgen_futures::spawn_local;
 use crate::{
     future::future_to_promise,
     identifiers::{DeviceId, RoomId, UserId},
-    impl_from_to_inner,
+    impl_from_to_inner, json,
     machine::promise_result_to_future,
     requests,
 };
@@ -784,6 +784,26 @@ impl VerificationRequest {
         ))?)
     }
 
+    /// Like {@link request}, but returns the content as a plain object, as
+    /// accepted by {@link OlmMachine.encryptRoomEvent}.
+    #[wasm_bindgen(js_name = "requestObject")]
+    pub fn request_object(
+        own_user_id: &UserId,
+        own_device_id: &DeviceId,
+        other_user_id: &UserId,
+        methods: Option<Vec<VerificationMethod>>,
+    ) -> Result<Object, JsError> {
+        let methods = methods.map(|methods| methods.iter().map(Into::into).collect());
+        let content = matrix_sdk_crypto::VerificationRequest::request(
+            &own_user_id.inner,
+            &own_device_id.inner,
+            &other_user_id.inner,
+            methods,
+        );
+
+        Ok(json::to_string_or_object(&content, true)?.unchecked_into())
+    }
+
     /// Our own user id.
     #[wasm_bindgen(getter, js_name = "ownUserId")]
     pub fn own_user_id(&self) -> UserId {
@@ -4,7 +4,7 @@ use matrix_sdk_crypto::vodozemac::{self, base64_decode, base64_encode};
 use wasm_bindgen::prelude::*;
 use zeroize::{Zeroize, Zeroizing};
 
-use crate::impl_from_to_inner;
+use crate::{error::SignatureVerificationError, impl_from_to_inner};
 
 pub mod ecies;
 pub mod pk_encryption;
@@ -18,6 +18,14 @@ pub struct Ed25519PublicKey {
 
 #[wasm_bindgen]
 impl Ed25519PublicKey {
+    /// Create a new [`Ed25519PublicKey`] from a base64 encoded string.
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &str) -> Result<Ed25519PublicKey, JsError> {
+        let inner = vodozemac::Ed25519PublicKey::from_base64(key)?;
+
+        Ok(Self { inner })
+    }
+
     /// The number of bytes an Ed25519 public key has.
     #[wasm_bindgen(getter)]
     pub fn length(&self) -> usize {
@@ -30,10 +38,106 @@ impl Ed25519PublicKey {
     pub fn to_base64(&self) -> String {
         self.inner.to_base64()
     }
+
+    /// Verify that the given signature is a valid signature of `message`
+    /// made by the secret key belonging to this public key.
+    ///
+    /// Throws a {@link SignatureVerificationError} if the signature is
+    /// invalid.
+    pub fn verify(
+        &self,
+        message: &str,
+        signature: &Ed25519Signature,
+    ) -> Result<(), SignatureVerificationError> {
+        Ok(self.inner.verify(message.as_bytes(), &signature.inner)?)
+    }
 }
 
 impl_from_to_inner!(vodozemac::Ed25519PublicKey => Ed25519PublicKey);
 
+/// An Ed25519 secret key, used to create digital signatures.
+#[wasm_bindgen]
+#[allow(missing_debug_implementations)]
+pub struct Ed25519SecretKey {
+    inner: vodozemac::Ed25519SecretKey,
+}
+
+#[wasm_bindgen]
+impl Ed25519SecretKey {
+    /// Generates a new random Ed25519 secret key.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self { inner: vodozemac::Ed25519SecretKey::new() }
+    }
+
+    /// Creates an `Ed25519SecretKey` from a base64-encoded representation of
+    /// the key.
+    #[wasm_bindgen(js_name = "fromBase64")]
+    pub fn from_base64(string: &str) -> Result<Self, JsError> {
+        let mut key = base64_decode(string)?;
+        let result = Self::from_slice(&key);
+
+        key.zeroize();
+
+        result
+    }
+
+    /// Encodes the secret key into a base64 string.
+    #[wasm_bindgen(js_name = "toBase64")]
+    pub fn to_base64(&self) -> String {
+        self.inner.to_base64()
+    }
+
+    /// Converts the secret key into a raw byte vector.
+    #[wasm_bindgen(js_name = "toUint8Array")]
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = self.inner.to_bytes();
+        let vec = bytes.to_vec();
+
+        bytes.zeroize();
+
+        vec
+    }
+
+    /// Creates an `Ed25519SecretKey` from a raw byte slice.
+    #[wasm_bindgen(js_name = "fromUint8Array")]
+    pub fn from_slice(slice: &[u8]) -> Result<Self, JsError> {
+        let length = slice.len();
+
+        if length == vodozemac::Ed25519SecretKey::LENGTH {
+            let mut key = Zeroizing::new([0u8; 32]);
+            key.copy_from_slice(slice);
+
+            let inner = vodozemac::Ed25519SecretKey::from_slice(&key);
+
+            Ok(Self { inner })
+        } else {
+            Err(JsError::new(&format!(
+                "invalid key size for an Ed25519 key, expected 32 bytes, got {length}"
+            )))
+        }
+    }
+
+    /// Get the public key that belongs to this secret key.
+    #[wasm_bindgen(js_name = "publicKey")]
+    pub fn public_key(&self) -> Ed25519PublicKey {
+        self.inner.public_key().into()
+    }
+
+    /// Sign the given message with this secret key.
+    pub fn sign(&self, message: &str) -> Ed25519Signature {
+        self.inner.sign(message.as_bytes()).into()
+    }
+}
+
+impl Default for Ed25519SecretKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_from_to_inner!(vodozemac::Ed25519SecretKey => Ed25519SecretKey);
+
 /// An Ed25519 digital signature, can be used to verify the
 /// authenticity of a message.
 #[wasm_bindgen]
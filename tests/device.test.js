@@ -25,6 +25,7 @@ const {
     Qr,
     QrCode,
     QrCodeScan,
+    OlmDecryptionErrorCode,
 } = require("@matrix-org/matrix-sdk-crypto-wasm");
 const { zip, addMachineToMachine } = require("./helper");
 const { VerificationRequestPhase, QrState } = require("@matrix-org/matrix-sdk-crypto-wasm");
@@ -226,6 +227,46 @@ describe("Send to-device message", () => {
         expect(toDevice.ciphertext).toBeDefined();
         expect(toDevice.ciphertext["boYjDpaC+7NkECQEeMh5dC+I1+AfriX0VXG2UV7EUQo"]).toBeDefined();
     });
+
+    it("can tell which to-device messages were decrypted", async () => {
+        const userId2 = new UserId("@bob:example.org");
+        const deviceId2 = new DeviceId("bob_device");
+        const m1 = await machine(userId1, deviceId1);
+        const m2 = await machine(userId2, deviceId2);
+
+        const [keysUpload] = await m2.outgoingRequests();
+        const oneTimeKeys = JSON.parse(keysUpload.body).one_time_keys;
+        const [keyId] = Object.keys(oneTimeKeys);
+
+        await addMachineToMachine(m2, m1);
+        await addMachineToMachine(m1, m2);
+
+        // Let `m1` claim a one-time key of `m2`, and send it a message.
+        await m1.markRequestAsSent(
+            "claim",
+            RequestType.KeysClaim,
+            JSON.stringify({
+                one_time_keys: { [userId2.toString()]: { [deviceId2.toString()]: { [keyId]: oneTimeKeys[keyId] } } },
+                failures: {},
+            }),
+        );
+        const device2 = await m1.getDevice(userId2, deviceId2);
+        const content = JSON.parse(await device2.encryptToDeviceEvent("org.example.custom", { hello: "world" }));
+        const event = { type: "m.room.encrypted", sender: userId1.toString(), content };
+
+        let processed = await m2.processSyncChanges([event], new DeviceLists(), new Map(), new Set());
+        expect(processed.encryptedEvents).toStrictEqual(1);
+        expect(processed.undecryptedEvents).toStrictEqual([]);
+        expect(processed.toDeviceEvents[0].type).toStrictEqual("org.example.custom");
+        expect(processed.toDeviceEvents[0].content.hello).toStrictEqual("world");
+
+        // The same message cannot be decrypted twice.
+        processed = await m2.processSyncChanges([event], new DeviceLists(), new Map(), new Set());
+        expect(processed.encryptedEvents).toStrictEqual(1);
+        expect(processed.undecryptedEvents).toHaveLength(1);
+        expect(processed.undecryptedEvents[0].event).toStrictEqual(event);
+        expect(processed.undecryptedEvents[0].errorCode).toStrictEqual(OlmDecryptionErrorCode.PreKeyMessageRejected);
+    });
 });
 
 describe("Key Verification", () => {
@@ -239,6 +280,17 @@ describe("Key Verification", () => {
         return OlmMachine.initialize(newUser || userId1, newDevice || deviceId1);
     }
 
+    it("can create the content of a verification request, as a string or an object", () => {
+        const methods = [VerificationMethod.SasV1];
+        const content = VerificationRequest.requestObject(userId1, deviceId1, userId2, methods);
+
+        expect(content.msgtype).toStrictEqual("m.key.verification.request");
+        expect(content.from_device).toStrictEqual(deviceId1.toString());
+        expect(content.to).toStrictEqual(userId2.toString());
+        expect(content.methods).toStrictEqual(["m.sas.v1"]);
+        expect(JSON.parse(VerificationRequest.request(userId1, deviceId1, userId2, methods))).toStrictEqual(content);
+    });
+
     it("SAS", async () => {
         // First Olm machine.
         const m1 = await machine(userId1, deviceId1);
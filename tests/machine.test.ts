@@ -1,5 +1,6 @@
 import {
     BackupDecryptionKey,
+    CollectStrategy,
     CrossSigningStatus,
     DecryptedRoomEvent,
     DecryptionErrorCode,
@@ -11,29 +12,45 @@ import {
     EncryptionSettings,
     EventId,
     getVersions,
+    HistoryVisibility,
     InboundGroupSession,
     KeysBackupRequest,
     KeysClaimRequest,
     KeysQueryRequest,
     KeysUploadRequest,
+    LocalTrust,
     MaybeSignature,
     MegolmDecryptionError,
+    OlmDecryptionErrorCode,
     OlmMachine,
     OwnUserIdentity,
+    ProcessedSyncChanges,
+    RequestFailure,
     RequestType,
     RoomId,
+    RoomKeyRecipient,
+    RoomKeyRecipientState,
+    RoomKeyShareInfo,
+    RoomKeyShareReport,
+    RoomKeyShareState,
     RoomKeyWithheldInfo,
     RoomMessageRequest,
     RoomSettings,
     ShieldColor,
     ShieldStateCode,
+    SenderDataType,
     SignatureState,
     SignatureUploadRequest,
     StoreHandle,
+    StoreOpenPhase,
+    StoreStatistics,
+    SyncResponseSummary,
     ToDeviceRequest,
     TrustRequirement,
+    UndecryptedToDeviceEvent,
     UserId,
     OtherUserIdentity,
+    OutboundGroupSessionInfo,
     VerificationRequest,
     Versions,
 } from "@matrix-org/matrix-sdk-crypto-wasm";
@@ -86,6 +103,20 @@ describe(OlmMachine.name, () => {
         storeHandle.free();
     });
 
+    test("can report the progress of opening a StoreHandle", async () => {
+        const progress: StoreOpenPhase[] = [];
+        const onProgress = (phase: StoreOpenPhase) => progress.push(phase);
+
+        let storeHandle = await StoreHandle.open("progress", "world", onProgress);
+        storeHandle.free();
+        expect(progress).toStrictEqual([StoreOpenPhase.KeyDerivation]);
+
+        progress.length = 0;
+        storeHandle = await StoreHandle.openWithKey("progressWithKey", new Uint8Array(32), onProgress);
+        storeHandle.free();
+        expect(progress).toStrictEqual([StoreOpenPhase.Opening]);
+    });
+
     test("can be instantiated with passphrase", async () => {
         let storeName = "hello2";
         let storePassphrase = "world";
@@ -151,6 +182,127 @@ describe(OlmMachine.name, () => {
         expect(deviceKeys2.ed25519.toBase64()).toEqual(deviceKeys.ed25519.toBase64());
     });
 
+    test("can share a store between two machines, using the store lock", async () => {
+        const storeName = "shared";
+        const userId = new UserId("@foo:bar.org");
+        const deviceId = new DeviceId("baz");
+        const bob = new UserId("@bob:bar.org");
+
+        const machine1 = await OlmMachine.initFromStore(userId, deviceId, await StoreHandle.open(storeName));
+        const machine2 = await OlmMachine.initFromStore(userId, deviceId, await StoreHandle.open(storeName));
+
+        // Nobody held the lock before, so there is nothing to reload.
+        expect(await machine1.acquireStoreLock()).toStrictEqual(false);
+
+        // While the first machine holds the lock, the second one cannot take it.
+        await expect(machine2.acquireStoreLock(20)).rejects.toThrow();
+
+        // The second machine caches the tracked users...
+        expect((await machine2.trackedUsers()).size).toStrictEqual(0);
+        const staleDevice = await machine2.getDevice(userId, deviceId);
+
+        // ... which the first one then changes.
+        await machine1.updateTrackedUsers([bob.clone()]);
+        machine1.releaseStoreLock();
+
+        // The second machine notices that the store was written to, and reloads.
+        expect(await machine2.acquireStoreLock()).toStrictEqual(true);
+        expect([...(await machine2.trackedUsers())].map((u: UserId) => u.toString())).toStrictEqual([
+            bob.toString(),
+        ]);
+
+        // The objects obtained before the reload can no longer write to the store.
+        await expect(staleDevice!.setLocalTrust(LocalTrust.Verified)).rejects.toThrow(/reloaded/);
+        await (await machine2.getDevice(userId, deviceId))!.setLocalTrust(LocalTrust.Verified);
+        machine2.releaseStoreLock();
+
+        // The first machine in turn notices that the second one held the lock.
+        expect(await machine1.acquireStoreLock()).toStrictEqual(true);
+        machine1.releaseStoreLock();
+    });
+
+    test("can be instantiated read-only, to decrypt events", async () => {
+        const userId = new UserId("@foo:bar.org");
+        const deviceId = new DeviceId("baz");
+        const room = new RoomId("!test:localhost");
+
+        const storeHandle = await StoreHandle.open("readOnly");
+
+        // A read-only machine needs an existing account.
+        await expect(OlmMachine.initializeReadOnly(userId, deviceId, storeHandle)).rejects.toThrow();
+
+        const m = await OlmMachine.initFromStore(userId, deviceId, storeHandle);
+        await m.shareRoomKey(room, [userId.clone()], new EncryptionSettings());
+        const encrypted = await m.encryptRoomEvent(room, "m.room.message", JSON.stringify({ body: "Hello" }));
+
+        const readOnly = await OlmMachine.initializeReadOnly(userId, deviceId, storeHandle);
+        expect(readOnly.isReadOnly).toStrictEqual(true);
+        expect(m.isReadOnly).toStrictEqual(false);
+        expect(readOnly.identityKeys.ed25519.toBase64()).toStrictEqual(m.identityKeys.ed25519.toBase64());
+
+        // Decryption works...
+        const event = JSON.stringify({
+            type: "m.room.encrypted",
+            event_id: "$xxxxx:example.org",
+            origin_server_ts: Date.now(),
+            sender: userId.toString(),
+            content: JSON.parse(encrypted),
+        });
+        const decrypted = await readOnly.decryptRoomEvent(
+            event,
+            room,
+            new DecryptionSettings(TrustRequirement.Untrusted),
+        );
+        expect(JSON.parse(decrypted.event).content.body).toStrictEqual("Hello");
+        expect((await readOnly.getRoomEventEncryptionInfo(event, room)).sender?.toString()).toStrictEqual(
+            userId.toString(),
+        );
+
+        // ... but anything which would modify the state of the machine is refused.
+        await expect(readOnly.outgoingRequests()).rejects.toThrow(/read-only/);
+        await expect(readOnly.shareRoomKey(room, [userId.clone()], new EncryptionSettings())).rejects.toThrow(
+            /read-only/,
+        );
+        expect(() => readOnly.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set())).toThrow(
+            /read-only/,
+        );
+        expect(() => readOnly.queryKeysForUsers([userId.clone()])).toThrow(/read-only/);
+        await expect(readOnly.markAllTrackedUsersAsDirty()).rejects.toThrow(/read-only/);
+        expect(() => readOnly.dehydratedDevices()).toThrow(/read-only/);
+
+        // So does anything which would modify it via the objects obtained from the machine.
+        const device = (await readOnly.getDevice(userId, deviceId))!;
+        expect(() => device.requestVerification()).toThrow(/read-only/);
+        await expect(device.setLocalTrust(LocalTrust.Verified)).rejects.toThrow(/read-only/);
+        await expect(device.encryptToDeviceEvent("m.test", { body: "Hi" })).rejects.toThrow(/read-only/);
+        const devices = await readOnly.getUserDevices(userId);
+        await expect(devices.get(deviceId)!.verify()).rejects.toThrow(/read-only/);
+
+        expect(readOnly.roomKeyRequestsEnabled).toStrictEqual(false);
+        expect(() => {
+            readOnly.roomKeyRequestsEnabled = true;
+        }).toThrow(/read-only/);
+    });
+
+    test("can report statistics about its store, and check its integrity", async () => {
+        const userId = new UserId("@foo:bar.org");
+        const deviceId = new DeviceId("baz");
+        const room = new RoomId("!test:localhost");
+
+        const storeHandle = await StoreHandle.open("statistics");
+        const m = await OlmMachine.initFromStore(userId, deviceId, storeHandle);
+        await m.shareRoomKey(room, [userId.clone()], new EncryptionSettings());
+
+        const statistics = await storeHandle.statistics();
+        expect(statistics).toBeInstanceOf(StoreStatistics);
+        expect(statistics.roomKeyCounts.total).toStrictEqual(1);
+        expect(statistics.roomKeyCounts.backedUp).toStrictEqual(0);
+        expect(statistics.inboundGroupSessionsPerRoom.get(room.toString())).toStrictEqual(1);
+        expect(statistics.secretsInInbox).toStrictEqual(0);
+
+        expect(await storeHandle.verifyIntegrity()).toStrictEqual([]);
+    });
+
     describe("cannot be instantiated with a store", () => {
         test("store name is missing", async () => {
             let storePassphrase = "world";
@@ -450,6 +602,468 @@ describe(OlmMachine.name, () => {
         });
     });
 
+    test("can mark requests as failed, and backs off from them", async () => {
+        const m = await machine();
+        await m.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set());
+
+        const failures: Array<RequestFailure> = [];
+        m.registerRequestFailedCallback((failure: RequestFailure) => failures.push(failure));
+
+        const [keysUpload] = await m.outgoingRequests();
+        expect(keysUpload).toBeInstanceOf(KeysUploadRequest);
+
+        const failure = await m.markRequestAsFailed(
+            keysUpload.id!,
+            keysUpload.type,
+            429,
+            JSON.stringify({ errcode: "M_LIMIT_EXCEEDED", error: "Too many requests", retry_after_ms: 10000 }),
+        );
+        expect(failure).toBeInstanceOf(RequestFailure);
+        expect(failure.requestId).toStrictEqual(keysUpload.id);
+        expect(failure.type).toStrictEqual(RequestType.KeysUpload);
+        expect(failure.status).toStrictEqual(429);
+        expect(failure.errcode).toStrictEqual("M_LIMIT_EXCEEDED");
+        expect(failure.message).toStrictEqual("Too many requests");
+        expect(failure.retryable).toStrictEqual(true);
+        expect(failure.retryAfterMs).toStrictEqual(10000);
+        expect(failure.backupDisabled).toStrictEqual(false);
+        expect(failures).toHaveLength(1);
+
+        // The keys upload is held back, but the other requests are not.
+        const outgoingRequests = await m.outgoingRequests();
+        expect(outgoingRequests.some((request) => request instanceof KeysUploadRequest)).toStrictEqual(false);
+        expect(outgoingRequests.some((request) => request instanceof KeysQueryRequest)).toStrictEqual(true);
+
+        // Client errors are not worth retrying...
+        const clientError = await m.markRequestAsFailed(
+            "id",
+            RequestType.KeysQuery,
+            400,
+            JSON.stringify({ errcode: "M_UNKNOWN", error: "Bad request" }),
+        );
+        expect(clientError.retryable).toStrictEqual(false);
+        expect(clientError.retryAfterMs).toStrictEqual(1000);
+
+        // ... but network errors are, with an increasing delay.
+        const networkError = await m.markRequestAsFailed("id", RequestType.KeysQuery, 0);
+        expect(networkError.retryable).toStrictEqual(true);
+        expect(networkError.errcode).toBeUndefined();
+        expect(networkError.retryAfterMs).toStrictEqual(2000);
+        expect(failures).toHaveLength(3);
+
+        // The callback can register another callback.
+        const laterFailures: Array<RequestFailure> = [];
+        m.registerRequestFailedCallback(() =>
+            m.registerRequestFailedCallback((failure: RequestFailure) => laterFailures.push(failure)),
+        );
+        await m.markRequestAsFailed("id", RequestType.KeysQuery, 0);
+        await m.markRequestAsFailed("id", RequestType.KeysQuery, 0);
+        expect(laterFailures).toHaveLength(1);
+    });
+
+    test("accepts and returns plain objects instead of JSON strings", async () => {
+        const userId = new UserId("@foo:bar.org");
+        const room = new RoomId("!test:localhost");
+        const m = await machine(userId);
+
+        // `receiveSyncChanges` returns objects when given objects...
+        expect(await m.receiveSyncChanges([], new DeviceLists(), new Map(), new Set())).toStrictEqual([]);
+        // ... and strings when given strings.
+        expect(await m.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set())).toStrictEqual("[]");
+
+        const [keysUpload] = await m.outgoingRequests();
+        expect(keysUpload).toBeInstanceOf(KeysUploadRequest);
+        expect(
+            await m.markRequestAsSent(keysUpload.id!, keysUpload.type, {
+                one_time_key_counts: { signed_curve25519: 50 },
+            }),
+        ).toStrictEqual(true);
+
+        await m.shareRoomKey(room, [userId.clone()], new EncryptionSettings());
+        const encrypted = await m.encryptRoomEvent(room, "m.room.message", { msgtype: "m.text", body: "Hello" });
+        expect(encrypted.algorithm).toStrictEqual("m.megolm.v1.aes-sha2");
+
+        const event = {
+            type: "m.room.encrypted",
+            event_id: "$xxxxx:example.org",
+            origin_server_ts: Date.now(),
+            sender: userId.toString(),
+            content: encrypted,
+        };
+        const decrypted = await m.decryptRoomEvent(event, room, new DecryptionSettings(TrustRequirement.Untrusted));
+        expect(typeof decrypted.event).toStrictEqual("string");
+        expect((decrypted.eventObject as any).content.body).toStrictEqual("Hello");
+        expect(decrypted.sender?.toString()).toStrictEqual(userId.toString());
+
+        // Strings still work.
+        const decryptedFromString = await m.decryptRoomEvent(
+            JSON.stringify(event),
+            room,
+            new DecryptionSettings(TrustRequirement.Untrusted),
+        );
+        expect(decryptedFromString.eventObject).toStrictEqual(decrypted.eventObject);
+        expect((await m.getRoomEventEncryptionInfo(event, room)).sender.toString()).toStrictEqual(userId.toString());
+    });
+
+    test("can tell which to-device events could not be decrypted", async () => {
+        const m = await machine();
+        const ownKey = m.identityKeys.curve25519.toBase64();
+        const senderKey = "7mzXDkr6/M7rY0O7TAHrd3DbQHh0oSMtfTVLWkJXKQ8";
+        const encrypted = (ciphertext: object) => ({
+            type: "m.room.encrypted",
+            sender: "@bob:example.org",
+            content: {
+                algorithm: "m.olm.v1.curve25519-aes-sha2",
+                sender_key: senderKey,
+                ciphertext,
+            },
+        });
+
+        // A well-formed normal Olm message, which we cannot decrypt.
+        const body = Buffer.concat([
+            Buffer.from([0x03, 0x0a, 0x20]),
+            Buffer.alloc(32, 1),
+            Buffer.from([0x10, 0x00, 0x22, 0x04, 1, 2, 3, 4]),
+            Buffer.alloc(8),
+        ])
+            .toString("base64")
+            .replace(/=+$/, "");
+
+        const result = await m.processSyncChanges(
+            [
+                { type: "org.example.custom", sender: "@bob:example.org", content: { hello: "world" } },
+                encrypted({ [ownKey]: { type: 1, body } }),
+                encrypted({ [senderKey]: { type: 1, body } }),
+                encrypted({ [ownKey]: { type: 1, body: "AwogbWFsZm9ybWVk" } }),
+                // A plaintext event claiming to have been decrypted.
+                {
+                    type: "m.room_key",
+                    sender: "@bob:example.org",
+                    keys: { ed25519: "NayrMQ33ObqMRqz6R9GosmHdT6HQ6b/RX/3QlZ2yiec" },
+                    content: { algorithm: "m.megolm.v1.aes-sha2", room_id: "!test:localhost", session_id: "id" },
+                },
+            ],
+            new DeviceLists(),
+            new Map(),
+            new Set(),
+        );
+
+        expect(result).toBeInstanceOf(ProcessedSyncChanges);
+        expect(result.roomKeyInfos).toStrictEqual([]);
+        expect(result.toDeviceEvents).toHaveLength(5);
+        expect(result.toDeviceEvents[0].content.hello).toStrictEqual("world");
+        expect(result.encryptedEvents).toStrictEqual(3);
+
+        const [missingSession, missingCiphertext, malformed] = result.undecryptedEvents;
+        expect(result.undecryptedEvents).toHaveLength(3);
+        expect(missingSession).toBeInstanceOf(UndecryptedToDeviceEvent);
+        expect(missingSession.event.content.ciphertext[ownKey].body).toStrictEqual(body);
+        expect(missingSession.errorCode).toStrictEqual(OlmDecryptionErrorCode.MissingSession);
+        expect(missingCiphertext.errorCode).toStrictEqual(OlmDecryptionErrorCode.MissingCiphertext);
+        expect(malformed.errorCode).toStrictEqual(OlmDecryptionErrorCode.Malformed);
+
+        // Events passed as a string are returned as strings.
+        const fromString = await m.processSyncChanges(
+            JSON.stringify([encrypted({ [senderKey]: { type: 1, body } })]),
+            new DeviceLists(),
+            new Map(),
+        );
+        expect(typeof fromString.toDeviceEvents).toStrictEqual("string");
+        expect(typeof fromString.undecryptedEvents[0].event).toStrictEqual("string");
+    });
+
+    test("can receive a whole sync response", async () => {
+        const m = await machine();
+        const room = "!encrypted:example.org";
+        const member = (userId: string, membership: string) => ({
+            type: "m.room.member",
+            state_key: userId,
+            sender: userId,
+            event_id: `$${membership}:${userId}`,
+            origin_server_ts: 0,
+            content: { membership },
+        });
+
+        const summary = await m.receiveSyncResponse({
+            next_batch: "s1",
+            to_device: { events: [] },
+            device_lists: { changed: [], left: [] },
+            "org.matrix.msc3202.device_one_time_keys_count": { signed_curve25519: 50 },
+            device_unused_fallback_key_types: ["signed_curve25519"],
+            rooms: {
+                join: {
+                    [room]: {
+                        state: {
+                            events: [
+                                {
+                                    type: "m.room.encryption",
+                                    state_key: "",
+                                    sender: "@bob:example.org",
+                                    event_id: "$encryption",
+                                    origin_server_ts: 0,
+                                    content: { algorithm: "m.megolm.v1.aes-sha2", rotation_period_msgs: 10 },
+                                },
+                                member("@bob:example.org", "join"),
+                            ],
+                        },
+                        timeline: {
+                            events: [
+                                member("@carol:example.org", "invite"),
+                                member("@dave:example.org", "join"),
+                                member("@dave:example.org", "leave"),
+                            ],
+                        },
+                    },
+                    "!plaintext:example.org": {
+                        timeline: { events: [member("@erin:example.org", "join")] },
+                    },
+                },
+            },
+        });
+
+        expect(summary).toBeInstanceOf(SyncResponseSummary);
+        expect(summary.toDeviceEvents).toStrictEqual([]);
+        expect(summary.encryptedToDeviceEvents).toStrictEqual(0);
+        expect(summary.undecryptedToDeviceEvents).toStrictEqual([]);
+        expect(summary.roomKeyInfos).toStrictEqual([]);
+        expect(summary.encryptedRooms.map((roomId: RoomId) => roomId.toString())).toStrictEqual([room]);
+        expect([...summary.newMembers.keys()]).toStrictEqual([room]);
+        expect(summary.newMembers.get(room).map((userId: UserId) => userId.toString())).toStrictEqual([
+            "@bob:example.org",
+            "@carol:example.org",
+        ]);
+        expect(summary.departedMembers.get(room).map((userId: UserId) => userId.toString())).toStrictEqual([
+            "@dave:example.org",
+        ]);
+
+        const settings = await m.getRoomSettings(new RoomId(room));
+        expect(settings.algorithm).toStrictEqual(EncryptionAlgorithm.MegolmV1AesSha2);
+        expect(settings.sessionRotationPeriodMessages).toStrictEqual(10);
+
+        const trackedUsers = [...(await m.trackedUsers())].map((userId: UserId) => userId.toString());
+        expect(trackedUsers).toContain("@bob:example.org");
+        expect(trackedUsers).toContain("@carol:example.org");
+        expect(trackedUsers).not.toContain("@erin:example.org");
+
+        const recipients = await m.getRoomKeyRecipients(new RoomId(room));
+        expect(recipients.map((userId: UserId) => userId.toString())).toStrictEqual([
+            "@bob:example.org",
+            "@carol:example.org",
+        ]);
+
+        // A JSON-encoded response gets JSON-encoded to-device events back.
+        const again = await m.receiveSyncResponse(JSON.stringify({ next_batch: "s2" }));
+        expect(again.toDeviceEvents).toStrictEqual("[]");
+        expect(again.encryptedRooms).toStrictEqual([]);
+    });
+
+    test("tracks the members of encrypted rooms", async () => {
+        const m = await machine();
+        const room = new RoomId("!encrypted:example.org");
+        const member = (userId: string, membership: string) => ({
+            type: "m.room.member",
+            state_key: userId,
+            sender: userId,
+            content: { membership },
+        });
+        const recipients = async () =>
+            (await m.getRoomKeyRecipients(room)).map((userId: UserId) => userId.toString());
+
+        // The events of rooms which are not encrypted are ignored.
+        expect(await m.receiveRoomStateEvents(room, [member("@bob:example.org", "join")])).toStrictEqual(false);
+        expect(await recipients()).toStrictEqual([]);
+
+        // The room key is not shared with nobody when no member is tracked.
+        await expect(m.shareRoomKey(room, undefined, new EncryptionSettings())).rejects.toThrow(/not tracked/);
+        await expect(m.shareRoomKeyWithReport(room, undefined, new EncryptionSettings())).rejects.toThrow(
+            /not tracked/,
+        );
+
+        await m.setRoomSettings(room, new RoomSettings());
+        await m.receiveRoomStateEvents(
+            room,
+            JSON.stringify([
+                member("@bob:example.org", "join"),
+                member("@carol:example.org", "invite"),
+                { type: "m.room.message", sender: "@bob:example.org", content: { body: "Hi" } },
+            ]),
+        );
+        expect(await recipients()).toStrictEqual(["@bob:example.org", "@carol:example.org"]);
+        expect([...(await m.trackedUsers())].map((userId: UserId) => userId.toString())).toContain(
+            "@carol:example.org",
+        );
+
+        // The room key is shared with the tracked members by default.
+        await m.shareRoomKey(room, undefined, new EncryptionSettings());
+        expect(await m.getOutboundGroupSession(room)).toBeDefined();
+
+        // Invited members don't receive the room keys if the history is only visible to joined members,
+        // so the room key is discarded.
+        const discarded = await m.receiveRoomStateEvents(room, [
+            {
+                type: "m.room.history_visibility",
+                state_key: "",
+                sender: "@bob:example.org",
+                content: { history_visibility: "joined" },
+            },
+        ]);
+        expect(discarded).toStrictEqual(true);
+        expect(await recipients()).toStrictEqual(["@bob:example.org"]);
+        expect((await m.getOutboundGroupSession(room))!.needsRotation).toStrictEqual(true);
+
+        await m.receiveRoomStateEvents(room, [
+            member("@carol:example.org", "join"),
+            member("@bob:example.org", "leave"),
+        ]);
+        expect(await recipients()).toStrictEqual(["@carol:example.org"]);
+    });
+
+    test("applies concurrent membership changes of a room one after the other", async () => {
+        const m = await machine();
+        const room = new RoomId("!encrypted:example.org");
+        const member = (userId: string, membership: string) => ({
+            type: "m.room.member",
+            state_key: userId,
+            sender: userId,
+            event_id: `$${membership}:${userId}`,
+            origin_server_ts: 0,
+            content: { membership },
+        });
+
+        await m.setRoomSettings(room, new RoomSettings());
+
+        // Both updates load, modify and store the members of the same room: none of the changes is lost.
+        await Promise.all([
+            m.receiveRoomStateEvents(room, [member("@bob:example.org", "join")]),
+            m.receiveSyncResponse({
+                next_batch: "s1",
+                rooms: {
+                    join: {
+                        [room.toString()]: { timeline: { events: [member("@carol:example.org", "join")] } },
+                    },
+                },
+            }),
+            m.receiveRoomStateEvents(room, [member("@dave:example.org", "invite")]),
+        ]);
+
+        const recipients = await m.getRoomKeyRecipients(room);
+        expect(recipients.map((userId: UserId) => userId.toString())).toStrictEqual([
+            "@bob:example.org",
+            "@carol:example.org",
+            "@dave:example.org",
+        ]);
+    });
+
+    test("derives the room settings from m.room.encryption events", async () => {
+        const m = await machine();
+        const room = new RoomId("!encrypted:example.org");
+        const encryption = (content: object) => ({
+            type: "m.room.encryption",
+            state_key: "",
+            sender: "@bob:example.org",
+            content,
+        });
+
+        expect(await m.getEncryptionSettings(room)).toBeUndefined();
+
+        // Invalid events are rejected.
+        const megolm = encryption({ algorithm: "m.megolm.v1.aes-sha2" });
+        await expect(m.receiveRoomEncryptionEvent(room, { ...megolm, state_key: "x" })).rejects.toThrow(/state key/);
+        await expect(m.receiveRoomEncryptionEvent(room, { ...megolm, type: "m.room.name" })).rejects.toThrow(
+            /Expected/,
+        );
+        await expect(m.receiveRoomEncryptionEvent(room, encryption({}))).rejects.toThrow(/Invalid/);
+        await expect(
+            m.receiveRoomEncryptionEvent(room, encryption({ algorithm: "m.olm.v1.curve25519-aes-sha2" })),
+        ).rejects.toThrow(/Unsupported/);
+        expect(await m.getRoomSettings(room)).toBeUndefined();
+
+        const settings = await m.receiveRoomEncryptionEvent(
+            room,
+            JSON.stringify(
+                encryption({
+                    algorithm: "m.megolm.v1.aes-sha2",
+                    rotation_period_ms: 3600000,
+                    rotation_period_msgs: 10,
+                }),
+            ),
+        );
+        expect(settings.algorithm).toStrictEqual(EncryptionAlgorithm.MegolmV1AesSha2);
+        expect(settings.onlyAllowTrustedDevices).toStrictEqual(false);
+        expect(settings.sessionRotationPeriodMs).toStrictEqual(3600000);
+        expect(settings.sessionRotationPeriodMessages).toStrictEqual(10);
+        expect((await m.getRoomSettings(room)).sessionRotationPeriodMs).toStrictEqual(3600000);
+
+        // The settings of an encrypted room can't be downgraded, and don't change.
+        await expect(m.receiveRoomEncryptionEvent(room, encryption({ algorithm: "m.unknown" }))).rejects.toThrow(
+            /Unsupported/,
+        );
+        const again = await m.receiveRoomEncryptionEvent(
+            room,
+            encryption({ algorithm: "m.megolm.v1.aes-sha2", rotation_period_msgs: 1000 }),
+        );
+        expect(again.sessionRotationPeriodMessages).toStrictEqual(10);
+
+        // The settings to share the room keys with follow the room settings and history visibility.
+        let encryptionSettings = (await m.getEncryptionSettings(room))!;
+        expect(encryptionSettings.algorithm).toStrictEqual(EncryptionAlgorithm.MegolmV1AesSha2);
+        expect(encryptionSettings.rotationPeriod).toStrictEqual(3600000000n);
+        expect(encryptionSettings.rotationPeriodMessages).toStrictEqual(10n);
+        expect(encryptionSettings.historyVisibility).toStrictEqual(HistoryVisibility.Shared);
+        expect(encryptionSettings.sharingStrategy.eq(CollectStrategy.allDevices())).toBe(true);
+
+        await m.receiveRoomStateEvents(room, [
+            {
+                type: "m.room.history_visibility",
+                state_key: "",
+                sender: "@bob:example.org",
+                content: { history_visibility: "joined" },
+            },
+        ]);
+        encryptionSettings = (await m.getEncryptionSettings(room))!;
+        expect(encryptionSettings.historyVisibility).toStrictEqual(HistoryVisibility.Joined);
+
+        // Custom history visibilities are ignored.
+        await m.receiveRoomStateEvents(room, [
+            {
+                type: "m.room.history_visibility",
+                state_key: "",
+                sender: "@bob:example.org",
+                content: { history_visibility: "org.example.custom" },
+            },
+        ]);
+        encryptionSettings = (await m.getEncryptionSettings(room))!;
+        expect(encryptionSettings.historyVisibility).toStrictEqual(HistoryVisibility.Joined);
+
+        await m.shareRoomKey(room, undefined, encryptionSettings);
+        expect((await m.getOutboundGroupSession(room))!.settings.rotationPeriodMessages).toStrictEqual(10n);
+    });
+
+    test("can receive the extensions of a sliding sync response", async () => {
+        const m = await machine();
+        expect(await m.getNextBatchToken()).toBeUndefined();
+
+        const toDeviceEvents = await m.receiveSlidingSyncExtensions(
+            { next_batch: "to-device-1", events: [] },
+            {
+                device_lists: { changed: ["@bob:example.org"] },
+                device_one_time_keys_count: { signed_curve25519: 50 },
+                device_unused_fallback_key_types: [],
+            },
+        );
+        expect(toDeviceEvents).toStrictEqual([]);
+        expect(await m.getNextBatchToken()).toStrictEqual("to-device-1");
+
+        // Either extension may be missing, and the token is kept if the
+        // `to_device` one is.
+        expect(await m.receiveSlidingSyncExtensions(undefined, "{}")).toStrictEqual([]);
+        expect(await m.getNextBatchToken()).toStrictEqual("to-device-1");
+        expect(
+            await m.receiveSlidingSyncExtensions(JSON.stringify({ next_batch: "to-device-2", events: [] })),
+        ).toStrictEqual("[]");
+        expect(await m.getNextBatchToken()).toStrictEqual("to-device-2");
+    });
+
     describe("setup workflow to encrypt/decrypt events", () => {
         let m: OlmMachine;
         const user = new UserId("@alice:example.org");
@@ -578,6 +1192,11 @@ describe(OlmMachine.name, () => {
             const messageContent = content.messages["@example:localhost"]["AFGUOBTZWM"];
             expect(messageContent["org.matrix.msgid"]).toBeDefined();
 
+            const pendingSession = (await m.getOutboundGroupSession(room))!;
+            expect(pendingSession.shared).toStrictEqual(false);
+            expect(pendingSession.devices).toHaveLength(1);
+            expect(pendingSession.devices[0].state).toStrictEqual(RoomKeyShareState.Pending);
+
             await m.markRequestAsSent(requests[0].id, RequestType.ToDevice, "{}");
 
             const requestsAfterMarkedAsSent = await m.shareRoomKey(
@@ -588,6 +1207,22 @@ describe(OlmMachine.name, () => {
             expect(requestsAfterMarkedAsSent).toHaveLength(0);
         });
 
+        test("can report who a room key was shared with", async () => {
+            const other_user_id = new UserId("@example:localhost");
+
+            const report = await m.shareRoomKeyWithReport(room, [other_user_id.clone()], new EncryptionSettings());
+            expect(report).toBeInstanceOf(RoomKeyShareReport);
+            expect(report.requests).toHaveLength(0);
+            expect(report.recipients).toHaveLength(1);
+
+            const [recipient] = report.recipients;
+            expect(recipient).toBeInstanceOf(RoomKeyRecipient);
+            expect(recipient.deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(recipient.state).toStrictEqual(RoomKeyRecipientState.Shared);
+            expect(recipient.messageIndex).toStrictEqual(0);
+            expect(recipient.skipReason).toBeUndefined();
+        });
+
         let encrypted: Record<string, any>;
 
         test("can encrypt an event", async () => {
@@ -609,6 +1244,29 @@ describe(OlmMachine.name, () => {
             expect(encrypted.session_id).toBeDefined();
         });
 
+        test("can get the outbound group session", async () => {
+            expect(await m.getOutboundGroupSession(new RoomId("!unknown:localhost"))).toBeUndefined();
+
+            const session = (await m.getOutboundGroupSession(room))!;
+            expect(session).toBeInstanceOf(OutboundGroupSessionInfo);
+            expect(session.sessionId).toStrictEqual(encrypted.session_id);
+            expect(session.messageCount).toStrictEqual(1);
+            expect(session.creationTimeMs).toBeLessThanOrEqual(Date.now());
+            expect(session.rotationDeadlineMs - session.creationTimeMs).toStrictEqual(7 * 24 * 60 * 60 * 1000);
+            expect(session.settings.rotationPeriodMessages).toStrictEqual(100n);
+            expect(session.shared).toStrictEqual(true);
+            expect(session.needsRotation).toStrictEqual(false);
+
+            expect(session.devices).toHaveLength(1);
+            const [shareInfo] = session.devices;
+            expect(shareInfo).toBeInstanceOf(RoomKeyShareInfo);
+            expect(shareInfo.userId.toString()).toStrictEqual("@example:localhost");
+            expect(shareInfo.deviceId.toString()).toStrictEqual("AFGUOBTZWM");
+            expect(shareInfo.state).toStrictEqual(RoomKeyShareState.Shared);
+            expect(shareInfo.messageIndex).toStrictEqual(0);
+            expect(shareInfo.withheldCode).toBeUndefined();
+        });
+
         test("can decrypt an event", async () => {
             const stringifiedEvent = JSON.stringify({
                 type: "m.room.encrypted",
@@ -649,6 +1307,29 @@ describe(OlmMachine.name, () => {
             expect(decryptionInfo.shieldState(false)?.color).toStrictEqual(ShieldColor.Red);
             expect(decryptionInfo.shieldState(false)?.code).toStrictEqual(ShieldStateCode.UnsignedDevice);
         });
+
+        test("can get the inbound group sessions", async () => {
+            expect(await m.getInboundGroupSession(room, "unknown")).toBeUndefined();
+            expect(await m.getInboundGroupSessions(new RoomId("!unknown:localhost"))).toStrictEqual([]);
+
+            const sessions = await m.getInboundGroupSessions(room);
+            expect(sessions).toHaveLength(1);
+
+            const session = (await m.getInboundGroupSession(room, encrypted.session_id))!;
+            expect(session).toBeInstanceOf(InboundGroupSession);
+            for (const s of [session, sessions[0]]) {
+                expect(s.sessionId).toStrictEqual(encrypted.session_id);
+                expect(s.roomId.toString()).toStrictEqual(room.toString());
+                expect(s.firstKnownIndex).toStrictEqual(0);
+                expect(s.algorithm).toStrictEqual(EncryptionAlgorithm.MegolmV1AesSha2);
+                expect(s.backedUp).toStrictEqual(false);
+                expect(s.sharedHistory).toStrictEqual(true);
+                expect(s.hasBeenImported()).toStrictEqual(false);
+                expect(s.senderDataType).toStrictEqual(SenderDataType.DeviceInfo);
+                expect(s.senderUserId?.toString()).toStrictEqual(user.toString());
+                expect(s.senderDeviceId?.toString()).toStrictEqual(device.toString());
+            }
+        });
     });
 
     test("failure to decrypt returns a valid error", async () => {
@@ -725,6 +1406,51 @@ describe(OlmMachine.name, () => {
         }
     });
 
+    test("can sign a JSON object", async () => {
+        const m = await machine();
+
+        const signed = await m.signJson({
+            b: 1,
+            a: "x",
+            signatures: { "@other:example.org": { "ed25519:OTHER": "c2lnbmF0dXJl" } },
+        });
+
+        expect(signed.a).toStrictEqual("x");
+        expect(signed.signatures["@other:example.org"]).toStrictEqual({ "ed25519:OTHER": "c2lnbmF0dXJl" });
+        expect(signed.signatures[user.toString()]["ed25519:foobar"]).toMatch(/^[A-Za-z0-9\+/]+$/);
+
+        const verification = await m.verifyJsonSignatures(JSON.stringify(signed), user);
+        expect(verification.deviceState).toStrictEqual(SignatureState.ValidAndTrusted);
+
+        // We have no cross-signing keys, so signing with the master key must fail.
+        await expect(m.signJson({ a: "x" }, { withMasterKey: true })).rejects.toThrow();
+    });
+
+    test("can verify the signatures on a signed JSON object", async () => {
+        const m = await machine();
+        const signatures = await m.sign('{"a":"x","b":1}');
+
+        const signedObject = {
+            b: 1,
+            a: "x",
+            signatures: JSON.parse(signatures.asJSON()),
+            unsigned: { age: 1234 },
+        };
+
+        const verification = await m.verifyJsonSignatures(JSON.stringify(signedObject), user);
+        expect(verification.deviceState).toStrictEqual(SignatureState.ValidAndTrusted);
+        expect(verification.userState).toStrictEqual(SignatureState.Missing);
+        expect(verification.keyStates.get("ed25519:foobar")).toStrictEqual(SignatureState.ValidAndTrusted);
+        expect(verification.trusted()).toStrictEqual(true);
+
+        const tampered = await m.verifyJsonSignatures(JSON.stringify({ ...signedObject, b: 2 }), user);
+        expect(tampered.deviceState).toStrictEqual(SignatureState.Invalid);
+        expect(tampered.trusted()).toStrictEqual(false);
+
+        const unsigned = await m.verifyJsonSignatures(JSON.stringify({ a: "x", b: 1 }), user);
+        expect(unsigned.deviceState).toStrictEqual(SignatureState.Missing);
+    });
+
     test("can mark all tracked users as dirty", async () => {
         const m = await machine();
         await m.markAllTrackedUsersAsDirty();
@@ -1787,4 +2513,109 @@ describe(OlmMachine.name, () => {
             expect(toDeviceEvent.content.foo).toEqual("bar");
         });
     });
+
+    describe("Unwedging Olm sessions", () => {
+        test("does nothing for unknown devices", async () => {
+            const m = await machine();
+            const bobUserId = new UserId("@bob:example.org");
+            const bobDeviceId = new DeviceId("BOB_DEV");
+
+            expect(await m.getOlmSessions(bobUserId, bobDeviceId)).toStrictEqual([]);
+            expect(await m.forceNewOlmSession(bobUserId, bobDeviceId)).toBeUndefined();
+        });
+
+        test("fails without sending an m.dummy event if no new session is created", async () => {
+            const bobUserId = new UserId("@bob:example.org");
+            const bobDeviceId = new DeviceId("BOB_DEV");
+
+            const alice = await machine(new UserId("@alice:example.org"), new DeviceId("ALICE_DEV"));
+            const bob = await machine(bobUserId, bobDeviceId);
+
+            const [keysUploadRequest] = await bob.outgoingRequests();
+            await alice.markRequestAsSent(
+                "SomeUniqueId",
+                RequestType.KeysQuery,
+                JSON.stringify({
+                    device_keys: { "@bob:example.org": { BOB_DEV: JSON.parse(keysUploadRequest.body).device_keys } },
+                    failures: {},
+                }),
+            );
+
+            const claimRequest = (await alice.forceNewOlmSession(bobUserId, bobDeviceId))!;
+            await expect(
+                alice.markRequestAsSent(claimRequest.id, RequestType.KeysClaim, JSON.stringify({ one_time_keys: {} })),
+            ).rejects.toThrow("No new Olm session");
+
+            expect(await alice.getOlmSessions(bobUserId, bobDeviceId)).toStrictEqual([]);
+            expect(
+                (await alice.outgoingRequests()).filter((request: any) => request instanceof ToDeviceRequest),
+            ).toHaveLength(0);
+        });
+
+        test("creates a new session and sends an m.dummy event over it", async () => {
+            const bobUserId = new UserId("@bob:example.org");
+            const bobDeviceId = new DeviceId("BOB_DEV");
+
+            const alice = await machine(new UserId("@alice:example.org"), new DeviceId("ALICE_DEV"));
+            const bob = await machine(bobUserId, bobDeviceId);
+
+            const [keysUploadRequest] = await bob.outgoingRequests();
+            const keysUploadBody = JSON.parse(keysUploadRequest.body);
+
+            // Let Alice know about Bob's device
+            await alice.markRequestAsSent(
+                "SomeUniqueId",
+                RequestType.KeysQuery,
+                JSON.stringify({
+                    device_keys: {
+                        "@bob:example.org": {
+                            BOB_DEV: keysUploadBody.device_keys,
+                        },
+                    },
+                    failures: {},
+                }),
+            );
+            expect(await alice.getOlmSessions(bobUserId, bobDeviceId)).toStrictEqual([]);
+
+            const claimRequest = (await alice.forceNewOlmSession(bobUserId, bobDeviceId))!;
+            expect(claimRequest).toBeInstanceOf(KeysClaimRequest);
+            expect(JSON.parse(claimRequest.body).one_time_keys).toStrictEqual({
+                "@bob:example.org": { BOB_DEV: "signed_curve25519" },
+            });
+
+            const [otkId, otk] = Object.entries(keysUploadBody.one_time_keys)[0];
+            await alice.markRequestAsSent(
+                claimRequest.id,
+                RequestType.KeysClaim,
+                JSON.stringify({
+                    one_time_keys: {
+                        "@bob:example.org": {
+                            BOB_DEV: {
+                                [otkId]: otk,
+                            },
+                        },
+                    },
+                }),
+            );
+
+            const sessions = await alice.getOlmSessions(bobUserId, bobDeviceId);
+            expect(sessions).toHaveLength(1);
+            expect(sessions[0].createdUsingFallbackKey).toStrictEqual(false);
+
+            const dummyRequests = (await alice.outgoingRequests()).filter(
+                (request: any) => request instanceof ToDeviceRequest,
+            ) as ToDeviceRequest[];
+            expect(dummyRequests).toHaveLength(1);
+            expect(dummyRequests[0].event_type).toStrictEqual("m.room.encrypted");
+            expect(Object.keys(JSON.parse(dummyRequests[0].body).messages["@bob:example.org"])).toStrictEqual([
+                "BOB_DEV",
+            ]);
+
+            // Once sent, the m.dummy event is not sent again.
+            await alice.markRequestAsSent(dummyRequests[0].id, RequestType.ToDevice, "{}");
+            expect(
+                (await alice.outgoingRequests()).filter((request: any) => request instanceof ToDeviceRequest),
+            ).toHaveLength(0);
+        });
+    });
 });
@@ -20,3 +20,52 @@ describe("RequestType", () => {
         expect(RequestType.KeysBackup).toStrictEqual(6);
     });
 });
+
+describe("toHttpRequest", () => {
+    const homeserverUrl = "https://example.org/";
+
+    test("builds requests without path parameters", () => {
+        const request = new KeysUploadRequest("id", '{"device_keys":{}}').toHttpRequest(homeserverUrl, "token");
+
+        expect(request.method).toStrictEqual("POST");
+        expect(request.url).toStrictEqual("https://example.org/_matrix/client/v3/keys/upload");
+        expect(request.headers.get("Content-Type")).toStrictEqual("application/json");
+        expect(request.headers.get("Authorization")).toStrictEqual("Bearer token");
+        expect(request.body).toStrictEqual('{"device_keys":{}}');
+
+        expect(new KeysQueryRequest("id", "{}").toHttpRequest(homeserverUrl).url).toStrictEqual(
+            "https://example.org/_matrix/client/v3/keys/query",
+        );
+        expect(new KeysClaimRequest("id", "{}").toHttpRequest(homeserverUrl).url).toStrictEqual(
+            "https://example.org/_matrix/client/v3/keys/claim",
+        );
+        expect(new SignatureUploadRequest("id", "{}").toHttpRequest(homeserverUrl).url).toStrictEqual(
+            "https://example.org/_matrix/client/v3/keys/signatures/upload",
+        );
+        expect(new KeysQueryRequest("id", "{}").toHttpRequest(homeserverUrl).headers.has("Authorization")).toStrictEqual(
+            false,
+        );
+    });
+
+    test("builds requests with path and query parameters", () => {
+        const toDevice = new ToDeviceRequest("id", "m.room.encrypted", "txn1", "{}").toHttpRequest(homeserverUrl);
+        expect(toDevice.method).toStrictEqual("PUT");
+        expect(toDevice.url).toStrictEqual("https://example.org/_matrix/client/v3/sendToDevice/m.room.encrypted/txn1");
+
+        const roomMessage = new RoomMessageRequest(
+            "id",
+            "!room:example.org",
+            "txn2",
+            "m.room.encrypted",
+            "{}",
+        ).toHttpRequest(homeserverUrl);
+        expect(roomMessage.method).toStrictEqual("PUT");
+        expect(roomMessage.url).toStrictEqual(
+            "https://example.org/_matrix/client/v3/rooms/!room:example.org/send/m.room.encrypted/txn2",
+        );
+
+        const backup = new KeysBackupRequest("id", '{"rooms":{}}', "1").toHttpRequest(homeserverUrl);
+        expect(backup.method).toStrictEqual("PUT");
+        expect(backup.url).toStrictEqual("https://example.org/_matrix/client/v3/room_keys/keys?version=1");
+    });
+});
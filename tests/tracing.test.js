@@ -1,4 +1,12 @@
-const { Tracing, LoggerLevel, OlmMachine, UserId, DeviceId } = require("@matrix-org/matrix-sdk-crypto-wasm");
+const {
+    Tracing,
+    LoggerLevel,
+    OlmMachine,
+    UserId,
+    DeviceId,
+    DeviceLists,
+    SpanTimings,
+} = require("@matrix-org/matrix-sdk-crypto-wasm");
 
 describe("LoggerLevel", () => {
     test("has the correct variant values", () => {
@@ -20,6 +28,118 @@ describe(Tracing.name, () => {
             new Tracing(LoggerLevel.Debug);
         });
 
+        test("can pass structured records to a log sink", async () => {
+            const records = [];
+            tracing.setLogSink((record) => records.push(record));
+
+            // Do something that emits a `DEBUG` log.
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+
+            tracing.setLogSink(undefined);
+
+            expect(records.length).toBeGreaterThan(0);
+
+            const record = records[0];
+            expect(record.level).toBeLessThanOrEqual(LoggerLevel.Debug);
+            expect(record.target).toMatch(/^matrix_sdk/);
+            expect(typeof record.message).toStrictEqual("string");
+            expect(typeof record.fields).toStrictEqual("object");
+            expect(Array.isArray(record.spans)).toStrictEqual(true);
+            expect(record.timestamp).toBeLessThanOrEqual(Date.now());
+
+            // Once the sink is removed, it no longer receives records.
+            const count = records.length;
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+            expect(records.length).toStrictEqual(count);
+        });
+
+        test("can set per-target filter directives", async () => {
+            const records = [];
+            tracing.setLogSink((record) => records.push(record));
+            tracing.setFilterDirectives("matrix_sdk_crypto=warn");
+
+            expect(tracing.filterDirectives).toMatch(/^matrix_sdk_crypto=warn$/i);
+
+            // Do something that emits `DEBUG` logs from `matrix_sdk_crypto`.
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+
+            tracing.setFilterDirectives("");
+            tracing.setLogSink(undefined);
+
+            expect(tracing.filterDirectives).toStrictEqual("");
+            expect(
+                records.filter((r) => r.target.startsWith("matrix_sdk_crypto") && r.level < LoggerLevel.Warn),
+            ).toHaveLength(0);
+        });
+
+        test("does not turn the logger on when setting filter directives", async () => {
+            const records = [];
+            tracing.setLogSink((record) => records.push(record));
+            tracing.turnOff();
+            tracing.setFilterDirectives("matrix_sdk_crypto=debug");
+
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+            expect(records).toHaveLength(0);
+
+            tracing.setFilterDirectives("");
+            tracing.turnOn();
+            tracing.setLogSink(undefined);
+        });
+
+        test("rejects invalid filter directives", () => {
+            expect(() => tracing.setFilterDirectives("matrix_sdk_crypto=loud")).toThrow();
+        });
+
+        test("can keep recent logs in memory, at a separate level", async () => {
+            tracing.minLevel = LoggerLevel.Warn;
+            tracing.enableRecentLogs(LoggerLevel.Debug, 1000);
+
+            // Do something that emits a `DEBUG` log.
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+
+            tracing.minLevel = LoggerLevel.Debug;
+
+            const logs = tracing.dumpRecentLogs();
+            expect(logs).toMatch(/^\d{4}-\d{2}-\d{2}T[^ ]+ DEBUG matrix_sdk/m);
+
+            // Shrinking the buffer drops the oldest records.
+            tracing.enableRecentLogs(LoggerLevel.Debug, 2);
+            expect(tracing.dumpRecentLogs().trimEnd().split("\n")).toHaveLength(2);
+
+            tracing.clearRecentLogs();
+            expect(tracing.dumpRecentLogs()).toStrictEqual("");
+
+            tracing.disableRecentLogs();
+            await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+            expect(tracing.dumpRecentLogs()).toStrictEqual("");
+        });
+
+        test("can measure how long spans take", async () => {
+            const spanName = "matrix_sdk_crypto_wasm::machine::receiveSyncChanges";
+            tracing.enableTimings(LoggerLevel.Debug, false);
+
+            const machine = await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("foo"));
+            await machine.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set());
+
+            tracing.disableTimings();
+
+            const timings = tracing.getTimings();
+            const timing = timings.get(spanName);
+            expect(timing).toBeDefined();
+            expect(timing.count).toStrictEqual(1);
+            expect(timing.minMs).toBeLessThanOrEqual(timing.maxMs);
+            expect(timing.totalMs).toBeGreaterThanOrEqual(timing.busyMs);
+            expect(timing.histogram).toHaveLength(SpanTimings.bucketBoundariesMs().length + 1);
+            expect(timing.histogram.reduce((a, b) => a + b, 0)).toStrictEqual(1);
+
+            // Once disabled, no more spans are measured.
+            await machine.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set());
+            expect(tracing.getTimings().get(spanName).count).toStrictEqual(1);
+
+            tracing.resetTimings();
+            expect(tracing.getTimings().size).toStrictEqual(0);
+        });
+
         const originalConsoleDebug = console.debug;
 
         for (const [testName, testPreState, testPostState, expectedGotcha] of [
@@ -0,0 +1,162 @@
+import {
+    DeviceId,
+    DeviceLists,
+    HomeserverTransport,
+    OlmMachine,
+    RequestFailure,
+    RequestType,
+    TransportReport,
+    UserId,
+} from "@matrix-org/matrix-sdk-crypto-wasm";
+import "fake-indexeddb/auto";
+import * as http from "node:http";
+import { AddressInfo } from "node:net";
+
+afterEach(() => {
+    // reset fake-indexeddb after each test, to make sure we don't leak data
+    // cf https://github.com/dumbmatter/fakeIndexedDB#wipingresetting-the-indexeddb-for-a-fresh-state
+    // eslint-disable-next-line no-global-assign
+    indexedDB = new IDBFactory();
+});
+
+interface ReceivedRequest {
+    method: string;
+    url: string;
+    authorization?: string;
+    body: any;
+}
+
+/**
+ * Start a stub homeserver, which answers each request with the response returned by `respond`.
+ */
+async function startServer(
+    respond: (request: ReceivedRequest) => { status: number; body: any },
+): Promise<{ url: string; received: ReceivedRequest[]; close: () => Promise<void> }> {
+    const received: ReceivedRequest[] = [];
+
+    const server = http.createServer((req, res) => {
+        let body = "";
+        req.on("data", (chunk) => (body += chunk));
+        req.on("end", () => {
+            const request = {
+                method: req.method!,
+                url: req.url!,
+                authorization: req.headers.authorization,
+                body: JSON.parse(body),
+            };
+            received.push(request);
+
+            const response = respond(request);
+            res.writeHead(response.status, { "Content-Type": "application/json" });
+            res.end(JSON.stringify(response.body));
+        });
+    });
+
+    await new Promise<void>((resolve) => server.listen(0, "127.0.0.1", resolve));
+    const { port } = server.address() as AddressInfo;
+
+    return {
+        url: `http://127.0.0.1:${port}`,
+        received,
+        close: () => new Promise((resolve) => server.close(() => resolve())),
+    };
+}
+
+async function machine(): Promise<OlmMachine> {
+    const m = await OlmMachine.initialize(new UserId("@alice:example.org"), new DeviceId("DEVICEID"));
+    await m.receiveSyncChanges("[]", new DeviceLists(), new Map(), new Set());
+    return m;
+}
+
+describe(HomeserverTransport.name, () => {
+    test("sends the outgoing requests and passes the responses back", async () => {
+        const server = await startServer((request) => {
+            if (request.url === "/_matrix/client/v3/keys/upload") {
+                return { status: 200, body: { one_time_key_counts: { signed_curve25519: 50 } } };
+            }
+            return { status: 200, body: { device_keys: {}, failures: {} } };
+        });
+
+        try {
+            const m = await machine();
+            const transport = new HomeserverTransport(server.url, async () => "secret");
+
+            const report = await transport.sendOutgoingRequests(m);
+            expect(report).toBeInstanceOf(TransportReport);
+            expect(report.sent).toStrictEqual(2);
+            expect(report.failures).toStrictEqual([]);
+
+            expect(server.received.map((request) => request.url).sort()).toStrictEqual([
+                "/_matrix/client/v3/keys/query",
+                "/_matrix/client/v3/keys/upload",
+            ]);
+            for (const request of server.received) {
+                expect(request.method).toStrictEqual("POST");
+                expect(request.authorization).toStrictEqual("Bearer secret");
+            }
+
+            // The keys have been uploaded, so there is nothing left to send.
+            expect(await m.outgoingRequests()).toStrictEqual([]);
+        } finally {
+            await server.close();
+        }
+    });
+
+    test("retries requests which may succeed, and reports failures", async () => {
+        let attempts = 0;
+        const server = await startServer((request) => {
+            if (request.url === "/_matrix/client/v3/keys/upload") {
+                attempts += 1;
+                return {
+                    status: 429,
+                    body: { errcode: "M_LIMIT_EXCEEDED", error: "Too many requests", retry_after_ms: 10 },
+                };
+            }
+            return { status: 400, body: { errcode: "M_UNKNOWN", error: "Bad request" } };
+        });
+
+        try {
+            const m = await machine();
+            const reported: Array<RequestFailure> = [];
+            m.registerRequestFailedCallback((failure: RequestFailure) => reported.push(failure));
+
+            const transport = new HomeserverTransport(server.url, () => undefined);
+            transport.maxRetries = 2;
+            transport.maxConcurrentRequests = 1;
+
+            const report = await transport.sendOutgoingRequests(m);
+            expect(report.sent).toStrictEqual(0);
+            expect(report.failures).toHaveLength(2);
+
+            const failures = report.failures.sort((a: RequestFailure, b: RequestFailure) => a.type - b.type);
+            expect(failures[0].type).toStrictEqual(RequestType.KeysUpload);
+            expect(failures[0].errcode).toStrictEqual("M_LIMIT_EXCEEDED");
+            expect(failures[1].type).toStrictEqual(RequestType.KeysQuery);
+            expect(failures[1].retryable).toStrictEqual(false);
+
+            // The rate-limited request was sent three times, the bad one once.
+            expect(attempts).toStrictEqual(3);
+            expect(server.received).toHaveLength(4);
+            expect(reported).toHaveLength(4);
+            expect(server.received[0].authorization).toBeUndefined();
+        } finally {
+            await server.close();
+        }
+    });
+
+    test("reports network errors", async () => {
+        const m = await machine();
+        const transport = new HomeserverTransport("http://127.0.0.1:1", () => "secret", async () => {
+            throw new TypeError("fetch failed");
+        });
+        transport.maxRetries = 0;
+
+        const report = await transport.sendOutgoingRequests(m);
+        expect(report.sent).toStrictEqual(0);
+        expect(report.failures).toHaveLength(2);
+        for (const failure of report.failures) {
+            expect(failure.status).toStrictEqual(0);
+            expect(failure.retryable).toStrictEqual(true);
+        }
+    });
+});
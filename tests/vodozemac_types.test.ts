@@ -1,4 +1,10 @@
-const { Curve25519PublicKey } = require("@matrix-org/matrix-sdk-crypto-wasm");
+const {
+    Curve25519PublicKey,
+    Ed25519PublicKey,
+    Ed25519SecretKey,
+    Ed25519Signature,
+    SignatureVerificationError,
+} = require("@matrix-org/matrix-sdk-crypto-wasm");
 
 describe(Curve25519PublicKey.name, () => {
     test("Can create a Curve25519PublicKey from a base64 string", async () => {
@@ -10,3 +16,53 @@ describe(Curve25519PublicKey.name, () => {
         expect(serialized).toStrictEqual(key);
     });
 });
+
+describe(Ed25519SecretKey.name, () => {
+    test("can sign a message and verify the signature", () => {
+        const secretKey = new Ed25519SecretKey();
+        const publicKey = secretKey.publicKey();
+
+        const signature = secretKey.sign("It's a secret to everybody");
+
+        expect(() => publicKey.verify("It's a secret to everybody", signature)).not.toThrow();
+    });
+
+    test("can be restored from base64 and from bytes", () => {
+        const secretKey = new Ed25519SecretKey();
+
+        const fromBase64 = Ed25519SecretKey.fromBase64(secretKey.toBase64());
+        expect(fromBase64.publicKey().toBase64()).toStrictEqual(secretKey.publicKey().toBase64());
+
+        const fromBytes = Ed25519SecretKey.fromUint8Array(secretKey.toUint8Array());
+        expect(fromBytes.publicKey().toBase64()).toStrictEqual(secretKey.publicKey().toBase64());
+    });
+
+    test("rejects keys of the wrong size", () => {
+        expect(() => Ed25519SecretKey.fromUint8Array(new Uint8Array(16))).toThrow();
+    });
+});
+
+describe(Ed25519PublicKey.name, () => {
+    test("can be created from a base64 string", () => {
+        const key = new Ed25519SecretKey().publicKey().toBase64();
+
+        expect(new Ed25519PublicKey(key).toBase64()).toStrictEqual(key);
+    });
+
+    test("throws a SignatureVerificationError on an invalid signature", () => {
+        const secretKey = new Ed25519SecretKey();
+        const publicKey = secretKey.publicKey();
+
+        const signature = new Ed25519Signature(secretKey.sign("a message").toBase64());
+
+        let error;
+        try {
+            publicKey.verify("another message", signature);
+        } catch (e) {
+            error = e;
+        }
+
+        expect(error).toBeInstanceOf(SignatureVerificationError);
+        expect(error.description).toContain("invalid");
+    });
+});